@@ -0,0 +1,163 @@
+use std::fmt;
+
+use crate::span::{Span, Spanned};
+use crate::{Code, Error};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Node {
+    /// Adds the value to the current cell
+    Add(i32),
+    /// Moves the tape pointer by the offset
+    Move(i32),
+    /// Sets the current cell to the value
+    Set(i32),
+    /// Adds the current cell multiplied by `factor` to the cell at `offset`
+    ///
+    /// The loop this replaces is never entered on a zero cell, so the target is only checked
+    /// against the ends of the tape if the current cell is nonzero.
+    MulAdd {
+        offset: i32,
+        factor: i32,
+    },
+    Output,
+    Input,
+    /// Moves the tape pointer by the step until the current cell is zero, like `[>]` or `[<<]`
+    Scan(i32),
+    /// Sets the current cell and the `len - 1` cells after it to zero, without moving the pointer
+    Zero(i32),
+    /// Repeats the body while the current cell is nonzero
+    Loop(Vec<Node>),
+}
+
+impl Node {
+    /// The node of a code other than a bracket, or `None` for `#`, which does nothing in the
+    /// compiled programs
+    pub fn leaf(code: &Code) -> Option<Node> {
+        let node = match code {
+            Code::MemInc => Node::Add(1),
+            Code::MemDec => Node::Add(-1),
+            Code::PtrInc => Node::Move(1),
+            Code::PtrDec => Node::Move(-1),
+            Code::SysWrite => Node::Output,
+            Code::SysRead => Node::Input,
+            Code::Debug | Code::LoopStart | Code::LoopEnd => return None,
+        };
+        Some(node)
+    }
+}
+
+/// Writes the node on one line, or a loop followed by its body indented on the next lines
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_node(self, f, 0)
+    }
+}
+
+fn write_node(node: &Node, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+    match node {
+        Node::Loop(body) => {
+            write!(f, "Loop")?;
+            for node in body {
+                write!(f, "\n{}", "  ".repeat(depth + 1))?;
+                write_node(node, f, depth + 1)?;
+            }
+            Ok(())
+        }
+        node => write!(f, "{:?}", node),
+    }
+}
+
+/// Builds the loop structure of the program, rejecting unmatched brackets.
+///
+/// The codes are consumed in a single pass. Unmatched brackets are reported at `Span::of_index`,
+/// see `build_ast_spanned` for the positions in a source.
+pub fn build_ast<I: IntoIterator<Item = Code>>(codes: I) -> Result<Vec<Node>, Error> {
+    build_ast_spanned(codes.into_iter().enumerate().map(|(index, inner)| Spanned {
+        inner,
+        span: Span::of_index(index),
+    }))
+}
+
+/// Builds the loop structure of the program like `build_ast`, reporting unmatched brackets at
+/// the positions of their codes.
+pub fn build_ast_spanned<I>(codes: I) -> Result<Vec<Node>, Error>
+where
+    I: IntoIterator<Item = Spanned<Code>>,
+{
+    let mut stack = vec![vec![]];
+    // the position of the `[` of each open loop
+    let mut opens = vec![];
+    for Spanned { inner: code, span } in codes {
+        let node = match code {
+            Code::LoopStart => {
+                stack.push(vec![]);
+                opens.push(span);
+                continue;
+            }
+            Code::LoopEnd => {
+                if opens.pop().is_none() {
+                    return Err(Error::UnmatchedClose { span });
+                }
+                let body = stack.pop().expect("a loop is open");
+                Node::Loop(body)
+            }
+            code => match Node::leaf(&code) {
+                Some(node) => node,
+                // there is no debugger in the compiled programs
+                None => continue,
+            },
+        };
+        stack
+            .last_mut()
+            .expect("the top level is never popped")
+            .push(node);
+    }
+
+    if let Some(&span) = opens.first() {
+        return Err(Error::UnmatchedOpen { span });
+    }
+    Ok(stack.pop().expect("the top level is never popped"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loop_becomes_a_node() {
+        let codes = [Code::LoopStart, Code::MemInc, Code::LoopEnd];
+        assert_eq!(
+            build_ast(codes).unwrap(),
+            vec![Node::Loop(vec![Node::Add(1)])]
+        );
+    }
+
+    #[test]
+    fn loop_bodies_are_indented() {
+        let nodes = [
+            Node::Add(3),
+            Node::Loop(vec![
+                Node::Add(-1),
+                Node::Move(1),
+                Node::Loop(vec![Node::Output]),
+                Node::Move(-1),
+            ]),
+        ];
+        let dump = nodes
+            .iter()
+            .map(|node| node.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(
+            dump,
+            "Add(3)\nLoop\n  Add(-1)\n  Move(1)\n  Loop\n    Output\n  Move(-1)"
+        );
+    }
+
+    #[test]
+    fn hash_is_ignored() {
+        let codes = [Code::MemInc, Code::Debug];
+        assert_eq!(build_ast(codes).unwrap(), vec![Node::Add(1)]);
+    }
+}
@@ -0,0 +1,293 @@
+use std::io::{self, Write};
+
+use super::{
+    reduce, scan_loop, start_address, write_align, zero_run, Syscalls, GENERIC_LINUX_SYSCALLS,
+    TAPE_ERRORS,
+};
+use crate::ast::Node;
+use crate::CompileOptions;
+
+const SYSCALLS: Syscalls = GENERIC_LINUX_SYSCALLS;
+
+/// Writes GNU assembler source for AArch64 Linux.
+///
+/// The tape pointer is kept in `x19`, which is preserved across syscalls and restored before
+/// returning from `main`.
+pub fn write<W: Write>(nodes: &[Node], out: &mut W, opts: &CompileOptions) -> io::Result<()> {
+    let cell = Cell::new(opts.cell_bits);
+
+    if !opts.tape_wrap {
+        writeln!(out, "  .section .rodata")?;
+        for &(label, message) in &TAPE_ERRORS {
+            writeln!(out, "{}_msg:", label)?;
+            writeln!(out, "  .ascii \"{}\\n\"", message)?;
+        }
+    }
+
+    if opts.init_cell == 0 {
+        writeln!(out, "  .bss")?;
+        write_align(out, opts, ".balign")?;
+        writeln!(out, "tape:")?;
+        writeln!(out, "  .skip {}", opts.tape_bytes())?;
+    } else {
+        writeln!(out, "  .data")?;
+        write_align(out, opts, ".balign")?;
+        writeln!(out, "tape:")?;
+        writeln!(
+            out,
+            "  .fill {}, {}, {}",
+            opts.tape_size,
+            opts.cell_bytes(),
+            opts.init_cell
+        )?;
+    }
+
+    writeln!(out, "  .text")?;
+    writeln!(out, "  .global {}", opts.entry)?;
+    writeln!(out, "{}:", opts.entry)?;
+    if opts.returns_from_main() {
+        // x19 is callee-saved
+        writeln!(out, "  str x19, [sp, #-16]!")?;
+    }
+    writeln!(out, "  ldr x19, ={}", start_address(opts))?;
+
+    write_nodes(nodes, out, opts, &cell, &mut 0)?;
+
+    writeln!(out, "  mov x0, #0")?;
+    if opts.returns_from_main() {
+        writeln!(out, "  ldr x19, [sp], #16")?;
+        writeln!(out, "  ret")?;
+    } else {
+        writeln!(out, "  mov x8, #{}", SYSCALLS.exit)?;
+        writeln!(out, "  svc #0")?;
+    }
+
+    if !opts.tape_wrap {
+        for &(label, message) in &TAPE_ERRORS {
+            writeln!(out, "{}:", label)?;
+            writeln!(out, "  ldr x1, ={}_msg", label)?;
+            writeln!(out, "  mov x2, #{}", message.len() + 1)?;
+            writeln!(out, "  b tape_error")?;
+        }
+        writeln!(out, "tape_error:")?;
+        writeln!(out, "  mov x0, #2")?;
+        writeln!(out, "  mov x8, #{}", SYSCALLS.write)?;
+        writeln!(out, "  svc #0")?;
+        writeln!(out, "  mov x0, #1")?;
+        writeln!(out, "  mov x8, #{}", SYSCALLS.exit)?;
+        writeln!(out, "  svc #0")?;
+    }
+
+    if opts.gnu_stack {
+        writeln!(out, "  .section .note.GNU-stack,\"\",%progbits")?;
+    }
+
+    Ok(())
+}
+
+/// Instruction and register names for the cell width
+struct Cell {
+    bits: u8,
+    load: &'static str,
+    store: &'static str,
+    /// The register prefix for values as wide as a cell
+    reg: char,
+}
+
+impl Cell {
+    fn new(bits: u8) -> Self {
+        let (load, store, reg) = match bits {
+            8 => ("ldrb", "strb", 'w'),
+            16 => ("ldrh", "strh", 'w'),
+            32 => ("ldr", "str", 'w'),
+            _ => ("ldr", "str", 'x'),
+        };
+        Self {
+            bits,
+            load,
+            store,
+            reg,
+        }
+    }
+
+    fn bytes(&self) -> i32 {
+        i32::from(self.bits / 8)
+    }
+
+    /// The scratch register `n` as wide as a cell
+    fn r(&self, n: u8) -> String {
+        format!("{}{}", self.reg, n)
+    }
+}
+
+fn write_nodes<W: Write>(
+    nodes: &[Node],
+    out: &mut W,
+    opts: &CompileOptions,
+    cell: &Cell,
+    next_label: &mut usize,
+) -> io::Result<()> {
+    for node in nodes {
+        match *node {
+            Node::Add(delta) => match reduce(delta, cell.bits) {
+                0 => {}
+                delta => {
+                    writeln!(out, "  {} {}, [x19]", cell.load, cell.r(0))?;
+                    write_add(out, &cell.r(0), &cell.r(0), delta.into())?;
+                    writeln!(out, "  {} {}, [x19]", cell.store, cell.r(0))?;
+                }
+            },
+            Node::Move(delta) => {
+                write_add(
+                    out,
+                    "x19",
+                    "x19",
+                    i64::from(delta) * i64::from(cell.bytes()),
+                )?;
+                write_bound(out, opts, "x19", delta)?;
+            }
+            Node::Set(value) => {
+                write_mov(out, &cell.r(0), reduce(value, cell.bits))?;
+                writeln!(out, "  {} {}, [x19]", cell.store, cell.r(0))?;
+            }
+            Node::MulAdd { offset, factor } => {
+                writeln!(out, "  {} {}, [x19]", cell.load, cell.r(0))?;
+                let skip = if opts.tape_wrap {
+                    None
+                } else {
+                    *next_label += 1;
+                    writeln!(out, "  cbz {}, .Lmul_{}", cell.r(0), next_label)?;
+                    Some(*next_label)
+                };
+                write_mov(out, &cell.r(1), factor)?;
+                writeln!(out, "  mul {0}, {0}, {1}", cell.r(0), cell.r(1))?;
+                write_add(
+                    out,
+                    "x2",
+                    "x19",
+                    i64::from(offset) * i64::from(cell.bytes()),
+                )?;
+                write_bound(out, opts, "x2", offset)?;
+                writeln!(out, "  {} {}, [x2]", cell.load, cell.r(1))?;
+                writeln!(out, "  add {0}, {0}, {1}", cell.r(1), cell.r(0))?;
+                writeln!(out, "  {} {}, [x2]", cell.store, cell.r(1))?;
+                if let Some(label) = skip {
+                    writeln!(out, ".Lmul_{}:", label)?;
+                }
+            }
+            Node::Output => write_syscall(out, 1, SYSCALLS.write)?,
+            Node::Input => {
+                write_syscall(out, 0, SYSCALLS.read)?;
+                // read(2) does not touch the buffer when it returns 0 (EOF) or a negative errno
+                let eof = opts.eof.value();
+                if cell.bits > 8 || eof.is_some() {
+                    *next_label += 1;
+                    let label = *next_label;
+                    writeln!(out, "  cmp x0, #1")?;
+                    writeln!(out, "  b.ne .Leof_{}", label)?;
+                    if cell.bits > 8 {
+                        // only the low byte was read, clear the rest of the cell
+                        writeln!(out, "  ldrb w0, [x19]")?;
+                        writeln!(out, "  {} {}, [x19]", cell.store, cell.r(0))?;
+                    }
+                    writeln!(out, "  b .Lread_{}", label)?;
+                    writeln!(out, ".Leof_{}:", label)?;
+                    if let Some(value) = eof {
+                        write_mov(out, &cell.r(0), value)?;
+                        writeln!(out, "  {} {}, [x19]", cell.store, cell.r(0))?;
+                    }
+                    writeln!(out, ".Lread_{}:", label)?;
+                }
+            }
+            Node::Scan(step) => write_nodes(&scan_loop(step), out, opts, cell, next_label)?,
+            Node::Zero(len) => write_nodes(&zero_run(len), out, opts, cell, next_label)?,
+            Node::Loop(ref body) => {
+                *next_label += 1;
+                let label = *next_label;
+                writeln!(out, "  {} {}, [x19]", cell.load, cell.r(0))?;
+                writeln!(out, "  cbz {}, .Lend_{}", cell.r(0), label)?;
+                writeln!(out, ".Lstart_{}:", label)?;
+                write_nodes(body, out, opts, cell, next_label)?;
+                writeln!(out, "  {} {}, [x19]", cell.load, cell.r(0))?;
+                writeln!(out, "  cbnz {}, .Lstart_{}", cell.r(0), label)?;
+                writeln!(out, ".Lend_{}:", label)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Loads a signed constant, which does not need to fit in an immediate operand
+fn write_mov<W: Write>(out: &mut W, dest: &str, value: i32) -> io::Result<()> {
+    if value.unsigned_abs() < 65536 {
+        writeln!(out, "  mov {}, #{}", dest, value)
+    } else {
+        writeln!(out, "  ldr {}, ={}", dest, value)
+    }
+}
+
+/// Adds a signed constant, which does not need to fit in an immediate operand
+fn write_add<W: Write>(out: &mut W, dest: &str, src: &str, value: i64) -> io::Result<()> {
+    let op = if value < 0 { "sub" } else { "add" };
+    let abs = value.unsigned_abs();
+    if abs < 4096 {
+        writeln!(out, "  {} {}, {}, #{}", op, dest, src, abs)
+    } else {
+        let scratch = if dest.starts_with('x') { "x9" } else { "w9" };
+        writeln!(out, "  ldr {}, ={}", scratch, abs)?;
+        writeln!(out, "  {} {}, {}, {}", op, dest, src, scratch)
+    }
+}
+
+/// Wraps the address in `reg` around the tape, or branches to the runtime error if it is off the tape
+///
+/// Without wrapping, only the end of the tape in the direction of `delta` is checked.
+/// This clobbers `x10` and `x11`.
+fn write_bound<W: Write>(
+    out: &mut W,
+    opts: &CompileOptions,
+    reg: &str,
+    delta: i32,
+) -> io::Result<()> {
+    if opts.tape_wrap {
+        writeln!(out, "  ldr x10, =tape")?;
+        writeln!(out, "  ldr x11, ={}", opts.tape_bytes() - 1)?;
+        writeln!(out, "  sub {0}, {0}, x10", reg)?;
+        writeln!(out, "  and {0}, {0}, x11", reg)?;
+        writeln!(out, "  add {0}, {0}, x10", reg)?;
+    } else if delta < 0 {
+        writeln!(out, "  ldr x10, =tape")?;
+        writeln!(out, "  cmp {}, x10", reg)?;
+        writeln!(out, "  b.lo tape_underflow")?;
+    } else {
+        writeln!(out, "  ldr x10, =tape+{}", opts.tape_bytes())?;
+        writeln!(out, "  cmp {}, x10", reg)?;
+        writeln!(out, "  b.hs tape_overflow")?;
+    }
+    Ok(())
+}
+
+/// Transfers one byte between the current cell and the file descriptor
+fn write_syscall<W: Write>(out: &mut W, fd: u32, number: u32) -> io::Result<()> {
+    writeln!(out, "  mov x0, #{}", fd)?;
+    writeln!(out, "  mov x1, x19")?;
+    writeln!(out, "  mov x2, #1")?;
+    writeln!(out, "  mov x8, #{}", number)?;
+    writeln!(out, "  svc #0")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_calls_write() {
+        let mut out = vec![];
+        write(&[Node::Output], &mut out, &CompileOptions::default()).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+        let lines = asm.lines().collect::<Vec<_>>();
+        let svc = lines.iter().position(|line| *line == "  svc #0").unwrap();
+        assert_eq!(lines[svc - 1], "  mov x8, #64");
+    }
+}
@@ -0,0 +1,146 @@
+use std::io::{self, Write};
+
+use super::{reduce, scan_loop, zero_run};
+use crate::ast::Node;
+use crate::{Code, CompileOptions};
+
+/// Writes the program back as Brainfuck on a single line, without comments.
+///
+/// Multiplication loops are written as the loops they were folded from.
+pub fn write<W: Write>(nodes: &[Node], out: &mut W, opts: &CompileOptions) -> io::Result<()> {
+    if opts.init_cell != 0 {
+        // the output is run on a zeroed tape
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The bf target cannot initialize the tape",
+        ));
+    }
+    // the program is written at once, so that an error leaves no partial output
+    let mut buf = vec![];
+    write_nodes(nodes, &mut buf, opts)?;
+    writeln!(buf)?;
+    out.write_all(&buf)
+}
+
+fn write_nodes<W: Write>(nodes: &[Node], out: &mut W, opts: &CompileOptions) -> io::Result<()> {
+    let mut nodes = nodes.iter().peekable();
+    while let Some(node) = nodes.next() {
+        match *node {
+            Node::Add(delta) => write_add(out, reduce(delta, opts.cell_bits))?,
+            Node::Move(delta) => write_move(out, delta)?,
+            Node::Set(value) => {
+                write!(out, "{}{}{}", Code::LoopStart, Code::MemDec, Code::LoopEnd)?;
+                write_add(out, reduce(value, opts.cell_bits))?;
+            }
+            Node::MulAdd { .. } => {
+                // `opt::mul_loops` emits the targets of a loop followed by the `Set(0)` ending it,
+                // which `opt::fold_sets` or `opt::zero_runs` may have merged with what follows
+                write!(out, "{}{}", Code::LoopStart, Code::MemDec)?;
+                let (mut next, mut current) = (Some(node), 0);
+                while let Some(&Node::MulAdd { offset, factor }) = next {
+                    write_move(out, offset - current)?;
+                    write_add(out, factor)?;
+                    current = offset;
+                    next = nodes.next();
+                }
+                write_move(out, -current)?;
+                write!(out, "{}", Code::LoopEnd)?;
+                // the loop leaves its source cell zero, so the clear itself is not written
+                match next {
+                    Some(&Node::Set(value)) => write_add(out, reduce(value, opts.cell_bits))?,
+                    Some(&Node::Zero(len)) => write_nodes(&zero_run(len)[1..], out, opts)?,
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "A multiplication is not followed by clearing its source cell",
+                        ))
+                    }
+                }
+            }
+            Node::Output => write!(out, "{}", Code::SysWrite)?,
+            Node::Input => write!(out, "{}", Code::SysRead)?,
+            Node::Scan(step) => write_nodes(&scan_loop(step), out, opts)?,
+            Node::Zero(len) => write_nodes(&zero_run(len), out, opts)?,
+            Node::Loop(ref body) => {
+                write!(out, "{}", Code::LoopStart)?;
+                write_nodes(body, out, opts)?;
+                write!(out, "{}", Code::LoopEnd)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_add<W: Write>(out: &mut W, delta: i32) -> io::Result<()> {
+    let code = if delta < 0 {
+        Code::MemDec
+    } else {
+        Code::MemInc
+    };
+    write_repeated(out, &code, delta)
+}
+
+fn write_move<W: Write>(out: &mut W, delta: i32) -> io::Result<()> {
+    let code = if delta < 0 {
+        Code::PtrDec
+    } else {
+        Code::PtrInc
+    };
+    write_repeated(out, &code, delta)
+}
+
+fn write_repeated<W: Write>(out: &mut W, code: &Code, count: i32) -> io::Result<()> {
+    for _ in 0..count.unsigned_abs() {
+        write!(out, "{}", code)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    fn emit(src: &str, opt_level: u8) -> String {
+        let codes = src.bytes().filter_map(|byte| Code::try_from(byte).ok());
+        let nodes = crate::opt::optimize(crate::ast::build_ast(codes).unwrap(), opt_level);
+        let mut out = vec![];
+        write(&nodes, &mut out, &CompileOptions::default()).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn comments_and_whitespace_are_stripped() {
+        assert_eq!(emit("++  +", 0), "+++\n");
+        assert_eq!(emit("+ add one\n[-] clear", 1), "+[-]\n");
+    }
+
+    #[test]
+    fn folded_loops_are_written_back() {
+        assert_eq!(emit(",+++[-]", 2), ",+++[-]\n");
+        assert_eq!(emit(",[->++>+<<]", 2), ",[->++>+<<]\n");
+    }
+
+    #[test]
+    fn folded_clears_after_a_multiplication_are_written_back() {
+        assert_eq!(emit(",[->+<]+++.", 2), ",[->+<]+++.\n");
+        assert_eq!(
+            emit(",[->+<]>[-]>[-]>[-].", 2),
+            ",[->+<]>[-]>[-]>[-]<<<>>>.\n"
+        );
+    }
+
+    #[test]
+    fn errors_leave_no_output() {
+        let mut out = vec![];
+        let nodes = [
+            Node::Output,
+            Node::MulAdd {
+                offset: 1,
+                factor: 1,
+            },
+        ];
+        assert!(write(&nodes, &mut out, &CompileOptions::default()).is_err());
+        assert!(out.is_empty());
+    }
+}
@@ -0,0 +1,216 @@
+use std::io::{self, Write};
+
+use super::{reduce, scan_loop, zero_run};
+use crate::ast::Node;
+use crate::CompileOptions;
+
+/// Writes a self-contained C program, or a function running on the tape of its caller if
+/// `opts.function` is set.
+pub fn write<W: Write>(nodes: &[Node], out: &mut W, opts: &CompileOptions) -> io::Result<()> {
+    writeln!(out, "#include <stdint.h>")?;
+    writeln!(out, "#include <stdio.h>")?;
+    if !opts.tape_wrap {
+        writeln!(out, "#include <stdlib.h>")?;
+    }
+    writeln!(out)?;
+    writeln!(out, "typedef uint{}_t cell;", opts.cell_bits)?;
+    writeln!(out)?;
+    if opts.function.is_none() {
+        match opts.tape_align {
+            Some(align) => writeln!(
+                out,
+                "static _Alignas({}) cell tape[{}];",
+                align, opts.tape_size
+            )?,
+            None => writeln!(out, "static cell tape[{}];", opts.tape_size)?,
+        }
+        writeln!(out)?;
+    }
+    if !opts.tape_wrap {
+        writeln!(out, "static void tape_error(const char *end) {{")?;
+        writeln!(
+            out,
+            "    fprintf(stderr, \"Runtime error: the pointer moved past the %s of the tape\\n\", end);"
+        )?;
+        writeln!(out, "    exit(1);")?;
+        writeln!(out, "}}")?;
+        writeln!(out)?;
+    }
+    match &opts.function {
+        Some(name) => writeln!(out, "void {}(uint{}_t *tape) {{", name, opts.cell_bits)?,
+        None => writeln!(out, "int main(void) {{")?,
+    }
+    if opts.init_cell != 0 {
+        writeln!(
+            out,
+            "    for (size_t i = 0; i < {}; i++) tape[i] = {};",
+            opts.tape_size, opts.init_cell
+        )?;
+    }
+    writeln!(out, "    cell *p = tape + {};", opts.start_cell())?;
+
+    write_nodes(nodes, out, opts, 1)?;
+
+    if opts.function.is_none() {
+        writeln!(out, "    return 0;")?;
+    }
+    writeln!(out, "}}")?;
+
+    Ok(())
+}
+
+fn write_nodes<W: Write>(
+    nodes: &[Node],
+    out: &mut W,
+    opts: &CompileOptions,
+    depth: usize,
+) -> io::Result<()> {
+    let indent = "    ".repeat(depth);
+    let cell_bits = opts.cell_bits;
+    for node in nodes {
+        match *node {
+            Node::Add(delta) => match reduce(delta, cell_bits) {
+                0 => {}
+                1 => writeln!(out, "{}++*p;", indent)?,
+                -1 => writeln!(out, "{}--*p;", indent)?,
+                delta if delta < 0 => writeln!(out, "{}*p -= {};", indent, -i64::from(delta))?,
+                delta => writeln!(out, "{}*p += {};", indent, delta)?,
+            },
+            Node::Move(delta) if opts.tape_wrap => writeln!(
+                out,
+                "{}p = tape + ((p - tape + {}) & {});",
+                indent,
+                delta,
+                opts.tape_size - 1
+            )?,
+            Node::Move(delta) => {
+                write_bound(out, opts, &indent, delta)?;
+                match delta {
+                    1 => writeln!(out, "{}++p;", indent)?,
+                    -1 => writeln!(out, "{}--p;", indent)?,
+                    delta if delta < 0 => {
+                        writeln!(out, "{}p -= {};", indent, delta.unsigned_abs())?
+                    }
+                    delta => writeln!(out, "{}p += {};", indent, delta)?,
+                }
+            }
+            Node::Set(value) => writeln!(out, "{}*p = {};", indent, reduce(value, cell_bits))?,
+            Node::MulAdd { offset, factor } if opts.tape_wrap => writeln!(
+                out,
+                "{}tape[(p - tape + {}) & {}] += *p * {};",
+                indent,
+                offset,
+                opts.tape_size - 1,
+                factor
+            )?,
+            Node::MulAdd { offset, factor } => {
+                writeln!(out, "{}if (*p) {{", indent)?;
+                let inner = format!("{}    ", indent);
+                write_bound(out, opts, &inner, offset)?;
+                writeln!(out, "{}p[{}] += *p * {};", inner, offset, factor)?;
+                writeln!(out, "{}}}", indent)?;
+            }
+            Node::Output => writeln!(out, "{}putchar(*p);", indent)?,
+            Node::Input => match opts.eof.value() {
+                None => writeln!(
+                    out,
+                    "{}{{ int c = getchar(); if (c != EOF) *p = c; }}",
+                    indent
+                )?,
+                Some(value) => writeln!(
+                    out,
+                    "{}{{ int c = getchar(); *p = c != EOF ? c : {}; }}",
+                    indent, value
+                )?,
+            },
+            Node::Scan(step) => write_nodes(&scan_loop(step), out, opts, depth)?,
+            Node::Zero(len) => write_nodes(&zero_run(len), out, opts, depth)?,
+            Node::Loop(ref body) => {
+                writeln!(out, "{}while (*p) {{", indent)?;
+                write_nodes(body, out, opts, depth + 1)?;
+                writeln!(out, "{}}}", indent)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Exits with a runtime error if the cell at `delta` from the current cell is off the tape
+///
+/// The check is done on the index rather than the pointer,
+/// because even computing a pointer off the array is undefined behavior.
+fn write_bound<W: Write>(
+    out: &mut W,
+    opts: &CompileOptions,
+    indent: &str,
+    delta: i32,
+) -> io::Result<()> {
+    if delta < 0 {
+        writeln!(
+            out,
+            "{}if (p - tape < {}) tape_error(\"start\");",
+            indent,
+            -i64::from(delta)
+        )
+    } else {
+        writeln!(
+            out,
+            "{}if (p - tape >= {}) tape_error(\"end\");",
+            indent,
+            opts.tape_size as i64 - i64::from(delta)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_uses_putchar() {
+        let mut out = vec![];
+        write(
+            &[Node::Add(1), Node::Output],
+            &mut out,
+            &CompileOptions::default(),
+        )
+        .unwrap();
+        let c = String::from_utf8(out).unwrap();
+        assert!(c.contains("++*p;"));
+        assert!(c.contains("putchar(*p);"));
+    }
+
+    #[test]
+    fn moves_are_checked_or_wrapped() {
+        let mut out = vec![];
+        write(&[Node::Move(-1)], &mut out, &CompileOptions::default()).unwrap();
+        let c = String::from_utf8(out).unwrap();
+        assert!(c.contains("if (p - tape < 1) tape_error(\"start\");"));
+
+        let mut out = vec![];
+        let opts = CompileOptions {
+            tape_size: 16,
+            tape_wrap: true,
+            ..CompileOptions::default()
+        };
+        write(&[Node::Move(-1)], &mut out, &opts).unwrap();
+        let c = String::from_utf8(out).unwrap();
+        assert!(c.contains("p = tape + ((p - tape + -1) & 15);"));
+        assert!(!c.contains("tape_error"));
+    }
+
+    #[test]
+    fn function_takes_the_tape() {
+        let mut out = vec![];
+        let opts = CompileOptions {
+            target: crate::Target::C,
+            function: Some("bf_run".to_string()),
+            ..CompileOptions::default()
+        };
+        write(&[Node::Add(1), Node::Output], &mut out, &opts).unwrap();
+        let c = String::from_utf8(out).unwrap();
+        assert!(c.contains("void bf_run(uint8_t *tape) {"));
+        assert!(!c.contains("int main"));
+        assert!(!c.contains("static cell tape["));
+    }
+}
@@ -0,0 +1,201 @@
+use std::io::{self, Write};
+
+use super::machine::{self, Runtime};
+use crate::ast::Node;
+use crate::CompileOptions;
+
+/// The names of the sections after the null section, in the order of their headers
+const SECTIONS: [&str; 4] = [".text", ".symtab", ".strtab", ".shstrtab"];
+
+/// The empty section that marks the stack as not executable, placed before `.shstrtab`
+const GNU_STACK: &str = ".note.GNU-stack";
+
+const ELF_HEADER_SIZE: u64 = 64;
+const SECTION_HEADER_SIZE: u64 = 64;
+const SYMBOL_SIZE: u64 = 24;
+
+/// Writes an ELF relocatable object for x86-64 Linux, which only needs to be linked.
+///
+/// The machine code is position-independent, maps its tape with `mmap` and needs no relocations.
+/// The entry symbol is the only symbol. The stack is marked as not executable unless
+/// `opts.gnu_stack` is false.
+pub fn write<W: Write>(nodes: &[Node], out: &mut W, opts: &CompileOptions) -> io::Result<()> {
+    let text = machine::assemble(nodes, opts, Runtime::Linux);
+
+    let mut strtab = vec![0];
+    strtab.extend_from_slice(opts.entry.as_bytes());
+    strtab.push(0);
+
+    let mut sections = SECTIONS.to_vec();
+    if opts.gnu_stack {
+        sections.insert(SECTIONS.len() - 1, GNU_STACK);
+    }
+    let mut shstrtab = vec![0];
+    let mut names = vec![];
+    for name in &sections {
+        names.push(shstrtab.len() as u32);
+        shstrtab.extend_from_slice(name.as_bytes());
+        shstrtab.push(0);
+    }
+
+    let mut symtab = vec![0; SYMBOL_SIZE as usize];
+    put32(&mut symtab, 1); // the name in .strtab
+    symtab.push(0x12); // STB_GLOBAL, STT_FUNC
+    symtab.push(0);
+    put16(&mut symtab, 1); // .text
+    put64(&mut symtab, 0);
+    put64(&mut symtab, text.len() as u64);
+
+    // the contents are laid out in the order of the sections after the ELF header
+    let text_offset = ELF_HEADER_SIZE;
+    let symtab_offset = align(text_offset + text.len() as u64, 8);
+    let strtab_offset = symtab_offset + symtab.len() as u64;
+    let shstrtab_offset = strtab_offset + strtab.len() as u64;
+    let headers_offset = align(shstrtab_offset + shstrtab.len() as u64, 8);
+
+    let mut file = vec![];
+    file.extend_from_slice(b"\x7fELF");
+    // 64-bit, little-endian, version 1, System V ABI
+    file.extend_from_slice(&[2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    put16(&mut file, 1); // ET_REL
+    put16(&mut file, 62); // EM_X86_64
+    put32(&mut file, 1);
+    put64(&mut file, 0); // no entry point
+    put64(&mut file, 0); // no program headers
+    put64(&mut file, headers_offset);
+    put32(&mut file, 0);
+    put16(&mut file, ELF_HEADER_SIZE as u16);
+    put16(&mut file, 0);
+    put16(&mut file, 0);
+    put16(&mut file, SECTION_HEADER_SIZE as u16);
+    put16(&mut file, sections.len() as u16 + 1);
+    put16(&mut file, sections.len() as u16); // .shstrtab
+
+    file.extend_from_slice(&text);
+    file.resize(symtab_offset as usize, 0);
+    file.extend_from_slice(&symtab);
+    file.extend_from_slice(&strtab);
+    file.extend_from_slice(&shstrtab);
+    file.resize(headers_offset as usize, 0);
+
+    file.resize(file.len() + SECTION_HEADER_SIZE as usize, 0);
+    let mut headers = vec![
+        // SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR
+        (1, 6, text_offset, text.len(), 0, 0, 16, 0),
+        // SHT_SYMTAB linked to .strtab, whose first global symbol is 1
+        (2, 0, symtab_offset, symtab.len(), 3, 1, 8, SYMBOL_SIZE),
+        // SHT_STRTAB
+        (3, 0, strtab_offset, strtab.len(), 0, 0, 1, 0),
+        (3, 0, shstrtab_offset, shstrtab.len(), 0, 0, 1, 0),
+    ];
+    if opts.gnu_stack {
+        // SHT_PROGBITS without SHF_EXECINSTR
+        headers.insert(SECTIONS.len() - 1, (1, 0, shstrtab_offset, 0, 0, 0, 1, 0));
+    }
+    for (&name, &(kind, flags, offset, size, link, info, align, entsize)) in
+        names.iter().zip(&headers)
+    {
+        put32(&mut file, name);
+        put32(&mut file, kind);
+        put64(&mut file, flags);
+        put64(&mut file, 0);
+        put64(&mut file, offset);
+        put64(&mut file, size as u64);
+        put32(&mut file, link);
+        put32(&mut file, info);
+        put64(&mut file, align);
+        put64(&mut file, entsize);
+    }
+
+    out.write_all(&file)
+}
+
+fn align(offset: u64, align: u64) -> u64 {
+    offset.div_ceil(align) * align
+}
+
+fn put16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put64(buf: &mut Vec<u8>, value: u64) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    /// The name, type, offset and size of each section header
+    fn sections(file: &[u8]) -> Vec<(String, u32, usize, usize)> {
+        let get16 = |at: usize| u16::from_le_bytes([file[at], file[at + 1]]) as usize;
+        let get32 = |at: usize| u32::from_le_bytes(file[at..at + 4].try_into().unwrap());
+        let get64 = |at: usize| u64::from_le_bytes(file[at..at + 8].try_into().unwrap()) as usize;
+        let (headers, count, names) = (get64(0x28), get16(0x3c), get16(0x3e));
+        let header = |index: usize| headers + index * SECTION_HEADER_SIZE as usize;
+        let names = get64(header(names) + 24);
+        (0..count)
+            .map(|index| {
+                let at = header(index);
+                let name = &file[names + get32(at) as usize..];
+                let name = &name[..name.iter().position(|&byte| byte == 0).unwrap()];
+                let name = String::from_utf8(name.to_vec()).unwrap();
+                (name, get32(at + 4), get64(at + 24), get64(at + 32))
+            })
+            .collect()
+    }
+
+    fn object(opts: &CompileOptions) -> Vec<u8> {
+        let mut out = vec![];
+        write(&[Node::Add(1), Node::Output], &mut out, opts).unwrap();
+        out
+    }
+
+    #[test]
+    fn object_has_text_and_entry_symbol() {
+        let file = object(&CompileOptions::default());
+        assert_eq!(&file[..4], b"\x7fELF");
+        let sections = sections(&file);
+        let names = sections.iter().map(|s| &s.0[..]).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "",
+                ".text",
+                ".symtab",
+                ".strtab",
+                ".note.GNU-stack",
+                ".shstrtab"
+            ]
+        );
+        assert!(sections[1].3 > 0);
+
+        // the first symbol after the null symbol names the entry
+        let (symtab, strtab) = (sections[2].2, sections[3].2);
+        let name = u32::from_le_bytes(file[symtab + 24..symtab + 28].try_into().unwrap());
+        let name = &file[strtab + name as usize..];
+        assert!(name.starts_with(b"_start\0"));
+    }
+
+    #[test]
+    fn gnu_stack_note_is_empty_or_left_out() {
+        let file = object(&CompileOptions::default());
+        let note = sections(&file)
+            .into_iter()
+            .find(|section| section.0 == ".note.GNU-stack")
+            .unwrap();
+        assert_eq!((note.1, note.3), (1, 0));
+
+        let file = object(&CompileOptions {
+            gnu_stack: false,
+            ..CompileOptions::default()
+        });
+        let names = sections(&file).into_iter().map(|s| s.0).collect::<Vec<_>>();
+        assert_eq!(names, ["", ".text", ".symtab", ".strtab", ".shstrtab"]);
+    }
+}
@@ -0,0 +1,289 @@
+use std::io::{self, Write};
+
+use super::{
+    byte_offset, reduce, scan_loop, start_address, write_align, zero_run, Syscalls,
+    I386_LINUX_SYSCALLS, TAPE_ERRORS,
+};
+use crate::ast::Node;
+use crate::CompileOptions;
+
+const SYSCALLS: Syscalls = I386_LINUX_SYSCALLS;
+
+/// Writes NASM assembly for 32-bit x86 Linux, using `int 0x80` with the arguments in `ebx`,
+/// `ecx` and `edx`.
+///
+/// The tape pointer is kept in `ecx`, which is the buffer argument of `read` and `write` and is
+/// preserved by the kernel. Cells are at most 32 bits wide.
+pub fn write<W: Write>(nodes: &[Node], out: &mut W, opts: &CompileOptions) -> io::Result<()> {
+    let cell = Cell::new(opts.cell_bits);
+
+    if !opts.tape_wrap {
+        writeln!(out, "section .rodata")?;
+        for &(label, message) in &TAPE_ERRORS {
+            writeln!(out, "  {}_msg db \"{}\", 10", label, message)?;
+            writeln!(out, "  {}_len equ $ - {}_msg", label, label)?;
+        }
+    }
+
+    if opts.init_cell == 0 {
+        writeln!(out, "section .bss")?;
+        write_align(out, opts, "alignb")?;
+        writeln!(out, "  tape RESB {}", opts.tape_bytes())?;
+    } else {
+        writeln!(out, "section .data")?;
+        write_align(out, opts, "align")?;
+        writeln!(
+            out,
+            "  tape TIMES {} {} {}",
+            opts.tape_size, cell.data, opts.init_cell
+        )?;
+    }
+
+    writeln!(out, "section .text")?;
+    writeln!(out, "  global {}", opts.entry)?;
+    writeln!(out, "{}:", opts.entry)?;
+    if opts.returns_from_main() {
+        // ebx is callee-saved
+        writeln!(out, "  push ebx")?;
+    }
+    writeln!(out, "  mov ecx, {}", start_address(opts))?;
+
+    write_nodes(nodes, out, opts, &cell, &mut 0)?;
+
+    if opts.returns_from_main() {
+        writeln!(out, "  pop ebx")?;
+        writeln!(out, "  xor eax, eax")?;
+        writeln!(out, "  ret")?;
+    } else {
+        writeln!(out, "  mov eax, {}", SYSCALLS.exit)?;
+        writeln!(out, "  xor ebx, ebx")?;
+        writeln!(out, "  int 0x80")?;
+    }
+
+    if !opts.tape_wrap {
+        for &(label, _) in &TAPE_ERRORS {
+            writeln!(out, "{}:", label)?;
+            writeln!(out, "  mov ecx, {}_msg", label)?;
+            writeln!(out, "  mov edx, {}_len", label)?;
+            writeln!(out, "  jmp tape_error")?;
+        }
+        writeln!(out, "tape_error:")?;
+        writeln!(out, "  mov eax, {}", SYSCALLS.write)?;
+        writeln!(out, "  mov ebx, 2")?;
+        writeln!(out, "  int 0x80")?;
+        writeln!(out, "  mov eax, {}", SYSCALLS.exit)?;
+        writeln!(out, "  mov ebx, 1")?;
+        writeln!(out, "  int 0x80")?;
+    }
+
+    if opts.gnu_stack {
+        writeln!(
+            out,
+            "section .note.GNU-stack noalloc noexec nowrite progbits"
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Operand names for the cell width
+struct Cell {
+    bits: u8,
+    /// The NASM size specifier
+    size: &'static str,
+    /// The part of `eax` as wide as a cell
+    eax: &'static str,
+    /// The NASM pseudo-instruction declaring an initialized cell
+    data: &'static str,
+}
+
+impl Cell {
+    fn new(bits: u8) -> Self {
+        let (size, eax, data) = match bits {
+            8 => ("byte", "al", "DB"),
+            16 => ("word", "ax", "DW"),
+            _ => ("dword", "eax", "DD"),
+        };
+        Self {
+            bits,
+            size,
+            eax,
+            data,
+        }
+    }
+
+    fn bytes(&self) -> i32 {
+        i32::from(self.bits / 8)
+    }
+}
+
+fn write_nodes<W: Write>(
+    nodes: &[Node],
+    out: &mut W,
+    opts: &CompileOptions,
+    cell: &Cell,
+    next_label: &mut usize,
+) -> io::Result<()> {
+    for node in nodes {
+        match *node {
+            Node::Add(delta) => match reduce(delta, cell.bits) {
+                0 => {}
+                1 => writeln!(out, "  inc {} [ecx]", cell.size)?,
+                -1 => writeln!(out, "  dec {} [ecx]", cell.size)?,
+                // i32::MIN cannot be negated, and adding it is the same for 32-bit cells
+                delta if delta < 0 && delta != i32::MIN => {
+                    writeln!(out, "  sub {} [ecx], {}", cell.size, -delta)?
+                }
+                delta => writeln!(out, "  add {} [ecx], {}", cell.size, delta)?,
+            },
+            Node::Move(delta) => {
+                match byte_offset(delta, cell.bytes(), opts)? {
+                    1 => writeln!(out, "  inc ecx")?,
+                    -1 => writeln!(out, "  dec ecx")?,
+                    bytes if bytes < 0 && bytes != i32::MIN => {
+                        writeln!(out, "  sub ecx, {}", -bytes)?
+                    }
+                    bytes => writeln!(out, "  add ecx, {}", bytes)?,
+                }
+                write_bound(out, opts, "ecx", delta)?;
+            }
+            Node::Set(value) => writeln!(
+                out,
+                "  mov {} [ecx], {}",
+                cell.size,
+                reduce(value, cell.bits)
+            )?,
+            Node::MulAdd { offset, factor } => {
+                if cell.bits == 32 {
+                    writeln!(out, "  mov eax, [ecx]")?;
+                } else {
+                    writeln!(out, "  movzx eax, {} [ecx]", cell.size)?;
+                }
+                let skip = if opts.tape_wrap {
+                    None
+                } else {
+                    *next_label += 1;
+                    writeln!(out, "  test eax, eax")?;
+                    writeln!(out, "  jz .mul_{}", next_label)?;
+                    Some(*next_label)
+                };
+                if factor != 1 {
+                    writeln!(out, "  imul eax, eax, {}", factor)?;
+                }
+                let bytes = byte_offset(offset, cell.bytes(), opts)?;
+                writeln!(out, "  lea edx, [ecx{:+}]", bytes)?;
+                write_bound(out, opts, "edx", offset)?;
+                writeln!(out, "  add {} [edx], {}", cell.size, cell.eax)?;
+                if let Some(label) = skip {
+                    writeln!(out, ".mul_{}:", label)?;
+                }
+            }
+            Node::Output => write_syscall(out, 1, SYSCALLS.write)?,
+            Node::Input => {
+                write_syscall(out, 0, SYSCALLS.read)?;
+                // read(2) does not touch the buffer when it returns 0 (EOF) or a negative errno
+                let eof = opts.eof.value();
+                if cell.bits > 8 || eof.is_some() {
+                    *next_label += 1;
+                    let label = *next_label;
+                    writeln!(out, "  cmp eax, 1")?;
+                    writeln!(out, "  jne .eof_{}", label)?;
+                    if cell.bits > 8 {
+                        // only the low byte was read, clear the rest of the cell
+                        writeln!(out, "  movzx eax, byte [ecx]")?;
+                        writeln!(out, "  mov [ecx], {}", cell.eax)?;
+                    }
+                    writeln!(out, "  jmp .read_{}", label)?;
+                    writeln!(out, ".eof_{}:", label)?;
+                    if let Some(value) = eof {
+                        writeln!(out, "  mov {} [ecx], {}", cell.size, value)?;
+                    }
+                    writeln!(out, ".read_{}:", label)?;
+                }
+            }
+            Node::Scan(step) => write_nodes(&scan_loop(step), out, opts, cell, next_label)?,
+            Node::Zero(len) => write_nodes(&zero_run(len), out, opts, cell, next_label)?,
+            Node::Loop(ref body) => {
+                *next_label += 1;
+                let label = *next_label;
+                writeln!(out, "  cmp {} [ecx], 0", cell.size)?;
+                writeln!(out, "  je .end_{}", label)?;
+                writeln!(out, ".start_{}:", label)?;
+                write_nodes(body, out, opts, cell, next_label)?;
+                writeln!(out, "  cmp {} [ecx], 0", cell.size)?;
+                writeln!(out, "  jne .start_{}", label)?;
+                writeln!(out, ".end_{}:", label)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Wraps the address in `reg` around the tape, or jumps to the runtime error if it is off the tape
+///
+/// Without wrapping, only the end of the tape in the direction of `delta` is checked.
+fn write_bound<W: Write>(
+    out: &mut W,
+    opts: &CompileOptions,
+    reg: &str,
+    delta: i32,
+) -> io::Result<()> {
+    if opts.tape_wrap {
+        writeln!(out, "  sub {}, tape", reg)?;
+        writeln!(out, "  and {}, {}", reg, opts.tape_bytes() - 1)?;
+        writeln!(out, "  add {}, tape", reg)?;
+    } else if delta < 0 {
+        writeln!(out, "  cmp {}, tape", reg)?;
+        writeln!(out, "  jb tape_underflow")?;
+    } else {
+        writeln!(out, "  cmp {}, tape+{}", reg, opts.tape_bytes())?;
+        writeln!(out, "  jae tape_overflow")?;
+    }
+    Ok(())
+}
+
+/// Transfers one byte between the current cell and the file descriptor
+///
+/// The return value of the syscall is left in `eax`.
+fn write_syscall<W: Write>(out: &mut W, fd: u32, number: u32) -> io::Result<()> {
+    writeln!(out, "  mov eax, {}", number)?;
+    writeln!(out, "  mov ebx, {}", fd)?;
+    writeln!(out, "  mov edx, 1")?;
+    writeln!(out, "  int 0x80")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_uses_int_0x80_with_ebx() {
+        let mut out = vec![];
+        let opts = CompileOptions {
+            target: crate::Target::I386Linux,
+            ..CompileOptions::default()
+        };
+        write(&[Node::Add(1), Node::Output], &mut out, &opts).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+        assert!(asm.contains("  mov eax, 4\n  mov ebx, 1\n  mov edx, 1\n  int 0x80\n"));
+        assert!(!asm.contains("syscall"));
+        assert!(!asm.contains("rax"));
+    }
+
+    #[test]
+    fn moves_past_32_bit_offsets_are_rejected_unless_the_tape_wraps() {
+        let nodes = [Node::Move(1 << 30)];
+        let mut opts = CompileOptions {
+            target: crate::Target::I386Linux,
+            cell_bits: 32,
+            ..CompileOptions::default()
+        };
+        let err = write(&nodes, &mut vec![], &opts).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        opts.tape_size = 16;
+        opts.tape_wrap = true;
+        write(&nodes, &mut vec![], &opts).unwrap();
+    }
+}
@@ -0,0 +1,370 @@
+use std::io::{self, Write};
+
+use super::{reduce, scan_loop, zero_run, TAPE_ERRORS};
+use crate::ast::Node;
+use crate::CompileOptions;
+
+/// Writes textual LLVM IR defining `main`, or a function taking a pointer to the tape if
+/// `opts.function` is set, with opaque pointers as in LLVM 15 and later.
+///
+/// The pointer is kept as an index into the tape in an `alloca`, so that LLVM can promote it to
+/// a register. The bytes are transferred with `read` and `write` through a one-byte buffer,
+/// which keeps the output independent of the byte order of the target.
+pub fn write<W: Write>(nodes: &[Node], out: &mut W, opts: &CompileOptions) -> io::Result<()> {
+    let cell = format!("i{}", opts.cell_bits);
+
+    if opts.function.is_none() {
+        write!(
+            out,
+            "@tape = internal global [{} x {}] zeroinitializer",
+            opts.tape_size, cell
+        )?;
+        match opts.tape_align {
+            Some(align) => writeln!(out, ", align {}", align)?,
+            None => writeln!(out)?,
+        }
+    }
+    if !opts.tape_wrap {
+        for &(label, message) in &TAPE_ERRORS {
+            writeln!(
+                out,
+                "@{}_msg = private constant [{} x i8] c\"{}\\0A\"",
+                label,
+                message.len() + 1,
+                message
+            )?;
+        }
+    }
+    writeln!(out)?;
+    writeln!(out, "declare i64 @read(i32, ptr, i64)")?;
+    writeln!(out, "declare i64 @write(i32, ptr, i64)")?;
+    if !opts.tape_wrap {
+        writeln!(out, "declare void @exit(i32) noreturn")?;
+        writeln!(out)?;
+        writeln!(
+            out,
+            "define internal void @tape_error(ptr %msg, i64 %len) noreturn {{"
+        )?;
+        writeln!(out, "  call i64 @write(i32 2, ptr %msg, i64 %len)")?;
+        writeln!(out, "  call void @exit(i32 1)")?;
+        writeln!(out, "  unreachable")?;
+        writeln!(out, "}}")?;
+    }
+    writeln!(out)?;
+    match &opts.function {
+        Some(name) => writeln!(out, "define void @{}(ptr %tape) {{", name)?,
+        None => writeln!(out, "define i32 @main() {{")?,
+    }
+    writeln!(out, "entry:")?;
+    writeln!(out, "  %p = alloca i64")?;
+    writeln!(out, "  %byte = alloca i8")?;
+    writeln!(out, "  store i64 {}, ptr %p", opts.start_cell())?;
+    if opts.init_cell != 0 {
+        writeln!(out, "  br label %init")?;
+        writeln!(out, "init:")?;
+        writeln!(out, "  %i = phi i64 [0, %entry], [%i.next, %init]")?;
+        writeln!(
+            out,
+            "  %i.cell = getelementptr inbounds [{} x {}], ptr @tape, i64 0, i64 %i",
+            opts.tape_size, cell
+        )?;
+        writeln!(out, "  store {} {}, ptr %i.cell", cell, opts.init_cell)?;
+        writeln!(out, "  %i.next = add i64 %i, 1")?;
+        writeln!(out, "  %i.more = icmp ult i64 %i.next, {}", opts.tape_size)?;
+        writeln!(out, "  br i1 %i.more, label %init, label %start")?;
+        writeln!(out, "start:")?;
+    }
+
+    let mut writer = Writer {
+        out,
+        opts,
+        cell,
+        tape: if opts.function.is_some() {
+            "%tape"
+        } else {
+            "@tape"
+        },
+        next: 0,
+    };
+    writer.write_nodes(nodes)?;
+
+    if opts.function.is_some() {
+        writeln!(writer.out, "  ret void")?;
+    } else {
+        writeln!(writer.out, "  ret i32 0")?;
+    }
+    writeln!(writer.out, "}}")?;
+
+    Ok(())
+}
+
+struct Writer<'a, W> {
+    out: &'a mut W,
+    opts: &'a CompileOptions,
+    /// The integer type of a cell
+    cell: String,
+    /// The pointer to the tape, the global or the parameter of the function
+    tape: &'static str,
+    /// The number of the next temporary or label
+    next: usize,
+}
+
+impl<W: Write> Writer<'_, W> {
+    /// A fresh name for a temporary or a label
+    fn fresh(&mut self, prefix: &str) -> String {
+        self.next += 1;
+        format!("{}{}", prefix, self.next)
+    }
+
+    fn write_nodes(&mut self, nodes: &[Node]) -> io::Result<()> {
+        for node in nodes {
+            match *node {
+                Node::Add(delta) => match reduce(delta, self.opts.cell_bits) {
+                    0 => {}
+                    delta => {
+                        let addr = self.write_cell(0)?;
+                        let value = self.write_load(&addr)?;
+                        let sum = self.fresh("%t");
+                        writeln!(
+                            self.out,
+                            "  {} = add {} {}, {}",
+                            sum, self.cell, value, delta
+                        )?;
+                        self.write_store(&sum, &addr)?;
+                    }
+                },
+                Node::Move(delta) => {
+                    let index = self.write_index(delta)?;
+                    writeln!(self.out, "  store i64 {}, ptr %p", index)?;
+                }
+                Node::Set(value) => {
+                    let addr = self.write_cell(0)?;
+                    self.write_store(&reduce(value, self.opts.cell_bits).to_string(), &addr)?;
+                }
+                Node::MulAdd { offset, factor } => {
+                    let addr = self.write_cell(0)?;
+                    let value = self.write_load(&addr)?;
+                    let product = self.fresh("%t");
+                    writeln!(
+                        self.out,
+                        "  {} = mul {} {}, {}",
+                        product,
+                        self.cell,
+                        value,
+                        reduce(factor, self.opts.cell_bits)
+                    )?;
+                    let (mul_label, done_label) = (self.fresh("mul"), self.fresh("done"));
+                    let nonzero = self.fresh("%t");
+                    writeln!(
+                        self.out,
+                        "  {} = icmp ne {} {}, 0",
+                        nonzero, self.cell, value
+                    )?;
+                    writeln!(
+                        self.out,
+                        "  br i1 {}, label %{}, label %{}",
+                        nonzero, mul_label, done_label
+                    )?;
+                    writeln!(self.out, "{}:", mul_label)?;
+                    let target = self.write_cell(offset)?;
+                    let old = self.write_load(&target)?;
+                    let sum = self.fresh("%t");
+                    writeln!(
+                        self.out,
+                        "  {} = add {} {}, {}",
+                        sum, self.cell, old, product
+                    )?;
+                    self.write_store(&sum, &target)?;
+                    writeln!(self.out, "  br label %{}", done_label)?;
+                    writeln!(self.out, "{}:", done_label)?;
+                }
+                Node::Output => {
+                    let addr = self.write_cell(0)?;
+                    let value = self.write_load(&addr)?;
+                    let byte = if self.opts.cell_bits == 8 {
+                        value
+                    } else {
+                        let byte = self.fresh("%t");
+                        writeln!(self.out, "  {} = trunc {} {} to i8", byte, self.cell, value)?;
+                        byte
+                    };
+                    writeln!(self.out, "  store i8 {}, ptr %byte", byte)?;
+                    writeln!(self.out, "  call i64 @write(i32 1, ptr %byte, i64 1)")?;
+                }
+                Node::Input => {
+                    let addr = self.write_cell(0)?;
+                    let read = self.fresh("%t");
+                    writeln!(
+                        self.out,
+                        "  {} = call i64 @read(i32 0, ptr %byte, i64 1)",
+                        read
+                    )?;
+                    let ok = self.fresh("%t");
+                    writeln!(self.out, "  {} = icmp eq i64 {}, 1", ok, read)?;
+                    let (byte_label, eof_label, done_label) = (
+                        self.fresh("read"),
+                        self.fresh("eof"),
+                        self.fresh("read_done"),
+                    );
+                    writeln!(
+                        self.out,
+                        "  br i1 {}, label %{}, label %{}",
+                        ok, byte_label, eof_label
+                    )?;
+                    writeln!(self.out, "{}:", byte_label)?;
+                    let byte = self.fresh("%t");
+                    writeln!(self.out, "  {} = load i8, ptr %byte", byte)?;
+                    let value = if self.opts.cell_bits == 8 {
+                        byte
+                    } else {
+                        let value = self.fresh("%t");
+                        writeln!(self.out, "  {} = zext i8 {} to {}", value, byte, self.cell)?;
+                        value
+                    };
+                    self.write_store(&value, &addr)?;
+                    writeln!(self.out, "  br label %{}", done_label)?;
+                    writeln!(self.out, "{}:", eof_label)?;
+                    if let Some(value) = self.opts.eof.value() {
+                        self.write_store(&value.to_string(), &addr)?;
+                    }
+                    writeln!(self.out, "  br label %{}", done_label)?;
+                    writeln!(self.out, "{}:", done_label)?;
+                }
+                Node::Scan(step) => self.write_nodes(&scan_loop(step))?,
+                Node::Zero(len) => self.write_nodes(&zero_run(len))?,
+                Node::Loop(ref body) => {
+                    let (cond_label, body_label, end_label) =
+                        (self.fresh("loop"), self.fresh("body"), self.fresh("end"));
+                    writeln!(self.out, "  br label %{}", cond_label)?;
+                    writeln!(self.out, "{}:", cond_label)?;
+                    let addr = self.write_cell(0)?;
+                    let value = self.write_load(&addr)?;
+                    let nonzero = self.fresh("%t");
+                    writeln!(
+                        self.out,
+                        "  {} = icmp ne {} {}, 0",
+                        nonzero, self.cell, value
+                    )?;
+                    writeln!(
+                        self.out,
+                        "  br i1 {}, label %{}, label %{}",
+                        nonzero, body_label, end_label
+                    )?;
+                    writeln!(self.out, "{}:", body_label)?;
+                    self.write_nodes(body)?;
+                    writeln!(self.out, "  br label %{}", cond_label)?;
+                    writeln!(self.out, "{}:", end_label)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Computes the index of the cell at `delta` from the current cell, wrapped around the tape
+    /// or checked against its ends, and returns its name
+    fn write_index(&mut self, delta: i32) -> io::Result<String> {
+        let current = self.fresh("%t");
+        writeln!(self.out, "  {} = load i64, ptr %p", current)?;
+        if delta == 0 {
+            return Ok(current);
+        }
+        let index = self.fresh("%t");
+        writeln!(self.out, "  {} = add i64 {}, {}", index, current, delta)?;
+        if self.opts.tape_wrap {
+            let wrapped = self.fresh("%t");
+            writeln!(
+                self.out,
+                "  {} = and i64 {}, {}",
+                wrapped,
+                index,
+                self.opts.tape_size - 1
+            )?;
+            return Ok(wrapped);
+        }
+        let off = self.fresh("%t");
+        let (label, message) = if delta < 0 {
+            writeln!(self.out, "  {} = icmp slt i64 {}, 0", off, index)?;
+            TAPE_ERRORS[0]
+        } else {
+            writeln!(
+                self.out,
+                "  {} = icmp sge i64 {}, {}",
+                off, index, self.opts.tape_size
+            )?;
+            TAPE_ERRORS[1]
+        };
+        let (error_label, ok_label) = (self.fresh("error"), self.fresh("ok"));
+        writeln!(
+            self.out,
+            "  br i1 {}, label %{}, label %{}",
+            off, error_label, ok_label
+        )?;
+        writeln!(self.out, "{}:", error_label)?;
+        writeln!(
+            self.out,
+            "  call void @tape_error(ptr @{}_msg, i64 {})",
+            label,
+            message.len() + 1
+        )?;
+        writeln!(self.out, "  unreachable")?;
+        writeln!(self.out, "{}:", ok_label)?;
+        Ok(index)
+    }
+
+    /// Computes the address of the cell at `delta` from the current cell and returns its name
+    fn write_cell(&mut self, delta: i32) -> io::Result<String> {
+        let index = self.write_index(delta)?;
+        let addr = self.fresh("%t");
+        writeln!(
+            self.out,
+            "  {} = getelementptr inbounds [{} x {}], ptr {}, i64 0, i64 {}",
+            addr, self.opts.tape_size, self.cell, self.tape, index
+        )?;
+        Ok(addr)
+    }
+
+    fn write_load(&mut self, addr: &str) -> io::Result<String> {
+        let value = self.fresh("%t");
+        writeln!(self.out, "  {} = load {}, ptr {}", value, self.cell, addr)?;
+        Ok(value)
+    }
+
+    fn write_store(&mut self, value: &str, addr: &str) -> io::Result<()> {
+        writeln!(self.out, "  store {} {}, ptr {}", self.cell, value, addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_calls_write_and_adds_are_i8() {
+        let mut out = vec![];
+        write(
+            &[Node::Add(1), Node::Output],
+            &mut out,
+            &CompileOptions::default(),
+        )
+        .unwrap();
+        let ir = String::from_utf8(out).unwrap();
+        assert!(ir.contains(" = add i8 "));
+        assert!(ir.contains("  call i64 @write(i32 1, ptr %byte, i64 1)"));
+        assert!(ir.contains("declare i64 @write(i32, ptr, i64)"));
+    }
+
+    #[test]
+    fn function_takes_the_tape() {
+        let mut out = vec![];
+        let opts = CompileOptions {
+            target: crate::Target::Llvm,
+            function: Some("bf_run".to_string()),
+            ..CompileOptions::default()
+        };
+        write(&[Node::Output], &mut out, &opts).unwrap();
+        let ir = String::from_utf8(out).unwrap();
+        assert!(ir.contains("define void @bf_run(ptr %tape) {"));
+        assert!(!ir.contains("@main"));
+        assert!(!ir.contains("@tape = "));
+    }
+}
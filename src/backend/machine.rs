@@ -0,0 +1,399 @@
+use super::{reduce, scan_loop, zero_run, Syscalls, TAPE_ERRORS, X86_64_LINUX_SYSCALLS};
+use crate::ast::Node;
+use crate::CompileOptions;
+
+/// The results of a function assembled for `Runtime::Callbacks`
+pub(crate) const EXIT_OK: u32 = 0;
+pub(crate) const EXIT_UNDERFLOW: u32 = 1;
+pub(crate) const EXIT_OVERFLOW: u32 = 2;
+pub(crate) const EXIT_IO: u32 = 3;
+
+/// How the machine code gets its tape and performs I/O
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Runtime {
+    /// A function called with the initial cell pointer, the start and the end of the tape and a
+    /// context pointer, which calls `put` and `get` with the context and the cell pointer
+    /// for I/O and returns one of the `EXIT_` codes; the callbacks return nonzero on failure
+    Callbacks { put: u64, get: u64 },
+    /// The entry of a Linux program, which maps its own tape and uses syscalls, like the
+    /// x86-64 assembly backend
+    Linux,
+}
+
+/// Encodes the optimized program as x86-64 machine code for the runtime.
+///
+/// The code is position-independent and only refers to itself.
+pub(crate) fn assemble(nodes: &[Node], opts: &CompileOptions, runtime: Runtime) -> Vec<u8> {
+    Assembler {
+        opts,
+        runtime,
+        code: vec![],
+        exits: vec![],
+    }
+    .assemble(nodes)
+}
+
+const SYSCALLS: Syscalls = X86_64_LINUX_SYSCALLS;
+
+/// The x86-64 Linux syscall number of `mmap`
+const MMAP: u32 = 9;
+
+/// General-purpose registers by their encoding
+const RAX: u8 = 0;
+const RCX: u8 = 1;
+const RDX: u8 = 2;
+const RBX: u8 = 3;
+const RSI: u8 = 6;
+const RDI: u8 = 7;
+const R12: u8 = 12;
+const R13: u8 = 13;
+const R14: u8 = 14;
+
+/// `rbx` holds the cell pointer, `r12` and `r13` the bounds of the tape and `r14` the context.
+/// `rax`, `rcx`, `rdx` and the registers clobbered by syscalls are scratch registers.
+struct Assembler<'a> {
+    opts: &'a CompileOptions,
+    runtime: Runtime,
+    code: Vec<u8>,
+    /// The positions of the rel32 operands that jump to each exit, by its `EXIT_` code
+    exits: Vec<(usize, u32)>,
+}
+
+impl Assembler<'_> {
+    fn assemble(mut self, nodes: &[Node]) -> Vec<u8> {
+        // push rbp, rbx, r12, r13, r14, which also aligns the stack for the calls
+        self.emit(&[0x55, 0x53, 0x41, 0x54, 0x41, 0x55, 0x41, 0x56]);
+        match self.runtime {
+            Runtime::Callbacks { .. } => {
+                self.mov(RBX, RDI);
+                self.mov(R12, RSI);
+                self.mov(R13, RDX);
+                self.mov(R14, RCX);
+            }
+            Runtime::Linux => self.map_tape(),
+        }
+        if self.opts.init_cell != 0 {
+            self.fill_tape();
+        }
+
+        self.nodes(nodes);
+
+        let mut exit_at = vec![];
+        let mut messages = vec![];
+        let mut to_epilogue = vec![];
+        for exit in &[EXIT_OK, EXIT_UNDERFLOW, EXIT_OVERFLOW, EXIT_IO] {
+            exit_at.push(self.code.len());
+            match self.runtime {
+                Runtime::Callbacks { .. } => {
+                    // mov eax, exit
+                    self.emit(&[0xb8]);
+                    self.emit(&exit.to_le_bytes());
+                }
+                Runtime::Linux if *exit == EXIT_OK && self.opts.returns_from_main() => {
+                    // xor eax, eax
+                    self.emit(&[0x31, 0xc0]);
+                }
+                Runtime::Linux => {
+                    let error = match *exit {
+                        EXIT_UNDERFLOW => Some(TAPE_ERRORS[0]),
+                        EXIT_OVERFLOW => Some(TAPE_ERRORS[1]),
+                        _ => None,
+                    };
+                    if let Some((_, message)) = error {
+                        self.emit(&[0xbf, 2, 0, 0, 0]);
+                        // lea rsi, [rel message]
+                        self.emit(&[0x48, 0x8d, 0x35, 0, 0, 0, 0]);
+                        messages.push((self.code.len() - 4, message));
+                        self.emit(&[0xba]);
+                        self.emit(&(message.len() as u32 + 1).to_le_bytes());
+                        self.syscall(SYSCALLS.write);
+                    }
+                    // mov edi, status
+                    self.emit(&[0xbf]);
+                    self.emit(&u32::from(*exit != EXIT_OK).to_le_bytes());
+                    self.syscall(SYSCALLS.exit);
+                    continue;
+                }
+            }
+            // jmp epilogue
+            self.emit(&[0xe9, 0, 0, 0, 0]);
+            to_epilogue.push(self.code.len() - 4);
+        }
+        let epilogue = self.code.len();
+        // pop r14, r13, r12, rbx, rbp; ret
+        self.emit(&[0x41, 0x5e, 0x41, 0x5d, 0x41, 0x5c, 0x5b, 0x5d, 0xc3]);
+
+        for (operand, message) in messages {
+            let at = self.code.len();
+            self.emit(message.as_bytes());
+            self.emit(b"\n");
+            self.patch(operand, at);
+        }
+        for operand in to_epilogue {
+            self.patch(operand, epilogue);
+        }
+        for (operand, exit) in std::mem::take(&mut self.exits) {
+            self.patch(operand, exit_at[exit as usize]);
+        }
+        self.code
+    }
+
+    /// Maps the tape with `mmap`, exiting on failure
+    fn map_tape(&mut self) {
+        let bytes = self.opts.tape_bytes();
+        // xor edi, edi; mov rsi, bytes; mov edx, PROT_READ | PROT_WRITE
+        self.emit(&[0x31, 0xff, 0x48, 0xbe]);
+        self.emit(&bytes.to_le_bytes());
+        self.emit(&[0xba, 3, 0, 0, 0]);
+        // mov r10d, MAP_PRIVATE | MAP_ANONYMOUS; mov r8, -1; xor r9d, r9d
+        self.emit(&[0x41, 0xba, 0x22, 0, 0, 0]);
+        self.emit(&[0x49, 0xc7, 0xc0, 0xff, 0xff, 0xff, 0xff]);
+        self.emit(&[0x45, 0x31, 0xc9]);
+        self.syscall(MMAP);
+        // test rax, rax; js io, since errors are returned as negative numbers
+        self.emit(&[0x48, 0x85, 0xc0, 0x0f, 0x88, 0, 0, 0, 0]);
+        self.exits.push((self.code.len() - 4, EXIT_IO));
+        self.mov(R12, RAX);
+        self.mov(R13, RAX);
+        self.add_imm(R13, bytes as i64);
+        self.mov(RBX, RAX);
+        self.add_imm(
+            RBX,
+            (self.opts.start_cell() * self.opts.cell_bytes()) as i64,
+        );
+    }
+
+    /// Stores `init_cell` into every cell with `rep stos`
+    fn fill_tape(&mut self) {
+        self.mov(RDI, R12);
+        // mov rcx, tape_size
+        self.emit(&[0x48, 0xb9]);
+        self.emit(&self.opts.tape_size.to_le_bytes());
+        self.mov_imm32(RAX, i32::from(self.opts.init_cell));
+        match self.opts.cell_bits {
+            8 => self.emit(&[0xf3, 0xaa]),
+            16 => self.emit(&[0x66, 0xf3, 0xab]),
+            32 => self.emit(&[0xf3, 0xab]),
+            _ => self.emit(&[0xf3, 0x48, 0xab]),
+        }
+    }
+
+    fn nodes(&mut self, nodes: &[Node]) {
+        let bytes = self.opts.cell_bytes() as i64;
+        for node in nodes {
+            match *node {
+                Node::Add(delta) => match reduce(delta, self.opts.cell_bits) {
+                    0 => {}
+                    delta => {
+                        self.load(RAX, RBX);
+                        // add rax, imm32
+                        self.emit(&[0x48, 0x05]);
+                        self.emit(&delta.to_le_bytes());
+                        self.store(RAX, RBX);
+                    }
+                },
+                Node::Move(delta) => {
+                    self.add_imm(RBX, i64::from(delta) * bytes);
+                    self.bound(RBX, delta);
+                }
+                Node::Set(value) => {
+                    self.mov_imm32(RAX, reduce(value, self.opts.cell_bits));
+                    self.store(RAX, RBX);
+                }
+                Node::MulAdd { offset, factor } => {
+                    let skip = if self.opts.tape_wrap {
+                        None
+                    } else {
+                        self.test_cell();
+                        // jz past the addition
+                        self.emit(&[0x0f, 0x84, 0, 0, 0, 0]);
+                        Some(self.code.len())
+                    };
+                    self.mov(RDX, RBX);
+                    self.add_imm(RDX, i64::from(offset) * bytes);
+                    self.bound(RDX, offset);
+                    self.load(RAX, RBX);
+                    // imul rax, rax, imm32
+                    self.emit(&[0x48, 0x69, 0xc0]);
+                    self.emit(&factor.to_le_bytes());
+                    self.load(RCX, RDX);
+                    self.op(0x01, RCX, RAX);
+                    self.store(RCX, RDX);
+                    if let Some(skip) = skip {
+                        let end = self.code.len();
+                        self.patch(skip - 4, end);
+                    }
+                }
+                Node::Output => match self.runtime {
+                    Runtime::Callbacks { put, .. } => self.call(put),
+                    Runtime::Linux => self.transfer(1, SYSCALLS.write),
+                },
+                Node::Input => match self.runtime {
+                    Runtime::Callbacks { get, .. } => self.call(get),
+                    Runtime::Linux => self.read(),
+                },
+                Node::Scan(step) => self.nodes(&scan_loop(step)),
+                Node::Zero(len) => self.nodes(&zero_run(len)),
+                Node::Loop(ref body) => {
+                    self.test_cell();
+                    // jz end
+                    self.emit(&[0x0f, 0x84, 0, 0, 0, 0]);
+                    let skip = self.code.len();
+                    self.nodes(body);
+                    self.test_cell();
+                    // jnz start
+                    self.emit(&[0x0f, 0x85, 0, 0, 0, 0]);
+                    let end = self.code.len();
+                    self.patch(end - 4, skip);
+                    self.patch(skip - 4, end);
+                }
+            }
+        }
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    /// Points the rel32 operand at `operand` to `target`
+    fn patch(&mut self, operand: usize, target: usize) {
+        let rel = target as i64 - (operand as i64 + 4);
+        self.code[operand..operand + 4].copy_from_slice(&(rel as i32).to_le_bytes());
+    }
+
+    /// Emits a 64-bit register-to-register instruction `op dest, src` of the `op r/m64, r64` form
+    fn op(&mut self, op: u8, dest: u8, src: u8) {
+        let rex = 0x48 | (src >> 3) << 2 | dest >> 3;
+        self.emit(&[rex, op, 0xc0 | (src & 7) << 3 | dest & 7]);
+    }
+
+    fn mov(&mut self, dest: u8, src: u8) {
+        self.op(0x89, dest, src);
+    }
+
+    /// mov reg, imm32, sign-extended
+    fn mov_imm32(&mut self, reg: u8, value: i32) {
+        self.emit(&[0x48, 0xc7, 0xc0 | reg]);
+        self.emit(&value.to_le_bytes());
+    }
+
+    /// Adds the constant to `reg`, clobbering `rcx`
+    fn add_imm(&mut self, reg: u8, value: i64) {
+        // mov rcx, imm64
+        self.emit(&[0x48, 0xb9]);
+        self.emit(&value.to_le_bytes());
+        self.op(0x01, reg, RCX);
+    }
+
+    /// Loads the cell at the address in `addr` into `reg`, zero-extended
+    fn load(&mut self, reg: u8, addr: u8) {
+        let modrm = reg << 3 | addr;
+        match self.opts.cell_bits {
+            8 => self.emit(&[0x0f, 0xb6, modrm]),
+            16 => self.emit(&[0x0f, 0xb7, modrm]),
+            32 => self.emit(&[0x8b, modrm]),
+            _ => self.emit(&[0x48, 0x8b, modrm]),
+        }
+    }
+
+    /// Stores the low part of `reg` into the cell at the address in `addr`
+    fn store(&mut self, reg: u8, addr: u8) {
+        let modrm = reg << 3 | addr;
+        match self.opts.cell_bits {
+            8 => self.emit(&[0x88, modrm]),
+            16 => self.emit(&[0x66, 0x89, modrm]),
+            32 => self.emit(&[0x89, modrm]),
+            _ => self.emit(&[0x48, 0x89, modrm]),
+        }
+    }
+
+    /// Sets the flags for the current cell, clobbering `rax`
+    fn test_cell(&mut self) {
+        self.load(RAX, RBX);
+        // test rax, rax
+        self.emit(&[0x48, 0x85, 0xc0]);
+    }
+
+    /// Wraps the address in `reg` around the tape, or exits if it is off the tape
+    /// in the direction of `delta`, clobbering `rcx`
+    fn bound(&mut self, reg: u8, delta: i32) {
+        if self.opts.tape_wrap {
+            self.op(0x29, reg, R12);
+            // mov rcx, imm64
+            self.emit(&[0x48, 0xb9]);
+            self.emit(&(self.opts.tape_bytes() - 1).to_le_bytes());
+            self.op(0x21, reg, RCX);
+            self.op(0x01, reg, R12);
+        } else if delta < 0 {
+            self.op(0x39, reg, R12);
+            // jb underflow
+            self.emit(&[0x0f, 0x82, 0, 0, 0, 0]);
+            self.exits.push((self.code.len() - 4, EXIT_UNDERFLOW));
+        } else {
+            self.op(0x39, reg, R13);
+            // jae overflow
+            self.emit(&[0x0f, 0x83, 0, 0, 0, 0]);
+            self.exits.push((self.code.len() - 4, EXIT_OVERFLOW));
+        }
+    }
+
+    /// Calls the I/O callback with the context and the cell pointer, exiting if it fails
+    fn call(&mut self, function: u64) {
+        self.mov(RDI, R14);
+        self.mov(RSI, RBX);
+        // mov rax, imm64; call rax
+        self.emit(&[0x48, 0xb8]);
+        self.emit(&function.to_le_bytes());
+        self.emit(&[0xff, 0xd0]);
+        // test eax, eax; jnz io
+        self.emit(&[0x85, 0xc0, 0x0f, 0x85, 0, 0, 0, 0]);
+        self.exits.push((self.code.len() - 4, EXIT_IO));
+    }
+
+    /// Transfers one byte between the current cell and the file descriptor
+    fn transfer(&mut self, fd: u8, number: u32) {
+        // mov edi, fd
+        self.emit(&[0xbf, fd, 0, 0, 0]);
+        self.mov(RSI, RBX);
+        // mov edx, 1
+        self.emit(&[0xba, 1, 0, 0, 0]);
+        self.syscall(number);
+    }
+
+    /// Reads a byte into the current cell, handling the end of input like the assembly backend
+    fn read(&mut self) {
+        self.transfer(0, SYSCALLS.read);
+        // read(2) does not touch the buffer when it returns 0 (EOF) or a negative errno
+        let eof = self.opts.eof.value();
+        if self.opts.cell_bits == 8 && eof.is_none() {
+            return;
+        }
+        // cmp rax, 1; jne eof
+        self.emit(&[0x48, 0x83, 0xf8, 0x01, 0x0f, 0x85, 0, 0, 0, 0]);
+        let to_eof = self.code.len() - 4;
+        if self.opts.cell_bits > 8 {
+            // only the low byte was read, clear the rest of the cell
+            self.emit(&[0x0f, 0xb6, 0x03]);
+            self.store(RAX, RBX);
+        }
+        // jmp done
+        self.emit(&[0xe9, 0, 0, 0, 0]);
+        let to_done = self.code.len() - 4;
+        let at = self.code.len();
+        self.patch(to_eof, at);
+        if let Some(value) = eof {
+            self.mov_imm32(RAX, value);
+            self.store(RAX, RBX);
+        }
+        let at = self.code.len();
+        self.patch(to_done, at);
+    }
+
+    /// Invokes the syscall, whose arguments must already be set
+    fn syscall(&mut self, number: u32) {
+        // mov eax, number; syscall
+        self.emit(&[0xb8]);
+        self.emit(&number.to_le_bytes());
+        self.emit(&[0x0f, 0x05]);
+    }
+}
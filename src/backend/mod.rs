@@ -0,0 +1,384 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use crate::ast::Node;
+use crate::{CompileOptions, Error};
+
+pub mod aarch64;
+pub mod bf;
+pub mod c;
+pub mod elf;
+pub mod i386;
+pub mod llvm;
+pub(crate) mod machine;
+pub mod riscv64;
+pub mod rust;
+pub mod wat;
+pub mod x86_64;
+
+/// The platform and output format to compile for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Target {
+    /// NASM assembly for x86-64 Linux
+    X86_64Linux,
+    /// An ELF object for x86-64 Linux, assembled without external tools
+    X86_64LinuxObj,
+    /// NASM assembly for x86-64 macOS
+    X86_64Macos,
+    /// NASM assembly for 32-bit x86 Linux
+    I386Linux,
+    /// GNU assembler source for AArch64 Linux
+    Aarch64Linux,
+    /// GNU assembler source for RISC-V 64 Linux
+    Riscv64Linux,
+    /// WebAssembly text format targeting WASI
+    Wasm32,
+    /// Portable C source
+    C,
+    /// Textual LLVM IR
+    Llvm,
+    /// A standalone Rust program
+    Rust,
+    /// The optimized program written back as Brainfuck
+    Brainfuck,
+}
+
+/// The facts about a target that the backends and the driver depend on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TargetInfo {
+    /// The name accepted by `--target`
+    pub name: &'static str,
+    /// The file extension of the output
+    pub extension: &'static str,
+    /// The syscall numbers, for targets that invoke the kernel directly
+    pub syscalls: Option<Syscalls>,
+    /// The size of a pointer in bytes
+    pub pointer_bytes: u8,
+    /// The entry symbol of programs that do not return from `main`
+    pub entry: &'static str,
+}
+
+/// The numbers of the syscalls used by the compiled programs
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Syscalls {
+    pub read: u32,
+    pub write: u32,
+    pub exit: u32,
+}
+
+/// The syscall numbers of x86-64 Linux
+pub(crate) const X86_64_LINUX_SYSCALLS: Syscalls = Syscalls {
+    read: 0,
+    write: 1,
+    exit: 60,
+};
+
+/// The syscall numbers of x86-64 macOS, which are in the BSD class `0x2000000`
+pub(crate) const X86_64_MACOS_SYSCALLS: Syscalls = Syscalls {
+    read: 0x2000003,
+    write: 0x2000004,
+    exit: 0x2000001,
+};
+
+/// The syscall numbers of 32-bit x86 Linux, invoked with `int 0x80`
+pub(crate) const I386_LINUX_SYSCALLS: Syscalls = Syscalls {
+    read: 3,
+    write: 4,
+    exit: 1,
+};
+
+/// The syscall numbers of the generic Linux syscall table, used by AArch64 and RISC-V
+pub(crate) const GENERIC_LINUX_SYSCALLS: Syscalls = Syscalls {
+    read: 63,
+    write: 64,
+    exit: 93,
+};
+
+impl Target {
+    /// All targets, in the order they are listed to users
+    pub const ALL: [Target; 11] = [
+        Self::X86_64Linux,
+        Self::X86_64LinuxObj,
+        Self::X86_64Macos,
+        Self::I386Linux,
+        Self::Aarch64Linux,
+        Self::Riscv64Linux,
+        Self::Wasm32,
+        Self::C,
+        Self::Llvm,
+        Self::Rust,
+        Self::Brainfuck,
+    ];
+
+    /// The facts about the target
+    pub fn info(self) -> &'static TargetInfo {
+        match self {
+            Self::X86_64Linux => &TargetInfo {
+                name: "x86_64-linux",
+                extension: "asm",
+                syscalls: Some(X86_64_LINUX_SYSCALLS),
+                pointer_bytes: 8,
+                entry: "_start",
+            },
+            Self::X86_64LinuxObj => &TargetInfo {
+                name: "x86_64-linux-obj",
+                extension: "o",
+                syscalls: Some(X86_64_LINUX_SYSCALLS),
+                pointer_bytes: 8,
+                entry: "_start",
+            },
+            Self::X86_64Macos => &TargetInfo {
+                name: "x86_64-macos",
+                extension: "asm",
+                syscalls: Some(X86_64_MACOS_SYSCALLS),
+                pointer_bytes: 8,
+                entry: "start",
+            },
+            Self::I386Linux => &TargetInfo {
+                name: "i386-linux",
+                extension: "asm",
+                syscalls: Some(I386_LINUX_SYSCALLS),
+                pointer_bytes: 4,
+                entry: "_start",
+            },
+            Self::Aarch64Linux => &TargetInfo {
+                name: "aarch64-linux",
+                extension: "s",
+                syscalls: Some(GENERIC_LINUX_SYSCALLS),
+                pointer_bytes: 8,
+                entry: "_start",
+            },
+            Self::Riscv64Linux => &TargetInfo {
+                name: "riscv64-linux",
+                extension: "s",
+                syscalls: Some(GENERIC_LINUX_SYSCALLS),
+                pointer_bytes: 8,
+                entry: "_start",
+            },
+            Self::Wasm32 => &TargetInfo {
+                name: "wasm32",
+                extension: "wat",
+                syscalls: None,
+                pointer_bytes: 4,
+                entry: "_start",
+            },
+            Self::C => &TargetInfo {
+                name: "c",
+                extension: "c",
+                syscalls: None,
+                pointer_bytes: 8,
+                entry: "_start",
+            },
+            Self::Llvm => &TargetInfo {
+                name: "llvm",
+                extension: "ll",
+                syscalls: None,
+                pointer_bytes: 8,
+                entry: "_start",
+            },
+            Self::Rust => &TargetInfo {
+                name: "rust",
+                extension: "rs",
+                syscalls: None,
+                pointer_bytes: 8,
+                entry: "_start",
+            },
+            Self::Brainfuck => &TargetInfo {
+                name: "bf",
+                extension: "bf",
+                syscalls: None,
+                pointer_bytes: 8,
+                entry: "_start",
+            },
+        }
+    }
+
+    /// The file extension of the output
+    pub fn extension(self) -> &'static str {
+        self.info().extension
+    }
+}
+
+/// The labels of the runtime errors for moving off the start and the end of the tape,
+/// and the messages they print
+pub(crate) const TAPE_ERRORS: [(&str, &str); 2] = [
+    (
+        "tape_underflow",
+        "Runtime error: the pointer moved past the start of the tape",
+    ),
+    (
+        "tape_overflow",
+        "Runtime error: the pointer moved past the end of the tape",
+    ),
+];
+
+/// The labels of the runtime errors for `-` on 0 and `+` on the largest value of a cell with
+/// `CellWrap::Error`, and the messages they print
+pub(crate) const CELL_ERRORS: [(&str, &str); 2] = [
+    (
+        "cell_underflow",
+        "Runtime error: a cell was decremented below 0",
+    ),
+    (
+        "cell_overflow",
+        "Runtime error: a cell was incremented past its largest value",
+    ),
+];
+
+/// The assembler expression for the address the tape pointer starts at
+pub(crate) fn start_address(opts: &CompileOptions) -> String {
+    match opts.start_cell() * opts.cell_bytes() {
+        0 => "tape".to_string(),
+        offset => format!("tape+{}", offset),
+    }
+}
+
+/// A target that the optimized program can be written for, one node at a time.
+///
+/// Third-party targets can be written with `write_with`, like the built-in `x86_64::X86_64`.
+pub trait Backend {
+    /// Writes everything before the first node.
+    fn prologue(&mut self, out: &mut dyn Write, opts: &CompileOptions) -> io::Result<()>;
+    /// Writes a node at the top level of the program; loops write their body themselves.
+    fn emit(&mut self, node: &Node, out: &mut dyn Write, opts: &CompileOptions) -> io::Result<()>;
+    /// Writes everything after the last node.
+    fn epilogue(&mut self, out: &mut dyn Write, opts: &CompileOptions) -> io::Result<()>;
+}
+
+/// Writes the whole program with the backend.
+pub fn write_with<B: Backend + ?Sized>(
+    backend: &mut B,
+    nodes: &[Node],
+    out: &mut dyn Write,
+    opts: &CompileOptions,
+) -> io::Result<()> {
+    backend.prologue(out, opts)?;
+    for node in nodes {
+        backend.emit(node, out, opts)?;
+    }
+    backend.epilogue(out, opts)
+}
+
+/// Writes the directive aligning the tape, if `opts.tape_align` is set
+pub(crate) fn write_align<W: Write + ?Sized>(
+    out: &mut W,
+    opts: &CompileOptions,
+    directive: &str,
+) -> io::Result<()> {
+    match opts.tape_align {
+        Some(align) => writeln!(out, "  {} {}", directive, align),
+        None => Ok(()),
+    }
+}
+
+/// The loop a `Node::Scan` stands for, for the backends that have no faster way to scan
+pub(crate) fn scan_loop(step: i32) -> [Node; 1] {
+    [Node::Loop(vec![Node::Move(step)])]
+}
+
+/// The clears a `Node::Zero` stands for, for the backends that have no faster way to zero cells
+pub(crate) fn zero_run(len: i32) -> Vec<Node> {
+    let mut nodes = vec![Node::Set(0)];
+    for _ in 1..len {
+        nodes.extend([Node::Move(1), Node::Set(0)]);
+    }
+    if len > 1 {
+        nodes.push(Node::Move(1 - len));
+    }
+    nodes
+}
+
+/// The distance in bytes of `cells` cells of `cell_bytes` bytes, for the targets whose pointers
+/// and immediates have 32 bits.
+///
+/// A wrapping tape is a power of two of at most 2^31 bytes there, so the distance may wrap
+/// modulo 2^32 without changing the cell it reaches. Otherwise a distance past 32 bits is an
+/// error.
+pub(crate) fn byte_offset(cells: i32, cell_bytes: i32, opts: &CompileOptions) -> io::Result<i32> {
+    let bytes = i64::from(cells) * i64::from(cell_bytes);
+    match i32::try_from(bytes) {
+        Ok(bytes) => Ok(bytes),
+        Err(_) if opts.tape_wrap => Ok(bytes as i32),
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "A move of {} cells is too far for the 32-bit offsets of the {} target",
+                cells, opts.target
+            ),
+        )),
+    }
+}
+
+/// Reduces a cell delta or value into the signed range of the cell width.
+pub(crate) fn reduce(value: i32, cell_bits: u8) -> i32 {
+    if cell_bits >= 32 {
+        return value;
+    }
+    let modulus = 1 << cell_bits;
+    let value = value.rem_euclid(modulus);
+    if value >= modulus / 2 {
+        value - modulus
+    } else {
+        value
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.info().name)
+    }
+}
+
+impl FromStr for Target {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Error> {
+        // the names of the former `--emit` values are still accepted, along with `obj`
+        let legacy = match s {
+            "asm" => Some(Self::X86_64Linux),
+            "obj" => Some(Self::X86_64LinuxObj),
+            "aarch64" => Some(Self::Aarch64Linux),
+            "riscv64" => Some(Self::Riscv64Linux),
+            "wat" | "wasm32-wasi" => Some(Self::Wasm32),
+            _ => None,
+        };
+        if let Some(target) = Self::ALL
+            .iter()
+            .cloned()
+            .find(|target| target.info().name == s)
+            .or(legacy)
+        {
+            return Ok(target);
+        }
+        let names = Self::ALL
+            .iter()
+            .map(|target| target.info().name)
+            .collect::<Vec<_>>();
+        Err(crate::unknown(
+            "target",
+            s,
+            format!("one of {}", names.join(", ")),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_are_parsed_by_name() {
+        assert_eq!(
+            "aarch64-linux".parse::<Target>().unwrap(),
+            Target::Aarch64Linux
+        );
+        for target in &Target::ALL {
+            assert_eq!(target.to_string().parse::<Target>().unwrap(), *target);
+        }
+        let err = "sparc".parse::<Target>().unwrap_err().to_string();
+        for target in &Target::ALL {
+            assert!(err.contains(target.info().name));
+        }
+    }
+}
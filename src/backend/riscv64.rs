@@ -0,0 +1,259 @@
+use std::io::{self, Write};
+
+use super::{
+    reduce, scan_loop, start_address, write_align, zero_run, Syscalls, GENERIC_LINUX_SYSCALLS,
+    TAPE_ERRORS,
+};
+use crate::ast::Node;
+use crate::CompileOptions;
+
+const SYSCALLS: Syscalls = GENERIC_LINUX_SYSCALLS;
+
+/// Writes GNU assembler source for RISC-V 64 Linux.
+///
+/// The tape pointer is kept in the saved register `s1`, which is restored before returning from
+/// `main`.
+pub fn write<W: Write>(nodes: &[Node], out: &mut W, opts: &CompileOptions) -> io::Result<()> {
+    let cell = Cell::new(opts.cell_bits);
+
+    if !opts.tape_wrap {
+        writeln!(out, "  .section .rodata")?;
+        for &(label, message) in &TAPE_ERRORS {
+            writeln!(out, "{}_msg:", label)?;
+            writeln!(out, "  .ascii \"{}\\n\"", message)?;
+        }
+    }
+
+    if opts.init_cell == 0 {
+        writeln!(out, "  .bss")?;
+        write_align(out, opts, ".balign")?;
+        writeln!(out, "tape:")?;
+        writeln!(out, "  .skip {}", opts.tape_bytes())?;
+    } else {
+        writeln!(out, "  .data")?;
+        write_align(out, opts, ".balign")?;
+        writeln!(out, "tape:")?;
+        writeln!(
+            out,
+            "  .fill {}, {}, {}",
+            opts.tape_size,
+            opts.cell_bytes(),
+            opts.init_cell
+        )?;
+    }
+
+    writeln!(out, "  .text")?;
+    writeln!(out, "  .global {}", opts.entry)?;
+    writeln!(out, "{}:", opts.entry)?;
+    if opts.returns_from_main() {
+        // s1 is callee-saved
+        writeln!(out, "  addi sp, sp, -16")?;
+        writeln!(out, "  sd s1, 0(sp)")?;
+    }
+    writeln!(out, "  la s1, {}", start_address(opts))?;
+
+    write_nodes(nodes, out, opts, &cell, &mut 0)?;
+
+    writeln!(out, "  li a0, 0")?;
+    if opts.returns_from_main() {
+        writeln!(out, "  ld s1, 0(sp)")?;
+        writeln!(out, "  addi sp, sp, 16")?;
+        writeln!(out, "  ret")?;
+    } else {
+        writeln!(out, "  li a7, {}", SYSCALLS.exit)?;
+        writeln!(out, "  ecall")?;
+    }
+
+    if !opts.tape_wrap {
+        for &(label, message) in &TAPE_ERRORS {
+            writeln!(out, "{}:", label)?;
+            writeln!(out, "  la a1, {}_msg", label)?;
+            writeln!(out, "  li a2, {}", message.len() + 1)?;
+            writeln!(out, "  j tape_error")?;
+        }
+        writeln!(out, "tape_error:")?;
+        writeln!(out, "  li a0, 2")?;
+        writeln!(out, "  li a7, {}", SYSCALLS.write)?;
+        writeln!(out, "  ecall")?;
+        writeln!(out, "  li a0, 1")?;
+        writeln!(out, "  li a7, {}", SYSCALLS.exit)?;
+        writeln!(out, "  ecall")?;
+    }
+
+    if opts.gnu_stack {
+        writeln!(out, "  .section .note.GNU-stack,\"\",%progbits")?;
+    }
+
+    Ok(())
+}
+
+/// Instruction names for the cell width
+struct Cell {
+    bits: u8,
+    load: &'static str,
+    store: &'static str,
+}
+
+impl Cell {
+    fn new(bits: u8) -> Self {
+        let (load, store) = match bits {
+            8 => ("lbu", "sb"),
+            16 => ("lhu", "sh"),
+            32 => ("lwu", "sw"),
+            _ => ("ld", "sd"),
+        };
+        Self { bits, load, store }
+    }
+
+    fn bytes(&self) -> i32 {
+        i32::from(self.bits / 8)
+    }
+}
+
+fn write_nodes<W: Write>(
+    nodes: &[Node],
+    out: &mut W,
+    opts: &CompileOptions,
+    cell: &Cell,
+    next_label: &mut usize,
+) -> io::Result<()> {
+    for node in nodes {
+        match *node {
+            Node::Add(delta) => match reduce(delta, cell.bits) {
+                0 => {}
+                delta => {
+                    writeln!(out, "  {} t0, 0(s1)", cell.load)?;
+                    write_add(out, "t0", "t0", delta.into())?;
+                    writeln!(out, "  {} t0, 0(s1)", cell.store)?;
+                }
+            },
+            Node::Move(delta) => {
+                write_add(out, "s1", "s1", i64::from(delta) * i64::from(cell.bytes()))?;
+                write_bound(out, opts, "s1", delta)?;
+            }
+            Node::Set(value) => {
+                writeln!(out, "  li t0, {}", reduce(value, cell.bits))?;
+                writeln!(out, "  {} t0, 0(s1)", cell.store)?;
+            }
+            Node::MulAdd { offset, factor } => {
+                writeln!(out, "  {} t0, 0(s1)", cell.load)?;
+                let skip = if opts.tape_wrap {
+                    None
+                } else {
+                    *next_label += 1;
+                    writeln!(out, "  beqz t0, .Lmul_{}", next_label)?;
+                    Some(*next_label)
+                };
+                writeln!(out, "  li t1, {}", factor)?;
+                writeln!(out, "  mul t0, t0, t1")?;
+                write_add(out, "t2", "s1", i64::from(offset) * i64::from(cell.bytes()))?;
+                write_bound(out, opts, "t2", offset)?;
+                writeln!(out, "  {} t1, 0(t2)", cell.load)?;
+                writeln!(out, "  add t1, t1, t0")?;
+                writeln!(out, "  {} t1, 0(t2)", cell.store)?;
+                if let Some(label) = skip {
+                    writeln!(out, ".Lmul_{}:", label)?;
+                }
+            }
+            Node::Output => write_syscall(out, 1, SYSCALLS.write)?,
+            Node::Input => {
+                write_syscall(out, 0, SYSCALLS.read)?;
+                // read(2) does not touch the buffer when it returns 0 (EOF) or a negative errno
+                let eof = opts.eof.value();
+                if cell.bits > 8 || eof.is_some() {
+                    *next_label += 1;
+                    let label = *next_label;
+                    writeln!(out, "  li t0, 1")?;
+                    writeln!(out, "  bne a0, t0, .Leof_{}", label)?;
+                    if cell.bits > 8 {
+                        // only the low byte was read, clear the rest of the cell
+                        writeln!(out, "  lbu t0, 0(s1)")?;
+                        writeln!(out, "  {} t0, 0(s1)", cell.store)?;
+                    }
+                    writeln!(out, "  j .Lread_{}", label)?;
+                    writeln!(out, ".Leof_{}:", label)?;
+                    if let Some(value) = eof {
+                        writeln!(out, "  li t0, {}", value)?;
+                        writeln!(out, "  {} t0, 0(s1)", cell.store)?;
+                    }
+                    writeln!(out, ".Lread_{}:", label)?;
+                }
+            }
+            Node::Scan(step) => write_nodes(&scan_loop(step), out, opts, cell, next_label)?,
+            Node::Zero(len) => write_nodes(&zero_run(len), out, opts, cell, next_label)?,
+            Node::Loop(ref body) => {
+                *next_label += 1;
+                let label = *next_label;
+                writeln!(out, "  {} t0, 0(s1)", cell.load)?;
+                writeln!(out, "  beqz t0, .Lend_{}", label)?;
+                writeln!(out, ".Lstart_{}:", label)?;
+                write_nodes(body, out, opts, cell, next_label)?;
+                writeln!(out, "  {} t0, 0(s1)", cell.load)?;
+                writeln!(out, "  bnez t0, .Lstart_{}", label)?;
+                writeln!(out, ".Lend_{}:", label)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Adds a signed constant, which does not need to fit in an immediate operand
+fn write_add<W: Write>(out: &mut W, dest: &str, src: &str, value: i64) -> io::Result<()> {
+    if (-2048..2048).contains(&value) {
+        writeln!(out, "  addi {}, {}, {}", dest, src, value)
+    } else {
+        writeln!(out, "  li t6, {}", value)?;
+        writeln!(out, "  add {}, {}, t6", dest, src)
+    }
+}
+
+/// Wraps the address in `reg` around the tape, or branches to the runtime error if it is off the tape
+///
+/// Without wrapping, only the end of the tape in the direction of `delta` is checked.
+/// This clobbers `t4` and `t5`.
+fn write_bound<W: Write>(
+    out: &mut W,
+    opts: &CompileOptions,
+    reg: &str,
+    delta: i32,
+) -> io::Result<()> {
+    writeln!(out, "  la t5, tape")?;
+    if opts.tape_wrap {
+        writeln!(out, "  li t4, {}", opts.tape_bytes() - 1)?;
+        writeln!(out, "  sub {0}, {0}, t5", reg)?;
+        writeln!(out, "  and {0}, {0}, t4", reg)?;
+        writeln!(out, "  add {0}, {0}, t5", reg)?;
+    } else if delta < 0 {
+        writeln!(out, "  bltu {}, t5, tape_underflow", reg)?;
+    } else {
+        writeln!(out, "  li t4, {}", opts.tape_bytes())?;
+        writeln!(out, "  add t5, t5, t4")?;
+        writeln!(out, "  bgeu {}, t5, tape_overflow", reg)?;
+    }
+    Ok(())
+}
+
+/// Transfers one byte between the current cell and the file descriptor
+fn write_syscall<W: Write>(out: &mut W, fd: u32, number: u32) -> io::Result<()> {
+    writeln!(out, "  li a0, {}", fd)?;
+    writeln!(out, "  mv a1, s1")?;
+    writeln!(out, "  li a2, 1")?;
+    writeln!(out, "  li a7, {}", number)?;
+    writeln!(out, "  ecall")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_calls_read() {
+        let mut out = vec![];
+        write(&[Node::Input], &mut out, &CompileOptions::default()).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+        let lines = asm.lines().collect::<Vec<_>>();
+        let ecall = lines.iter().position(|line| *line == "  ecall").unwrap();
+        assert_eq!(lines[ecall - 1], "  li a7, 63");
+    }
+}
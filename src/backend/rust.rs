@@ -0,0 +1,157 @@
+use std::io::{self, Write};
+
+use super::{scan_loop, zero_run};
+use crate::ast::Node;
+use crate::CompileOptions;
+
+/// Writes a self-contained Rust program that only depends on `std`.
+pub fn write<W: Write>(nodes: &[Node], out: &mut W, opts: &CompileOptions) -> io::Result<()> {
+    writeln!(out, "#![allow(unused)]")?;
+    writeln!(out)?;
+    writeln!(out, "use std::io::{{self, Read, Write}};")?;
+    writeln!(out)?;
+    writeln!(out, "type Cell = u{};", opts.cell_bits)?;
+    writeln!(out)?;
+    writeln!(out, "const TAPE_SIZE: usize = {};", opts.tape_size)?;
+    writeln!(out)?;
+    writeln!(out, "fn read_byte(input: &mut impl Read) -> Option<u8> {{")?;
+    writeln!(out, "    let mut byte = [0];")?;
+    writeln!(out, "    match input.read(&mut byte) {{")?;
+    writeln!(out, "        Ok(1) => Some(byte[0]),")?;
+    writeln!(out, "        _ => None,")?;
+    writeln!(out, "    }}")?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+    if !opts.tape_wrap {
+        writeln!(out, "fn tape_error(end: &str) -> ! {{")?;
+        writeln!(
+            out,
+            "    eprintln!(\"Runtime error: the pointer moved past the {{}} of the tape\", end);"
+        )?;
+        writeln!(out, "    std::process::exit(1);")?;
+        writeln!(out, "}}")?;
+        writeln!(out)?;
+    }
+    writeln!(out, "fn main() {{")?;
+    // a boxed slice rather than an array, which would overflow the stack
+    writeln!(
+        out,
+        "    let mut tape = vec![{} as Cell; TAPE_SIZE].into_boxed_slice();",
+        opts.init_cell
+    )?;
+    writeln!(out, "    let mut p: usize = {};", opts.start_cell())?;
+    writeln!(out, "    let mut input = io::stdin();")?;
+    writeln!(out, "    let stdout = io::stdout();")?;
+    writeln!(out, "    let mut output = stdout.lock();")?;
+
+    write_nodes(nodes, out, opts, 1)?;
+
+    writeln!(out, "    output.flush().unwrap();")?;
+    writeln!(out, "}}")?;
+
+    Ok(())
+}
+
+/// The value as an unsigned cell literal
+fn cell(value: i32, opts: &CompileOptions) -> u64 {
+    i64::from(value) as u64 & (u64::MAX >> (64 - opts.cell_bits))
+}
+
+fn write_nodes<W: Write>(
+    nodes: &[Node],
+    out: &mut W,
+    opts: &CompileOptions,
+    depth: usize,
+) -> io::Result<()> {
+    let indent = "    ".repeat(depth);
+    for node in nodes {
+        match *node {
+            Node::Add(delta) => match cell(delta, opts) {
+                0 => {}
+                delta => writeln!(out, "{}tape[p] = tape[p].wrapping_add({});", indent, delta)?,
+            },
+            Node::Move(delta) => {
+                writeln!(out, "{}p = {};", indent, index(opts, &indent, delta))?;
+            }
+            Node::Set(value) => writeln!(out, "{}tape[p] = {};", indent, cell(value, opts))?,
+            Node::MulAdd { offset, factor } => {
+                writeln!(out, "{}if tape[p] != 0 {{", indent)?;
+                let inner = format!("{}    ", indent);
+                writeln!(out, "{}let q = {};", inner, index(opts, &inner, offset))?;
+                writeln!(
+                    out,
+                    "{}tape[q] = tape[q].wrapping_add(tape[p].wrapping_mul({}));",
+                    inner,
+                    cell(factor, opts)
+                )?;
+                writeln!(out, "{}}}", indent)?;
+            }
+            Node::Output => writeln!(
+                out,
+                "{}output.write_all(&[tape[p] as u8]).unwrap();",
+                indent
+            )?,
+            Node::Input => {
+                writeln!(out, "{}output.flush().unwrap();", indent)?;
+                writeln!(
+                    out,
+                    "{}if let Some(byte) = read_byte(&mut input) {{",
+                    indent
+                )?;
+                writeln!(out, "{}    tape[p] = Cell::from(byte);", indent)?;
+                if let Some(value) = opts.eof.value() {
+                    writeln!(out, "{}}} else {{", indent)?;
+                    writeln!(out, "{}    tape[p] = {};", indent, cell(value, opts))?;
+                }
+                writeln!(out, "{}}}", indent)?;
+            }
+            Node::Scan(step) => write_nodes(&scan_loop(step), out, opts, depth)?,
+            Node::Zero(len) => write_nodes(&zero_run(len), out, opts, depth)?,
+            Node::Loop(ref body) => {
+                writeln!(out, "{}while tape[p] != 0 {{", indent)?;
+                write_nodes(body, out, opts, depth + 1)?;
+                writeln!(out, "{}}}", indent)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The expression of the index `delta` cells from `p`,
+/// which wraps around the tape or exits with a runtime error if it is off the tape
+fn index(opts: &CompileOptions, indent: &str, delta: i32) -> String {
+    let abs = delta.unsigned_abs();
+    if opts.tape_wrap {
+        let op = if delta < 0 {
+            "wrapping_sub"
+        } else {
+            "wrapping_add"
+        };
+        format!("p.{}({}) & (TAPE_SIZE - 1)", op, abs)
+    } else if delta < 0 {
+        format!(
+            "p.checked_sub({})\n{}    .unwrap_or_else(|| tape_error(\"start\"))",
+            abs, indent
+        )
+    } else {
+        format!(
+            "Some(p + {}).filter(|&q| q < TAPE_SIZE)\n{}    .unwrap_or_else(|| tape_error(\"end\"))",
+            abs, indent
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_writes_to_stdout() {
+        let mut out = vec![];
+        write(&[Node::Output], &mut out, &CompileOptions::default()).unwrap();
+        let rust = String::from_utf8(out).unwrap();
+        assert!(rust.contains("let stdout = io::stdout();"));
+        assert!(rust.contains("output.write_all(&[tape[p] as u8]).unwrap();"));
+        assert_eq!(rust.matches("fn main()").count(), 1);
+    }
+}
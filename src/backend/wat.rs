@@ -0,0 +1,343 @@
+use std::io::{self, Write};
+
+use super::{byte_offset, reduce, scan_loop, zero_run, TAPE_ERRORS};
+use crate::ast::Node;
+use crate::CompileOptions;
+
+/// The first bytes of the linear memory are reserved for the WASI I/O calls: the iovec at 0,
+/// the byte count at 8 and the input buffer at 12. The tape starts right after them.
+const TAPE_OFFSET: u64 = 16;
+
+const PAGE_SIZE: u64 = 65536;
+
+/// Writes a WebAssembly text module targeting WASI.
+pub fn write<W: Write>(nodes: &[Node], out: &mut W, opts: &CompileOptions) -> io::Result<()> {
+    let cell = Cell::new(opts.cell_bits);
+    let pages = message_address(opts, TAPE_ERRORS.len()).div_ceil(PAGE_SIZE);
+
+    writeln!(out, "(module")?;
+    for func in &["fd_write", "fd_read"] {
+        writeln!(
+            out,
+            "  (import \"wasi_snapshot_preview1\" \"{0}\" (func ${0} (param i32 i32 i32 i32) (result i32)))",
+            func
+        )?;
+    }
+    if !opts.tape_wrap {
+        writeln!(
+            out,
+            "  (import \"wasi_snapshot_preview1\" \"proc_exit\" (func $proc_exit (param i32)))"
+        )?;
+    }
+    writeln!(out, "  (memory (export \"memory\") {})", pages)?;
+    if !opts.tape_wrap {
+        write_tape_error(out, opts)?;
+    }
+    writeln!(out, "  (func (export \"{}\")", opts.entry)?;
+    writeln!(out, "    (local $p i32)")?;
+    writeln!(out, "    (local $a i32)")?;
+    writeln!(
+        out,
+        "    i32.const {}",
+        TAPE_OFFSET + opts.start_cell() * opts.cell_bytes()
+    )?;
+    writeln!(out, "    local.set $p")?;
+    if opts.init_cell != 0 {
+        write_init(out, opts, &cell)?;
+    }
+
+    write_nodes(nodes, out, opts, &cell, 2, &mut 0)?;
+
+    writeln!(out, "  )")?;
+    writeln!(out, ")")?;
+
+    Ok(())
+}
+
+/// Stores `opts.init_cell` into every cell of the tape, using `$a` as the address
+fn write_init<W: Write>(out: &mut W, opts: &CompileOptions, cell: &Cell) -> io::Result<()> {
+    writeln!(out, "    i32.const {}", TAPE_OFFSET)?;
+    writeln!(out, "    local.set $a")?;
+    writeln!(out, "    (loop $init")?;
+    writeln!(out, "      local.get $a")?;
+    writeln!(out, "      {}.const {}", cell.ty, opts.init_cell)?;
+    writeln!(out, "      {}", cell.store)?;
+    writeln!(out, "      local.get $a")?;
+    writeln!(out, "      i32.const {}", cell.bytes())?;
+    writeln!(out, "      i32.add")?;
+    writeln!(out, "      local.tee $a")?;
+    writeln!(out, "      i32.const {}", TAPE_OFFSET + opts.tape_bytes())?;
+    writeln!(out, "      i32.lt_u")?;
+    writeln!(out, "      br_if $init")?;
+    writeln!(out, "    )")?;
+    Ok(())
+}
+
+/// The address of the message of the runtime error, placed after the tape with a newline each
+pub(crate) fn message_address(opts: &CompileOptions, index: usize) -> u64 {
+    let before = TAPE_ERRORS[..index]
+        .iter()
+        .map(|(_, message)| message.len() as u64 + 1)
+        .sum::<u64>();
+    TAPE_OFFSET + opts.tape_bytes() + before
+}
+
+/// Writes the messages and the `$tape_error` function, which prints a message and exits.
+fn write_tape_error<W: Write>(out: &mut W, opts: &CompileOptions) -> io::Result<()> {
+    for (index, (_, message)) in TAPE_ERRORS.iter().enumerate() {
+        writeln!(
+            out,
+            "  (data (i32.const {}) \"{}\\0a\")",
+            message_address(opts, index),
+            message
+        )?;
+    }
+    writeln!(out, "  (func $tape_error (param $msg i32) (param $len i32)")?;
+    writeln!(out, "    i32.const 0")?;
+    writeln!(out, "    local.get $msg")?;
+    writeln!(out, "    i32.store")?;
+    writeln!(out, "    i32.const 4")?;
+    writeln!(out, "    local.get $len")?;
+    writeln!(out, "    i32.store")?;
+    writeln!(out, "    i32.const 2")?;
+    writeln!(out, "    i32.const 0")?;
+    writeln!(out, "    i32.const 1")?;
+    writeln!(out, "    i32.const 8")?;
+    writeln!(out, "    call $fd_write")?;
+    writeln!(out, "    drop")?;
+    writeln!(out, "    i32.const 1")?;
+    writeln!(out, "    call $proc_exit")?;
+    writeln!(out, "  )")?;
+    Ok(())
+}
+
+/// Instruction names for the cell width
+struct Cell {
+    bits: u8,
+    /// The value type holding a cell
+    ty: &'static str,
+    load: &'static str,
+    store: &'static str,
+}
+
+impl Cell {
+    fn new(bits: u8) -> Self {
+        let (ty, load, store) = match bits {
+            8 => ("i32", "i32.load8_u", "i32.store8"),
+            16 => ("i32", "i32.load16_u", "i32.store16"),
+            32 => ("i32", "i32.load", "i32.store"),
+            _ => ("i64", "i64.load", "i64.store"),
+        };
+        Self {
+            bits,
+            ty,
+            load,
+            store,
+        }
+    }
+
+    fn bytes(&self) -> i32 {
+        i32::from(self.bits / 8)
+    }
+}
+
+fn write_nodes<W: Write>(
+    nodes: &[Node],
+    out: &mut W,
+    opts: &CompileOptions,
+    cell: &Cell,
+    depth: usize,
+    next_label: &mut usize,
+) -> io::Result<()> {
+    let indent = "  ".repeat(depth);
+    for node in nodes {
+        match *node {
+            Node::Add(delta) => match reduce(delta, cell.bits) {
+                0 => {}
+                delta => {
+                    writeln!(out, "{}local.get $p", indent)?;
+                    writeln!(out, "{}local.get $p", indent)?;
+                    writeln!(out, "{}{}", indent, cell.load)?;
+                    writeln!(out, "{}{}.const {}", indent, cell.ty, delta)?;
+                    writeln!(out, "{}{}.add", indent, cell.ty)?;
+                    writeln!(out, "{}{}", indent, cell.store)?;
+                }
+            },
+            Node::Move(delta) => {
+                write_address(out, &indent, opts, cell, delta)?;
+                writeln!(out, "{}local.set $p", indent)?;
+            }
+            Node::Set(value) => {
+                writeln!(out, "{}local.get $p", indent)?;
+                writeln!(
+                    out,
+                    "{}{}.const {}",
+                    indent,
+                    cell.ty,
+                    reduce(value, cell.bits)
+                )?;
+                writeln!(out, "{}{}", indent, cell.store)?;
+            }
+            Node::MulAdd { offset, factor } => {
+                *next_label += 1;
+                let label = *next_label;
+                writeln!(out, "{}block $mul_{}", indent, label)?;
+                let inner = format!("{}  ", indent);
+                writeln!(out, "{}local.get $p", inner)?;
+                writeln!(out, "{}{}", inner, cell.load)?;
+                writeln!(out, "{}{}.eqz", inner, cell.ty)?;
+                writeln!(out, "{}br_if $mul_{}", inner, label)?;
+                write_address(out, &inner, opts, cell, offset)?;
+                writeln!(out, "{}local.tee $a", inner)?;
+                writeln!(out, "{}local.get $a", inner)?;
+                writeln!(out, "{}{}", inner, cell.load)?;
+                writeln!(out, "{}local.get $p", inner)?;
+                writeln!(out, "{}{}", inner, cell.load)?;
+                writeln!(out, "{}{}.const {}", inner, cell.ty, factor)?;
+                writeln!(out, "{}{}.mul", inner, cell.ty)?;
+                writeln!(out, "{}{}.add", inner, cell.ty)?;
+                writeln!(out, "{}{}", inner, cell.store)?;
+                writeln!(out, "{}end", indent)?;
+            }
+            Node::Output => {
+                writeln!(out, "{}i32.const 0", indent)?;
+                writeln!(out, "{}local.get $p", indent)?;
+                writeln!(out, "{}i32.store", indent)?;
+                write_io(out, &indent, "fd_write", 1)?;
+            }
+            Node::Input => {
+                writeln!(out, "{}i32.const 0", indent)?;
+                writeln!(out, "{}i32.const 12", indent)?;
+                writeln!(out, "{}i32.store", indent)?;
+                write_io(out, &indent, "fd_read", 0)?;
+                writeln!(out, "{}i32.const 8", indent)?;
+                writeln!(out, "{}i32.load", indent)?;
+                writeln!(out, "{}if", indent)?;
+                writeln!(out, "{}  local.get $p", indent)?;
+                writeln!(out, "{}  i32.const 12", indent)?;
+                writeln!(out, "{}  i32.load8_u", indent)?;
+                if cell.ty == "i64" {
+                    writeln!(out, "{}  i64.extend_i32_u", indent)?;
+                }
+                writeln!(out, "{}  {}", indent, cell.store)?;
+                if let Some(value) = opts.eof.value() {
+                    writeln!(out, "{}else", indent)?;
+                    writeln!(out, "{}  local.get $p", indent)?;
+                    writeln!(out, "{}  {}.const {}", indent, cell.ty, value)?;
+                    writeln!(out, "{}  {}", indent, cell.store)?;
+                }
+                writeln!(out, "{}end", indent)?;
+            }
+            Node::Scan(step) => write_nodes(&scan_loop(step), out, opts, cell, depth, next_label)?,
+            Node::Zero(len) => write_nodes(&zero_run(len), out, opts, cell, depth, next_label)?,
+            Node::Loop(ref body) => {
+                *next_label += 1;
+                let label = *next_label;
+                writeln!(out, "{}block $end_{}", indent, label)?;
+                writeln!(out, "{}  loop $start_{}", indent, label)?;
+                writeln!(out, "{}    local.get $p", indent)?;
+                writeln!(out, "{}    {}", indent, cell.load)?;
+                writeln!(out, "{}    {}.eqz", indent, cell.ty)?;
+                writeln!(out, "{}    br_if $end_{}", indent, label)?;
+                write_nodes(body, out, opts, cell, depth + 2, next_label)?;
+                writeln!(out, "{}    br $start_{}", indent, label)?;
+                writeln!(out, "{}  end", indent)?;
+                writeln!(out, "{}end", indent)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Pushes the address of the cell at `delta` from the current cell
+///
+/// The address wraps around the tape, or calls `$tape_error` if it is off the tape.
+fn write_address<W: Write>(
+    out: &mut W,
+    indent: &str,
+    opts: &CompileOptions,
+    cell: &Cell,
+    delta: i32,
+) -> io::Result<()> {
+    let bytes = byte_offset(delta, cell.bytes(), opts)?;
+    writeln!(out, "{}local.get $p", indent)?;
+    if opts.tape_wrap {
+        writeln!(out, "{}i32.const {}", indent, TAPE_OFFSET)?;
+        writeln!(out, "{}i32.sub", indent)?;
+        writeln!(out, "{}i32.const {}", indent, bytes)?;
+        writeln!(out, "{}i32.add", indent)?;
+        writeln!(out, "{}i32.const {}", indent, opts.tape_bytes() - 1)?;
+        writeln!(out, "{}i32.and", indent)?;
+        writeln!(out, "{}i32.const {}", indent, TAPE_OFFSET)?;
+        writeln!(out, "{}i32.add", indent)?;
+    } else {
+        writeln!(out, "{}i32.const {}", indent, bytes)?;
+        writeln!(out, "{}i32.add", indent)?;
+        writeln!(out, "{}local.tee $a", indent)?;
+        // an address below the tape also wraps to a large offset
+        writeln!(out, "{}i32.const {}", indent, TAPE_OFFSET)?;
+        writeln!(out, "{}i32.sub", indent)?;
+        writeln!(out, "{}i32.const {}", indent, opts.tape_bytes())?;
+        writeln!(out, "{}i32.ge_u", indent)?;
+        writeln!(out, "{}if", indent)?;
+        let index = if delta < 0 { 0 } else { 1 };
+        writeln!(
+            out,
+            "{}  i32.const {}",
+            indent,
+            message_address(opts, index)
+        )?;
+        writeln!(
+            out,
+            "{}  i32.const {}",
+            indent,
+            TAPE_ERRORS[index].1.len() + 1
+        )?;
+        writeln!(out, "{}  call $tape_error", indent)?;
+        writeln!(out, "{}end", indent)?;
+        writeln!(out, "{}local.get $a", indent)?;
+    }
+    Ok(())
+}
+
+/// Transfers one byte through the iovec at address 0, whose buffer must already be set
+fn write_io<W: Write>(out: &mut W, indent: &str, func: &str, fd: u32) -> io::Result<()> {
+    writeln!(out, "{}i32.const 4", indent)?;
+    writeln!(out, "{}i32.const 1", indent)?;
+    writeln!(out, "{}i32.store", indent)?;
+
+    writeln!(out, "{}i32.const {}", indent, fd)?;
+    writeln!(out, "{}i32.const 0", indent)?;
+    writeln!(out, "{}i32.const 1", indent)?;
+    writeln!(out, "{}i32.const 8", indent)?;
+    writeln!(out, "{}call ${}", indent, func)?;
+    writeln!(out, "{}drop", indent)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clear_loop_uses_memory_and_br_if() {
+        let mut out = vec![];
+        let nodes = [Node::Add(1), Node::Loop(vec![Node::Add(-1)])];
+        write(&nodes, &mut out, &CompileOptions::default()).unwrap();
+        let wat = String::from_utf8(out).unwrap();
+        assert!(wat.contains("memory"));
+        assert!(wat.contains("br_if"));
+    }
+
+    #[test]
+    fn tape_size_is_rounded_up_to_pages() {
+        let mut out = vec![];
+        let opts = CompileOptions {
+            tape_size: PAGE_SIZE,
+            ..CompileOptions::default()
+        };
+        write(&[], &mut out, &opts).unwrap();
+        let wat = String::from_utf8(out).unwrap();
+        assert!(wat.contains("(memory (export \"memory\") 2)"));
+    }
+}
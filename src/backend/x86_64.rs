@@ -0,0 +1,944 @@
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use std::slice;
+
+use super::{
+    reduce, scan_loop, start_address, write_align, write_with, zero_run, Backend, Syscalls,
+    CELL_ERRORS, TAPE_ERRORS, X86_64_LINUX_SYSCALLS, X86_64_MACOS_SYSCALLS,
+};
+use crate::ast::Node;
+use crate::span::{Span, Spanned};
+use crate::{CellWrap, Code, CompileOptions, Error, Target};
+
+/// The differences between the operating systems
+struct Os {
+    syscalls: Syscalls,
+    /// Whether addresses must be RIP-relative, for position-independent executables
+    rip_relative: bool,
+    /// Whether C symbols like `main` start with an underscore
+    underscore: bool,
+    /// Whether the object is ELF, which can have a `.note.GNU-stack` section
+    elf: bool,
+}
+
+const LINUX: Os = Os {
+    syscalls: X86_64_LINUX_SYSCALLS,
+    rip_relative: false,
+    underscore: false,
+    elf: true,
+};
+
+const LINUX_PIE: Os = Os {
+    rip_relative: true,
+    ..LINUX
+};
+
+/// Mach-O executables are always position-independent
+const MACOS: Os = Os {
+    syscalls: X86_64_MACOS_SYSCALLS,
+    rip_relative: true,
+    underscore: true,
+    elf: false,
+};
+
+/// Writes NASM assembly for x86-64 Linux, using the `syscall` instruction of the 64-bit ABI.
+///
+/// Addresses are RIP-relative with `opts.pie`.
+pub fn write<W: Write>(nodes: &[Node], out: &mut W, opts: &CompileOptions) -> io::Result<()> {
+    write_with(&mut X86_64::with_os(linux(opts), opts), nodes, out, opts)
+}
+
+/// Writes NASM assembly for x86-64 macOS, using the BSD syscall class and RIP-relative addressing.
+///
+/// The entry `main` is the C symbol `_main`.
+pub fn write_macos<W: Write>(nodes: &[Node], out: &mut W, opts: &CompileOptions) -> io::Result<()> {
+    write_with(&mut X86_64::with_os(&MACOS, opts), nodes, out, opts)
+}
+
+/// Writes NASM assembly for the x86-64 target in `opts` with a comment before the instructions
+/// of each code, such as `; + @ line 2, column 5`.
+///
+/// The nodes must be built from the codes of `source` without optimization.
+pub fn write_annotated<W: Write>(
+    nodes: &[Node],
+    source: &[Spanned<Code>],
+    out: &mut W,
+    opts: &CompileOptions,
+) -> io::Result<()> {
+    let mut backend = X86_64::new(opts);
+    backend.source = Some(Source {
+        codes: source.iter(),
+        lines: None,
+    });
+    write_with(&mut backend, nodes, out, opts)
+}
+
+/// Writes an annotated listing like `write_annotated`, but with each line of `text` as a comment
+/// before the instructions of its codes, such as `;    2 | [-]`, instead of a comment per code.
+///
+/// The codes of `source` must have been parsed from `text`. Lines without codes are shown with
+/// the next line that has some.
+pub fn write_listing<W: Write>(
+    nodes: &[Node],
+    source: &[Spanned<Code>],
+    text: &[u8],
+    out: &mut W,
+    opts: &CompileOptions,
+) -> io::Result<()> {
+    let mut backend = X86_64::new(opts);
+    backend.source = Some(Source {
+        codes: source.iter(),
+        lines: Some(Lines {
+            text: text.split(|&byte| byte == b'\n').collect(),
+            written: 0,
+        }),
+    });
+    write_with(&mut backend, nodes, out, opts)
+}
+
+/// Writes NASM assembly for the x86-64 target in `opts` straight from the codes, one instruction
+/// per code, without building the AST.
+///
+/// This is the output of `opts.opt_level` 0, and unmatched brackets are errors at
+/// `Span::of_index` like in `ast::build_ast`, found after the output up to them has been written.
+pub fn write_codes<I, W>(codes: I, out: &mut W, opts: &CompileOptions) -> io::Result<()>
+where
+    I: IntoIterator<Item = Code>,
+    W: Write,
+{
+    let mut backend = X86_64::new(opts);
+    backend.prologue(out, opts)?;
+    // the label and the index of the `[` of each open loop
+    let mut opens = vec![];
+    for (pos, code) in codes.into_iter().enumerate() {
+        match code {
+            Code::LoopStart => opens.push((backend.start_loop(out)?, pos)),
+            Code::LoopEnd => {
+                let (label, _) = opens.pop().ok_or_else(|| {
+                    io::Error::other(Error::UnmatchedClose {
+                        span: Span::of_index(pos),
+                    })
+                })?;
+                backend.end_loop(out, label)?;
+            }
+            code => {
+                if let Some(node) = Node::leaf(&code) {
+                    backend.emit(&node, out, opts)?;
+                }
+            }
+        }
+    }
+    if let Some(&(_, pos)) = opens.first() {
+        return Err(io::Error::other(Error::UnmatchedOpen {
+            span: Span::of_index(pos),
+        }));
+    }
+    backend.epilogue(out, opts)
+}
+
+fn linux(opts: &CompileOptions) -> &'static Os {
+    if opts.pie {
+        &LINUX_PIE
+    } else {
+        &LINUX
+    }
+}
+
+/// The backend of the x86-64 assembly targets
+pub struct X86_64<'a> {
+    os: &'static Os,
+    cell: Cell,
+    next_label: usize,
+    pending: Pending,
+    source: Option<Source<'a>>,
+}
+
+impl X86_64<'_> {
+    /// Writes for the operating system of `opts.target`, which is Linux unless it is x86_64-macos.
+    pub fn new(opts: &CompileOptions) -> Self {
+        let os = if opts.target == Target::X86_64Macos {
+            &MACOS
+        } else {
+            linux(opts)
+        };
+        Self::with_os(os, opts)
+    }
+
+    fn with_os(os: &'static Os, opts: &CompileOptions) -> Self {
+        Self {
+            os,
+            cell: Cell::new(opts.cell_bits),
+            next_label: 0,
+            pending: Pending::default(),
+            source: None,
+        }
+    }
+
+    /// Writes the start of a loop and returns its label
+    fn start_loop(&mut self, out: &mut dyn Write) -> io::Result<usize> {
+        self.flush(out)?;
+        self.next_label += 1;
+        let label = self.next_label;
+        writeln!(out, "  cmp {}, 0", self.cell.at(0))?;
+        writeln!(out, "  je .end_{}", label)?;
+        writeln!(out, ".start_{}:", label)?;
+        Ok(label)
+    }
+
+    fn end_loop(&mut self, out: &mut dyn Write, label: usize) -> io::Result<()> {
+        self.flush(out)?;
+        writeln!(out, "  cmp {}, 0", self.cell.at(0))?;
+        writeln!(out, "  jne .start_{}", label)?;
+        writeln!(out, ".end_{}:", label)
+    }
+
+    /// Applies the pending offset to `rax`, which has already been checked against the tape
+    fn flush(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        write_move(out, &self.cell, self.pending.offset)?;
+        self.pending = Pending::default();
+        Ok(())
+    }
+}
+
+impl Backend for X86_64<'_> {
+    fn prologue(&mut self, out: &mut dyn Write, opts: &CompileOptions) -> io::Result<()> {
+        let (os, cell) = (self.os, &self.cell);
+        let entry = if os.underscore && opts.returns_from_main() {
+            "_main"
+        } else {
+            &opts.entry
+        };
+
+        if os.rip_relative {
+            writeln!(out, "default rel")?;
+        }
+
+        let errors = runtime_errors(opts);
+        if !errors.is_empty() {
+            writeln!(out, "section .rodata")?;
+            for &(label, message) in &errors {
+                writeln!(out, "  {}_msg db \"{}\", 10", label, message)?;
+                writeln!(out, "  {}_len equ $ - {}_msg", label, label)?;
+            }
+        }
+
+        writeln!(out, "section .bss")?;
+        writeln!(out, "  tape_ptr RESQ 1")?;
+        if opts.init_cell == 0 {
+            write_align(out, opts, "alignb")?;
+            writeln!(out, "  tape RESB {}", opts.tape_bytes())?;
+        } else {
+            writeln!(out, "section .data")?;
+            write_align(out, opts, "align")?;
+            writeln!(
+                out,
+                "  tape TIMES {} {} {}",
+                opts.tape_size, cell.data, opts.init_cell
+            )?;
+        }
+
+        writeln!(out, "section .text")?;
+        writeln!(out, "  global {}", entry)?;
+        writeln!(out, "{}:", entry)?;
+        write_address(out, os, "rax", &start_address(opts))
+    }
+
+    fn emit(&mut self, node: &Node, out: &mut dyn Write, opts: &CompileOptions) -> io::Result<()> {
+        let (os, cell) = (self.os, &self.cell);
+        // wrapping would need a bound per access
+        let defer = opts.opt_level >= 1 && !opts.tape_wrap;
+        if let Some(source) = &mut self.source {
+            source.write_comment(out)?;
+        }
+        let at = self.pending.offset;
+        match *node {
+            Node::Add(delta) if opts.cell_wrap != CellWrap::Wrap => {
+                write_checked_add(out, opts, cell, at, delta, &mut self.next_label)?
+            }
+            // the delta is reduced to the cell width, so 257 `+` on bytes become `inc`
+            Node::Add(delta) => match reduce(delta, cell.bits) {
+                0 => {}
+                1 => writeln!(out, "  inc {}", cell.at(at))?,
+                -1 => writeln!(out, "  dec {}", cell.at(at))?,
+                // i32::MIN cannot be negated, but `add` sign-extends it all the same
+                delta if delta < 0 && delta != i32::MIN => {
+                    writeln!(out, "  sub {}, {}", cell.at(at), -delta)?
+                }
+                delta => writeln!(out, "  add {}, {}", cell.at(at), delta)?,
+            },
+            Node::Move(delta) if defer => {
+                let pending = &mut self.pending;
+                // the offsets are displacements of 32 bits, so a move past them updates `rax`
+                let offset = pending
+                    .offset
+                    .checked_add(delta)
+                    .filter(|offset| offset.checked_mul(cell.bytes()).is_some());
+                match offset {
+                    Some(offset) => {
+                        pending.offset = offset;
+                        if offset > pending.max || offset < pending.min {
+                            writeln!(out, "  lea rdx, [rax{:+}]", offset * cell.bytes())?;
+                            write_bound(out, opts, os, "rdx", delta)?;
+                            pending.max = pending.max.max(offset);
+                            pending.min = pending.min.min(offset);
+                        }
+                    }
+                    None => {
+                        write_move(out, cell, pending.offset)?;
+                        *pending = Pending::default();
+                        write_move(out, cell, delta)?;
+                        write_bound(out, opts, os, "rax", delta)?;
+                    }
+                }
+            }
+            Node::Move(delta) => {
+                write_move(out, cell, delta)?;
+                write_bound(out, opts, os, "rax", delta)?;
+            }
+            Node::Set(value) => {
+                writeln!(out, "  mov {}, {}", cell.at(at), reduce(value, cell.bits))?
+            }
+            Node::MulAdd { offset, factor } => {
+                writeln!(out, "{}", cell.load_rcx(at))?;
+                let skip = if opts.tape_wrap {
+                    None
+                } else {
+                    self.next_label += 1;
+                    writeln!(out, "  test rcx, rcx")?;
+                    writeln!(out, "  jz .mul_{}", self.next_label)?;
+                    Some(self.next_label)
+                };
+                if factor != 1 {
+                    writeln!(out, "  imul rcx, rcx, {}", factor)?;
+                }
+                let bytes = (i64::from(at) + i64::from(offset)) * i64::from(cell.bytes());
+                write_lea(out, "rdx", bytes)?;
+                write_bound(out, opts, os, "rdx", offset)?;
+                writeln!(out, "  add {} [rdx], {}", cell.size, cell.rcx)?;
+                if let Some(label) = skip {
+                    writeln!(out, ".mul_{}:", label)?;
+                }
+            }
+            Node::Output => {
+                self.flush(out)?;
+                write_syscall(out, 1, os.syscalls.write)?;
+                writeln!(out, "  mov rax, [tape_ptr]")?;
+            }
+            Node::Input => {
+                self.flush(out)?;
+                let cell = &self.cell;
+                write_syscall(out, 0, os.syscalls.read)?;
+                // read(2) does not touch the buffer when it returns 0 (EOF) or a negative errno
+                let eof = opts.eof.value();
+                if cell.bits == 8 && eof.is_none() {
+                    writeln!(out, "  mov rax, [tape_ptr]")?;
+                } else {
+                    self.next_label += 1;
+                    let label = self.next_label;
+                    writeln!(out, "  cmp rax, 1")?;
+                    writeln!(out, "  mov rax, [tape_ptr]")?;
+                    writeln!(out, "  jne .eof_{}", label)?;
+                    if cell.bits > 8 {
+                        // only the low byte was read, clear the rest of the cell
+                        writeln!(out, "  movzx ecx, byte [rax]")?;
+                        writeln!(out, "  mov {}, {}", cell.at(0), cell.rcx)?;
+                    }
+                    writeln!(out, "  jmp .read_{}", label)?;
+                    writeln!(out, ".eof_{}:", label)?;
+                    if let Some(value) = eof {
+                        writeln!(out, "  mov {}, {}", cell.at(0), value)?;
+                    }
+                    writeln!(out, ".read_{}:", label)?;
+                }
+            }
+            // wrapping scans would have to restart at the other end
+            Node::Scan(step @ (1 | -1)) if !opts.tape_wrap => {
+                self.flush(out)?;
+                write_scan(out, os, opts, &self.cell, step)?;
+            }
+            Node::Scan(step) => {
+                self.flush(out)?;
+                // the loop has no codes of its own
+                let source = self.source.take();
+                for node in &scan_loop(step) {
+                    self.emit(node, out, opts)?;
+                }
+                self.source = source;
+            }
+            // the cells of a wrapping tape need not be contiguous
+            Node::Zero(len) if !opts.tape_wrap => {
+                let last = i64::from(at) + i64::from(len) - 1;
+                if !defer || last > i64::from(self.pending.max) {
+                    write_lea(out, "rdx", last * i64::from(cell.bytes()))?;
+                    write_bound(out, opts, os, "rdx", 1)?;
+                }
+                if defer {
+                    self.pending.max = self
+                        .pending
+                        .max
+                        .max(i32::try_from(last).unwrap_or(i32::MAX));
+                }
+                // `rep stosb` stores `al` at `rdi` `rcx` times
+                write_lea(out, "rdi", i64::from(at) * i64::from(cell.bytes()))?;
+                writeln!(out, "  mov rdx, rax")?;
+                writeln!(out, "  xor eax, eax")?;
+                let bytes = i64::from(len) * i64::from(cell.bytes());
+                writeln!(out, "  mov rcx, {}", bytes)?;
+                writeln!(out, "  rep stosb")?;
+                writeln!(out, "  mov rax, rdx")?;
+            }
+            Node::Zero(len) => {
+                let source = self.source.take();
+                for node in &zero_run(len) {
+                    self.emit(node, out, opts)?;
+                }
+                self.source = source;
+            }
+            Node::Loop(ref body) => {
+                let label = self.start_loop(out)?;
+                for node in body {
+                    self.emit(node, out, opts)?;
+                }
+                if let Some(source) = &mut self.source {
+                    source.write_comment(out)?;
+                }
+                self.end_loop(out, label)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn epilogue(&mut self, out: &mut dyn Write, opts: &CompileOptions) -> io::Result<()> {
+        self.flush(out)?;
+        let os = self.os;
+        if opts.returns_from_main() {
+            // only caller-saved registers are used
+            writeln!(out, "  xor eax, eax")?;
+            writeln!(out, "  ret")?;
+        } else {
+            writeln!(out, "  mov rax, {}", os.syscalls.exit)?;
+            writeln!(out, "  xor rdi, rdi")?;
+            writeln!(out, "  syscall")?;
+        }
+
+        let errors = runtime_errors(opts);
+        if !errors.is_empty() {
+            for &(label, _) in &errors {
+                writeln!(out, "{}:", label)?;
+                write_address(out, os, "rsi", &format!("{}_msg", label))?;
+                writeln!(out, "  mov rdx, {}_len", label)?;
+                writeln!(out, "  jmp tape_error")?;
+            }
+            writeln!(out, "tape_error:")?;
+            writeln!(out, "  mov rax, {}", os.syscalls.write)?;
+            writeln!(out, "  mov rdi, 2")?;
+            writeln!(out, "  syscall")?;
+            writeln!(out, "  mov rax, {}", os.syscalls.exit)?;
+            writeln!(out, "  mov rdi, 1")?;
+            writeln!(out, "  syscall")?;
+        }
+
+        if os.elf && opts.gnu_stack {
+            writeln!(
+                out,
+                "section .note.GNU-stack noalloc noexec nowrite progbits"
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The labels and messages of the runtime errors the program can exit with
+fn runtime_errors(opts: &CompileOptions) -> Vec<(&'static str, &'static str)> {
+    let mut errors = vec![];
+    if !opts.tape_wrap {
+        errors.extend_from_slice(&TAPE_ERRORS);
+    }
+    if opts.cell_wrap == CellWrap::Error {
+        errors.extend_from_slice(&CELL_ERRORS);
+    }
+    errors
+}
+
+/// Operand names for the cell width
+struct Cell {
+    bits: u8,
+    /// The NASM size specifier
+    size: &'static str,
+    /// The part of `rcx` as wide as a cell
+    rcx: &'static str,
+    /// The string instruction comparing a cell with the same part of `rax`
+    scas: &'static str,
+    /// The NASM pseudo-instruction declaring an initialized cell
+    data: &'static str,
+}
+
+impl Cell {
+    fn new(bits: u8) -> Self {
+        let (size, rcx, scas, data) = match bits {
+            8 => ("byte", "cl", "scasb", "DB"),
+            16 => ("word", "cx", "scasw", "DW"),
+            32 => ("dword", "ecx", "scasd", "DD"),
+            _ => ("qword", "rcx", "scasq", "DQ"),
+        };
+        Self {
+            bits,
+            size,
+            rcx,
+            scas,
+            data,
+        }
+    }
+
+    fn bytes(&self) -> i32 {
+        i32::from(self.bits / 8)
+    }
+
+    /// The memory operand of the cell at the offset from the current cell
+    fn at(&self, offset: i32) -> String {
+        match offset * self.bytes() {
+            0 => format!("{} [rax]", self.size),
+            offset if offset < 0 => format!("{} [rax-{}]", self.size, -offset),
+            offset => format!("{} [rax+{}]", self.size, offset),
+        }
+    }
+
+    /// Loads the cell at the offset zero-extended into `rcx`
+    fn load_rcx(&self, offset: i32) -> String {
+        match self.bits {
+            8 | 16 => format!("  movzx ecx, {}", self.at(offset)),
+            32 => format!("  mov ecx, {}", self.at(offset)),
+            _ => format!("  mov rcx, {}", self.at(offset)),
+        }
+    }
+}
+
+/// The offset of the current cell from `rax`, when pointer moves are deferred
+///
+/// Cells are addressed at the offset until the pointer has to be in `rax`, at loop boundaries,
+/// I/O and the end of the program. Only the moves past the range of offsets already checked
+/// against the ends of the tape are checked, so `>+<+` needs no pointer update at all.
+#[derive(Default)]
+struct Pending {
+    offset: i32,
+    min: i32,
+    max: i32,
+}
+
+/// Adds the delta to the cell at the offset, saturating or jumping to the runtime error if the
+/// carry flag shows that it went past the range of the cell
+fn write_checked_add(
+    out: &mut dyn Write,
+    opts: &CompileOptions,
+    cell: &Cell,
+    at: i32,
+    delta: i32,
+    next_label: &mut usize,
+) -> io::Result<()> {
+    // `inc` and `dec` leave the carry flag alone
+    let (op, amount, limit, error) = if delta < 0 {
+        ("sub", -delta, 0, CELL_ERRORS[0].0)
+    } else {
+        ("add", delta, -1, CELL_ERRORS[1].0)
+    };
+    writeln!(out, "  {} {}, {}", op, cell.at(at), amount)?;
+    if opts.cell_wrap == CellWrap::Error {
+        writeln!(out, "  jc {}", error)?;
+    } else {
+        *next_label += 1;
+        let label = *next_label;
+        writeln!(out, "  jnc .in_range_{}", label)?;
+        writeln!(out, "  mov {}, {}", cell.at(at), limit)?;
+        writeln!(out, ".in_range_{}:", label)?;
+    }
+    Ok(())
+}
+
+/// The codes the nodes were built from, in the order of the nodes and the ends of their loops
+struct Source<'a> {
+    codes: slice::Iter<'a, Spanned<Code>>,
+    /// The lines of the source for a listing, which are written instead of the codes
+    lines: Option<Lines<'a>>,
+}
+
+/// The lines of the source of a listing
+struct Lines<'a> {
+    text: Vec<&'a [u8]>,
+    /// The number of lines written so far
+    written: usize,
+}
+
+impl Source<'_> {
+    /// Writes a comment showing the code of the next node or, in a listing, the lines up to it
+    fn write_comment(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        // `#` has no node
+        let code = match self.codes.find(|code| code.inner != Code::Debug) {
+            Some(code) => code,
+            None => return Ok(()),
+        };
+        let lines = match &mut self.lines {
+            Some(lines) => lines,
+            None => return writeln!(out, "  ; {} @ {}", code.inner, code.span),
+        };
+        let end = lines.text.len().min(code.span.line as usize);
+        for (index, line) in lines.text[..end].iter().enumerate().skip(lines.written) {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            writeln!(
+                out,
+                "; {:>4} | {}",
+                index + 1,
+                String::from_utf8_lossy(line)
+            )?;
+        }
+        lines.written = lines.written.max(end);
+        Ok(())
+    }
+}
+
+/// Scans for a zero cell with `repne scas` in the direction of the step, which is 1 or -1
+///
+/// The count is the number of cells up to the end of the tape in that direction, so running out
+/// of it means that the pointer would have moved off the tape.
+fn write_scan(
+    out: &mut dyn Write,
+    os: &Os,
+    opts: &CompileOptions,
+    cell: &Cell,
+    step: i32,
+) -> io::Result<()> {
+    let shift = cell.bytes().trailing_zeros();
+    writeln!(out, "  mov rdi, rax")?;
+    if step > 0 {
+        write_address(out, os, "rcx", &format!("tape+{}", opts.tape_bytes()))?;
+        writeln!(out, "  sub rcx, rdi")?;
+    } else {
+        writeln!(out, "  mov rcx, rdi")?;
+        write_address(out, os, "rsi", "tape")?;
+        writeln!(out, "  sub rcx, rsi")?;
+        writeln!(out, "  add rcx, {}", cell.bytes())?;
+        writeln!(out, "  std")?;
+    }
+    if shift > 0 {
+        writeln!(out, "  shr rcx, {}", shift)?;
+    }
+    writeln!(out, "  xor eax, eax")?;
+    writeln!(out, "  repne {}", cell.scas)?;
+    if step > 0 {
+        writeln!(out, "  jne tape_overflow")?;
+        writeln!(out, "  lea rax, [rdi-{}]", cell.bytes())?;
+    } else {
+        // the direction flag must be clear at calls and syscalls
+        writeln!(out, "  cld")?;
+        writeln!(out, "  jne tape_underflow")?;
+        writeln!(out, "  lea rax, [rdi+{}]", cell.bytes())?;
+    }
+    Ok(())
+}
+
+/// Moves `rax` by the number of cells without checking the ends of the tape
+fn write_move(out: &mut dyn Write, cell: &Cell, delta: i32) -> io::Result<()> {
+    match i64::from(delta) * i64::from(cell.bytes()) {
+        0 => Ok(()),
+        1 => writeln!(out, "  inc rax"),
+        -1 => writeln!(out, "  dec rax"),
+        delta if i32::try_from(delta).is_err() => {
+            writeln!(out, "  mov rsi, {}", delta)?;
+            writeln!(out, "  add rax, rsi")
+        }
+        delta if delta < 0 => writeln!(out, "  sub rax, {}", -delta),
+        delta => writeln!(out, "  add rax, {}", delta),
+    }
+}
+
+/// Loads the address `bytes` after `rax` into `reg`, which may be past a 32-bit displacement
+fn write_lea(out: &mut dyn Write, reg: &str, bytes: i64) -> io::Result<()> {
+    if i32::try_from(bytes).is_ok() {
+        writeln!(out, "  lea {}, [rax{:+}]", reg, bytes)
+    } else {
+        writeln!(out, "  mov {}, {}", reg, bytes)?;
+        writeln!(out, "  add {}, rax", reg)
+    }
+}
+
+/// Wraps the address in `reg` around the tape, or jumps to the runtime error if it is off the tape
+///
+/// Without wrapping, only the end of the tape in the direction of `delta` is checked.
+/// This clobbers `rsi`.
+fn write_bound(
+    out: &mut dyn Write,
+    opts: &CompileOptions,
+    os: &Os,
+    reg: &str,
+    delta: i32,
+) -> io::Result<()> {
+    if opts.tape_wrap {
+        write_address(out, os, "rsi", "tape")?;
+        writeln!(out, "  sub {}, rsi", reg)?;
+        let mask = opts.tape_bytes() - 1;
+        if mask <= i32::MAX as u64 {
+            writeln!(out, "  and {}, {}", reg, mask)?;
+        } else {
+            writeln!(out, "  mov rsi, {}", mask)?;
+            writeln!(out, "  and {}, rsi", reg)?;
+            write_address(out, os, "rsi", "tape")?;
+        }
+        writeln!(out, "  add {}, rsi", reg)?;
+    } else if delta < 0 {
+        write_address(out, os, "rsi", "tape")?;
+        writeln!(out, "  cmp {}, rsi", reg)?;
+        writeln!(out, "  jb tape_underflow")?;
+    } else {
+        write_address(out, os, "rsi", &format!("tape+{}", opts.tape_bytes()))?;
+        writeln!(out, "  cmp {}, rsi", reg)?;
+        writeln!(out, "  jae tape_overflow")?;
+    }
+    Ok(())
+}
+
+/// Loads the address of the label expression into the register
+fn write_address(out: &mut dyn Write, os: &Os, reg: &str, address: &str) -> io::Result<()> {
+    if os.rip_relative {
+        writeln!(out, "  lea {}, [{}]", reg, address)
+    } else {
+        writeln!(out, "  mov {}, {}", reg, address)
+    }
+}
+
+/// Transfers one byte between the current cell and the file descriptor
+///
+/// The tape pointer is saved in `[tape_ptr]` and the return value of the syscall is left in `rax`.
+fn write_syscall(out: &mut dyn Write, fd: u32, number: u32) -> io::Result<()> {
+    // rax, rcx and r11 are clobbered by the syscall
+    writeln!(out, "  mov [tape_ptr], rax")?;
+    writeln!(out, "  mov rsi, rax")?;
+    writeln!(out, "  mov rax, {}", number)?;
+    writeln!(out, "  mov rdi, {}", fd)?;
+    writeln!(out, "  mov rdx, 1")?;
+    writeln!(out, "  syscall")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TapeStart;
+
+    #[test]
+    fn io_uses_the_64_bit_abi() {
+        let mut out = vec![];
+        let nodes = [Node::Input, Node::Output];
+        write(&nodes, &mut out, &CompileOptions::default()).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+        assert!(!asm.contains("int 0x80"));
+        assert!(asm.contains("syscall"));
+    }
+
+    #[test]
+    fn wide_cells_use_wide_operands() {
+        let mut out = vec![];
+        let opts = CompileOptions {
+            tape_size: 16,
+            cell_bits: 16,
+            ..CompileOptions::default()
+        };
+        write(&[Node::Add(1), Node::Move(1)], &mut out, &opts).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+        assert!(asm.contains("tape RESB 32"));
+        assert!(asm.contains("inc word [rax]"));
+        assert!(asm.contains("add rax, 2"));
+    }
+
+    #[test]
+    fn moves_are_checked_or_wrapped() {
+        let mut out = vec![];
+        write(&[Node::Move(-1)], &mut out, &CompileOptions::default()).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+        assert!(asm.contains("jb tape_underflow"));
+
+        let mut out = vec![];
+        let opts = CompileOptions {
+            tape_size: 16,
+            tape_wrap: true,
+            ..CompileOptions::default()
+        };
+        write(&[Node::Move(-1)], &mut out, &opts).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+        assert!(asm.contains("and rax, 15"));
+        assert!(!asm.contains("jb tape_underflow"));
+    }
+
+    #[test]
+    fn tape_start_zero_has_no_offset() {
+        let mut out = vec![];
+        let opts = CompileOptions {
+            tape_start: TapeStart::Zero,
+            ..CompileOptions::default()
+        };
+        write(&[], &mut out, &opts).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+        assert!(asm.lines().any(|line| line == "  mov rax, tape"));
+    }
+
+    #[test]
+    fn tape_start_center_is_cell_aligned() {
+        let mut out = vec![];
+        let opts = CompileOptions {
+            tape_size: 5,
+            cell_bits: 32,
+            ..CompileOptions::default()
+        };
+        write(&[], &mut out, &opts).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+        assert!(asm.lines().any(|line| line == "  mov rax, tape+8"));
+    }
+
+    #[test]
+    fn main_entry_returns() {
+        let mut out = vec![];
+        let opts = CompileOptions {
+            entry: "main".to_string(),
+            ..CompileOptions::default()
+        };
+        write(&[], &mut out, &opts).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+        assert!(asm.contains("global main"));
+        assert!(asm.contains("main:"));
+        assert!(!asm.contains("_start"));
+        assert!(asm.contains("  ret"));
+    }
+
+    #[test]
+    fn macos_uses_the_bsd_syscalls() {
+        let mut out = vec![];
+        write_macos(&[Node::Output], &mut out, &CompileOptions::default()).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+        assert!(asm.contains(&format!("mov rax, {}", 0x2000004)));
+        assert!(asm.contains("default rel"));
+    }
+
+    #[test]
+    fn moves_are_deferred_into_offsets() {
+        let mut out = vec![];
+        let opts = CompileOptions {
+            opt_level: 1,
+            ..CompileOptions::default()
+        };
+        let nodes = [Node::Move(1), Node::Add(1), Node::Move(1), Node::Add(1)];
+        write(&nodes, &mut out, &opts).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+        assert!(asm.contains("inc byte [rax+1]"));
+        assert!(asm.contains("inc byte [rax+2]"));
+        let moves = asm
+            .lines()
+            .filter(|line| line.starts_with("  add rax,") || *line == "  inc rax")
+            .collect::<Vec<_>>();
+        assert_eq!(moves, ["  add rax, 2"]);
+    }
+
+    #[test]
+    fn scans_use_repne_scas() {
+        for (step, direction) in [(1, "jne tape_overflow"), (-1, "jne tape_underflow")] {
+            let mut out = vec![];
+            write(&[Node::Scan(step)], &mut out, &CompileOptions::default()).unwrap();
+            let asm = String::from_utf8(out).unwrap();
+            assert!(asm.contains("repne scasb"));
+            assert!(asm.contains(direction));
+            assert!(!asm.contains(".start_"));
+        }
+    }
+
+    #[test]
+    fn cell_errors_jump_to_their_handler() {
+        let mut out = vec![];
+        let opts = CompileOptions {
+            cell_wrap: crate::CellWrap::Error,
+            ..CompileOptions::default()
+        };
+        write(&[Node::Add(-1)], &mut out, &opts).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+        assert!(asm.contains("cell_underflow:"));
+        assert!(asm.contains("  sub byte [rax], 1\n  jc cell_underflow\n"));
+    }
+
+    #[test]
+    fn pie_uses_rip_relative_addresses() {
+        let mut out = vec![];
+        let opts = CompileOptions {
+            tape_size: 16,
+            pie: true,
+            ..CompileOptions::default()
+        };
+        write(&[Node::Output], &mut out, &opts).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+        assert!(asm.starts_with("default rel\n"));
+        assert!(asm.contains("  lea rax, [tape+8]\n"));
+        assert!(!asm.contains("mov rax, tape"));
+    }
+
+    #[test]
+    fn linux_output_has_a_gnu_stack_note() {
+        let note = "section .note.GNU-stack noalloc noexec nowrite progbits";
+        let asm = |opts: &CompileOptions, macos: bool| {
+            let mut out = vec![];
+            if macos {
+                write_macos(&[Node::Output], &mut out, opts).unwrap();
+            } else {
+                write(&[Node::Output], &mut out, opts).unwrap();
+            }
+            String::from_utf8(out).unwrap()
+        };
+        let opts = CompileOptions::default();
+        assert!(asm(&opts, false).contains(note));
+        assert!(!asm(&opts, true).contains(note));
+        let without = CompileOptions {
+            gnu_stack: false,
+            ..CompileOptions::default()
+        };
+        assert!(!asm(&without, false).contains(note));
+    }
+
+    #[test]
+    fn deltas_are_reduced_to_the_cell_width() {
+        let asm = |delta, cell_bits| {
+            let mut out = vec![];
+            let opts = CompileOptions {
+                tape_size: 16,
+                cell_bits,
+                ..CompileOptions::default()
+            };
+            write(&[Node::Add(delta)], &mut out, &opts).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert!(asm(257, 8).contains("  inc byte [rax]\n"));
+        assert!(asm(300, 8).contains("  add byte [rax], 44\n"));
+        assert!(asm(200, 8).contains("  sub byte [rax], 56\n"));
+        assert!(asm(i32::MIN, 32).contains("  add dword [rax], -2147483648\n"));
+    }
+
+    #[test]
+    fn moves_past_the_displacements_update_the_pointer() {
+        let mut out = vec![];
+        let opts = CompileOptions {
+            opt_level: 1,
+            ..CompileOptions::default()
+        };
+        let nodes = [Node::Move(i32::MAX), Node::Move(1), Node::Add(1)];
+        write(&nodes, &mut out, &opts).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+        assert!(asm.contains("  add rax, 2147483647\n  inc rax\n"));
+        assert!(asm.contains("  inc byte [rax]\n"));
+    }
+
+    #[test]
+    fn moves_past_32_bit_displacements_go_through_a_register() {
+        let mut out = vec![];
+        let opts = CompileOptions {
+            cell_bits: 64,
+            ..CompileOptions::default()
+        };
+        write(&[Node::Move(1 << 28)], &mut out, &opts).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+        assert!(asm.contains("  mov rsi, 2147483648\n  add rax, rsi\n"));
+    }
+}
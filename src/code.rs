@@ -1,5 +1,6 @@
 use std::convert::TryFrom;
 use std::fmt;
+use std::str::FromStr;
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Code {
@@ -11,6 +12,23 @@ pub enum Code {
     SysRead,
     LoopStart,
     LoopEnd,
+    /// `#`, which dumps the tape in the debugger and does nothing otherwise
+    Debug,
+}
+
+impl Code {
+    /// All codes, in the order of their declaration
+    pub const ALL: [Code; 9] = [
+        Self::MemInc,
+        Self::MemDec,
+        Self::PtrInc,
+        Self::PtrDec,
+        Self::SysWrite,
+        Self::SysRead,
+        Self::LoopStart,
+        Self::LoopEnd,
+        Self::Debug,
+    ];
 }
 
 impl fmt::Display for Code {
@@ -27,11 +45,31 @@ impl fmt::Display for Code {
                 Self::SysRead => ',',
                 Self::LoopStart => '[',
                 Self::LoopEnd => ']',
+                Self::Debug => '#',
             }
         )
     }
 }
 
+/// A whole program, displayed as its Brainfuck source without comments
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Program(pub Vec<Code>);
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for code in &self.0 {
+            write!(f, "{}", code)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<Vec<Code>> for Program {
+    fn from(codes: Vec<Code>) -> Self {
+        Self(codes)
+    }
+}
+
 impl TryFrom<char> for Code {
     type Error = ();
     fn try_from(from: char) -> Result<Self, ()> {
@@ -44,8 +82,87 @@ impl TryFrom<char> for Code {
             ',' => Self::SysRead,
             '[' => Self::LoopStart,
             ']' => Self::LoopEnd,
+            '#' => Self::Debug,
+            _ => return Err(()),
+        };
+        Ok(c)
+    }
+}
+
+impl TryFrom<u8> for Code {
+    type Error = ();
+    fn try_from(from: u8) -> Result<Self, ()> {
+        let c = match from {
+            b'+' => Self::MemInc,
+            b'-' => Self::MemDec,
+            b'>' => Self::PtrInc,
+            b'<' => Self::PtrDec,
+            b'.' => Self::SysWrite,
+            b',' => Self::SysRead,
+            b'[' => Self::LoopStart,
+            b']' => Self::LoopEnd,
+            b'#' => Self::Debug,
             _ => return Err(()),
         };
         Ok(c)
     }
 }
+
+impl FromStr for Code {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Self::try_from(c),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Serializes as the single-character string of the command, e.g. `"+"`
+#[cfg(feature = "serde")]
+impl serde::Serialize for Code {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Code {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::{Error, Unexpected};
+
+        let s = String::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|()| D::Error::invalid_value(Unexpected::Str(&s), &"a Brainfuck command"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_from_bytes() {
+        use Code::*;
+        let codes = b"+-><.,[]#"
+            .iter()
+            .map(|&byte| Code::try_from(byte))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            codes,
+            [
+                Ok(MemInc),
+                Ok(MemDec),
+                Ok(PtrInc),
+                Ok(PtrDec),
+                Ok(SysWrite),
+                Ok(SysRead),
+                Ok(LoopStart),
+                Ok(LoopEnd),
+                Ok(Debug),
+            ]
+        );
+        assert_eq!(Code::try_from(b'x'), Err(()));
+    }
+}
@@ -0,0 +1,122 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{Code, Error};
+
+/// The characters of the commands, in the order of `Code::ALL`
+const BRAINFUCK: &str = "+-><.,[]";
+
+/// Alphuck, which spells the commands with letters
+const ALPHUCK: &str = "eiacjops";
+
+/// The mapping from source characters to codes
+///
+/// `#` is the `Debug` code in every dialect that does not use it for a command.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dialect {
+    /// The eight command characters, in the order of `Code::ALL`
+    chars: String,
+    table: [Option<Code>; 256],
+}
+
+impl Dialect {
+    /// Standard Brainfuck
+    pub fn brainfuck() -> Self {
+        Self::new(BRAINFUCK)
+    }
+
+    /// Uses the eight distinct ASCII characters for `+-><.,[]` respectively.
+    pub fn from_chars(chars: &str) -> Result<Self, Error> {
+        let distinct = chars
+            .bytes()
+            .all(|byte| chars.bytes().filter(|&other| other == byte).count() == 1);
+        if chars.len() != 8 || !chars.is_ascii() || !distinct {
+            return Err(Error::BadDialect {
+                chars: chars.to_string(),
+            });
+        }
+        Ok(Self::new(chars))
+    }
+
+    /// Builds the table of valid command characters
+    fn new(chars: &str) -> Self {
+        const NONE: Option<Code> = None;
+        let mut table = [NONE; 256];
+        table[usize::from(b'#')] = Some(Code::Debug);
+        for (byte, code) in chars.bytes().zip(&Code::ALL) {
+            table[usize::from(byte)] = Some(code.clone());
+        }
+        Self {
+            chars: chars.to_string(),
+            table,
+        }
+    }
+
+    /// The code spelled by the byte, if any
+    pub fn code(&self, byte: u8) -> Option<Code> {
+        self.table[usize::from(byte)].clone()
+    }
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Self::brainfuck()
+    }
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.chars[..] {
+            BRAINFUCK => write!(f, "brainfuck"),
+            ALPHUCK => write!(f, "alphuck"),
+            chars => write!(f, "{}", chars),
+        }
+    }
+}
+
+impl FromStr for Dialect {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "brainfuck" => Ok(Self::brainfuck()),
+            "alphuck" => Self::from_chars(ALPHUCK),
+            chars if chars.chars().count() == 8 => Self::from_chars(chars),
+            _ => Err(crate::unknown(
+                "dialect",
+                s,
+                format!("brainfuck, alphuck or eight characters for `{}`", BRAINFUCK),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_mapping_decodes_to_the_same_codes() {
+        let decode = |src: &str, dialect: &Dialect| {
+            src.bytes()
+                .filter_map(|byte| dialect.code(byte))
+                .collect::<Vec<_>>()
+        };
+        let custom = "PMRLOIAB".parse::<Dialect>().unwrap();
+        assert_eq!(
+            decode("PPA MRPL B RO", &custom),
+            decode("++[ ->+< ] >.", &Dialect::brainfuck())
+        );
+        let alphuck = "alphuck".parse::<Dialect>().unwrap();
+        assert_eq!(
+            decode("eeeej", &alphuck),
+            decode("++++.", &Dialect::default())
+        );
+    }
+
+    #[test]
+    fn mappings_must_be_eight_distinct_characters() {
+        assert!("+-><.,[".parse::<Dialect>().is_err());
+        assert!(Dialect::from_chars("+-><.,[[").is_err());
+        assert!(Dialect::from_chars("+-><.,[]").is_ok());
+    }
+}
@@ -0,0 +1,132 @@
+use std::borrow::Cow;
+use std::error;
+use std::fmt;
+use std::io;
+
+use crate::span::Span;
+use crate::MAX_TAPE_BYTES;
+
+/// The errors of compiling, interpreting and analyzing programs
+#[derive(Debug)]
+pub enum CompileError {
+    /// Reading the input or writing the output failed
+    Io(io::Error),
+    /// A `]` code without a matching `[`
+    UnmatchedClose { span: Span },
+    /// A `[` code that is still open at the end of the program
+    UnmatchedOpen { span: Span },
+    /// A cell width other than 8, 16, 32 and 64 bits
+    BadCellSize(u8),
+    /// A tape without cells
+    EmptyTape,
+    /// A tape larger than `MAX_TAPE_BYTES` or the address space
+    TapeTooLarge { size: u64, cell_bits: u8 },
+    /// A wrapping tape whose size is not a power of two
+    BadTapeSize { size: u64 },
+    /// A tape alignment that is not a power of two
+    BadTapeAlign(u64),
+    /// A dialect that does not spell each command with its own ASCII character
+    BadDialect { chars: String },
+    /// A name that is not one of the accepted values of an option
+    UnknownValue {
+        /// What the name is of, like "target"
+        kind: &'static str,
+        value: String,
+        /// The accepted values
+        expected: Cow<'static, str>,
+    },
+    /// A combination of options that the target, the optimization level or the JIT does not
+    /// support
+    Unsupported(Cow<'static, str>),
+    /// The pointer moved before the first cell at runtime
+    TapeUnderflow,
+    /// The pointer moved past the last cell at runtime
+    TapeOverflow,
+    /// `-` on a cell of 0 with `CellWrap::Error`
+    CellUnderflow,
+    /// `+` on a cell of the largest value with `CellWrap::Error`
+    CellOverflow,
+    /// The program executed more steps than the limit
+    StepLimit(u64),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{}", err),
+            Self::UnmatchedClose { span } => {
+                write!(f, "Found a `]` code without a matching `[` at {}", span)
+            }
+            Self::UnmatchedOpen { span } => write!(
+                f,
+                "Reached end of file with the `[` code at {} unclosed",
+                span
+            ),
+            Self::BadCellSize(bits) => write!(
+                f,
+                "Unsupported cell size {}, expected 8, 16, 32 or 64",
+                bits
+            ),
+            Self::EmptyTape => write!(f, "The tape size must be nonzero"),
+            Self::TapeTooLarge { size, cell_bits } => write!(
+                f,
+                "The tape of {} {}-bit cells is larger than the maximum of {} bytes",
+                size, cell_bits, MAX_TAPE_BYTES
+            ),
+            Self::BadTapeSize { size } => write!(
+                f,
+                "A wrapping tape must have a power-of-two size, not {}",
+                size
+            ),
+            Self::BadTapeAlign(align) => {
+                write!(f, "The tape alignment {} is not a power of two", align)
+            }
+            Self::BadDialect { chars } => write!(
+                f,
+                "A dialect needs eight distinct ASCII characters for `+-><.,[]`, got {:?}",
+                chars
+            ),
+            Self::UnknownValue {
+                kind,
+                value,
+                expected,
+            } => write!(f, "Unknown {} {:?}, expected {}", kind, value, expected),
+            Self::Unsupported(message) => write!(f, "{}", message),
+            Self::TapeUnderflow => write!(f, "The pointer moved past the start of the tape"),
+            Self::TapeOverflow => write!(f, "The pointer moved past the end of the tape"),
+            Self::CellUnderflow => write!(f, "A cell was decremented below 0"),
+            Self::CellOverflow => write!(f, "A cell was incremented past its largest value"),
+            Self::StepLimit(steps) => write!(f, "Exceeded the limit of {} steps", steps),
+        }
+    }
+}
+
+impl error::Error for CompileError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Unwraps the errors that functions returning `io::Result` wrapped with `io::Error::other`.
+impl From<io::Error> for CompileError {
+    fn from(err: io::Error) -> Self {
+        if err
+            .get_ref()
+            .is_some_and(|inner| inner.is::<CompileError>())
+        {
+            let inner = err.into_inner().expect("checked by get_ref");
+            return *inner.downcast().expect("checked by is");
+        }
+        Self::Io(err)
+    }
+}
+
+/// The message of the error, for callers like the command line that report errors as strings
+impl From<CompileError> for Cow<'static, str> {
+    fn from(err: CompileError) -> Self {
+        err.to_string().into()
+    }
+}
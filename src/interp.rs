@@ -0,0 +1,862 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+use crate::ast;
+use crate::parse::CodeParser;
+use crate::span::Span;
+use crate::{CellWrap, Code, CompileOptions, Error};
+
+/// Executes the program directly, reading `,` from `input` and writing `.` to `output`.
+///
+/// Executing more than `max_steps` codes, if given, is an error.
+/// See `Machine` for the semantics.
+pub fn run<R, W>(
+    codes: &[Code],
+    opts: &CompileOptions,
+    max_steps: Option<u64>,
+    input: &mut R,
+    output: &mut W,
+) -> Result<(), Error>
+where
+    R: Read,
+    W: Write,
+{
+    execute(codes, opts, max_steps, input, output, |_, _, _| Ok(()))?;
+    Ok(())
+}
+
+/// Executes the program like `run`, writing a line to `trace` after each code with its index,
+/// the code, the index of the current cell and its value, separated by tabs.
+pub fn trace<R, W, T>(
+    codes: &[Code],
+    opts: &CompileOptions,
+    max_steps: Option<u64>,
+    input: &mut R,
+    output: &mut W,
+    trace: &mut T,
+) -> Result<(), Error>
+where
+    R: Read,
+    W: Write,
+    T: Write,
+{
+    let result = execute(codes, opts, max_steps, input, output, |machine, pc, _| {
+        let ptr = machine.ptr();
+        writeln!(
+            trace,
+            "{}\t{}\t{}\t{}",
+            pc,
+            codes[pc],
+            ptr,
+            machine.tape().get(ptr)
+        )
+        .map_err(Error::Io)
+    });
+    // the end of the trace shows where a runtime error happened
+    trace.flush().map_err(Error::Io)?;
+    result.map(drop)
+}
+
+/// How the output of `.` is shown
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OutputMode {
+    /// The bytes as they are
+    Raw,
+    /// Each byte as a decimal number, separated by spaces
+    Decimal,
+}
+
+impl fmt::Display for OutputMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Raw => "raw",
+                Self::Decimal => "decimal",
+            }
+        )
+    }
+}
+
+impl FromStr for OutputMode {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mode = match s {
+            "raw" => Self::Raw,
+            "decimal" => Self::Decimal,
+            _ => return Err(crate::unknown("output mode", s, "raw or decimal")),
+        };
+        Ok(mode)
+    }
+}
+
+/// Writes the bytes written to it as decimal numbers separated by spaces, for
+/// `OutputMode::Decimal`
+#[derive(Debug)]
+pub struct DecimalWriter<W> {
+    inner: W,
+    first: bool,
+}
+
+impl<W: Write> DecimalWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner, first: true }
+    }
+}
+
+impl<W: Write> Write for DecimalWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            if !self.first {
+                write!(self.inner, " ")?;
+            }
+            write!(self.inner, "{}", byte)?;
+            self.first = false;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Runs the source with the default options on the input and returns everything it wrote,
+/// which is the reference the output of the compiled programs is compared to.
+pub fn run_program(src: &str, mut input: &[u8]) -> Result<Vec<u8>, Error> {
+    let tokens = CodeParser::new(src.bytes()).spanned().collect::<Vec<_>>();
+    // the brackets are checked first, so that errors have their positions in the source
+    ast::build_ast_spanned(tokens.iter().cloned())?;
+    let codes = tokens
+        .into_iter()
+        .map(|code| code.inner)
+        .collect::<Vec<_>>();
+    let mut output = vec![];
+    run(
+        &codes,
+        &CompileOptions::default(),
+        None,
+        &mut input,
+        &mut output,
+    )?;
+    Ok(output)
+}
+
+/// Executes the program like `run`, counting the iterations of each loop.
+pub fn profile<R, W>(
+    codes: &[Code],
+    opts: &CompileOptions,
+    max_steps: Option<u64>,
+    input: &mut R,
+    output: &mut W,
+) -> Result<Profile, Error>
+where
+    R: Read,
+    W: Write,
+{
+    let mut iterations = HashMap::new();
+    let steps = execute(
+        codes,
+        opts,
+        max_steps,
+        input,
+        output,
+        |machine, _, effect| {
+            // entering the body of a loop from either bracket continues right after its `[`
+            let start = machine.pc() - 1;
+            if matches!(effect, Effect::Branch { .. }) && codes[start] == Code::LoopStart {
+                *iterations.entry(start).or_insert(0) += 1;
+            }
+            Ok(())
+        },
+    )?;
+    Ok(Profile { steps, iterations })
+}
+
+/// The execution counts collected by `profile`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// The number of codes executed
+    pub steps: u64,
+    /// The number of times the body of each loop was entered, keyed by the index of its `[`
+    pub iterations: HashMap<usize, u64>,
+}
+
+impl Profile {
+    /// The loops that were entered with their iteration counts, the most iterated first
+    pub fn hottest(&self) -> Vec<(usize, u64)> {
+        let mut loops = self
+            .iterations
+            .iter()
+            .map(|(&start, &count)| (start, count))
+            .collect::<Vec<_>>();
+        loops.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        loops
+    }
+}
+
+/// Runs the machine to the end, calling `inspect` with the index of each code after executing it,
+/// and returns the number of steps
+fn execute<R, W, F>(
+    codes: &[Code],
+    opts: &CompileOptions,
+    max_steps: Option<u64>,
+    input: &mut R,
+    output: &mut W,
+    mut inspect: F,
+) -> Result<u64, Error>
+where
+    R: Read,
+    W: Write,
+    F: FnMut(&Machine, usize, Effect) -> Result<(), Error>,
+{
+    let mut machine = Machine::new(codes, opts)?;
+    while !machine.is_done() {
+        if max_steps.is_some_and(|max| machine.steps() >= max) {
+            flush(output)?;
+            return Err(Error::StepLimit(machine.steps()));
+        }
+        let pc = machine.pc();
+        if let Some(effect) = machine.step(input, output)? {
+            inspect(&machine, pc, effect)?;
+        }
+    }
+    flush(output)?;
+    Ok(machine.steps())
+}
+
+/// A program being executed one code at a time
+///
+/// The pointer starts at `opts.tape_start`, like in the compiled programs.
+///
+/// Cells are stored at the width of `opts.cell_bits`, see `Tape`.
+/// Past the range of a cell, `+` and `-` behave as `opts.cell_wrap` says.
+///
+/// Moving the pointer off the tape wraps around with `opts.tape_wrap` and is an error otherwise.
+///
+/// The program ends at a `.` once the output is a pipe that was closed, like the compiled
+/// programs killed by `SIGPIPE`.
+#[derive(Clone, Debug)]
+pub struct Machine<'a> {
+    codes: &'a [Code],
+    jumps: Vec<usize>,
+    opts: &'a CompileOptions,
+    mask: u64,
+    tape: Tape,
+    ptr: usize,
+    pc: usize,
+    steps: u64,
+}
+
+/// What executing a code did
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Effect {
+    /// The current cell was changed to the value
+    Cell(u64),
+    /// The pointer moved to the cell
+    Pointer(usize),
+    /// The byte was written
+    Output(u8),
+    /// The byte was read, or `None` at the end of input
+    Input(Option<u8>),
+    /// A bracket jumped to its partner or not
+    Branch { taken: bool },
+    /// A `#` code was reached, which does nothing outside the debugger
+    Breakpoint,
+}
+
+impl<'a> Machine<'a> {
+    /// Prepares the program for execution, rejecting unmatched brackets and invalid options.
+    pub fn new(codes: &'a [Code], opts: &'a CompileOptions) -> Result<Self, Error> {
+        opts.check()?;
+        let jumps = jump_table(codes)?;
+        let tape_size = usize::try_from(opts.tape_size).map_err(|_| opts.tape_too_large())?;
+        Ok(Self {
+            codes,
+            jumps,
+            opts,
+            mask: u64::MAX >> (64 - opts.cell_bits),
+            tape: Tape::new(opts.cell_bits, tape_size, u64::from(opts.init_cell)),
+            ptr: opts.start_cell() as usize,
+            pc: 0,
+            steps: 0,
+        })
+    }
+
+    /// Whether the program has ended
+    pub fn is_done(&self) -> bool {
+        self.pc >= self.codes.len()
+    }
+
+    /// The index of the next code to execute
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// The next code to execute, or `None` if the program has ended
+    pub fn next_code(&self) -> Option<&Code> {
+        self.codes.get(self.pc)
+    }
+
+    /// The index of the current cell
+    pub fn ptr(&self) -> usize {
+        self.ptr
+    }
+
+    /// The cells of the tape
+    pub fn tape(&self) -> &Tape {
+        &self.tape
+    }
+
+    /// The number of codes executed so far
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Executes the next code, returning its effect, or `None` if the program has ended.
+    pub fn step<R, W>(&mut self, input: &mut R, output: &mut W) -> Result<Option<Effect>, Error>
+    where
+        R: Read,
+        W: Write,
+    {
+        let code = match self.codes.get(self.pc) {
+            Some(code) => code,
+            None => return Ok(None),
+        };
+        let (ptr, mask) = (self.ptr, self.mask);
+        let effect = match code {
+            Code::MemInc => {
+                let value = self.tape.get(ptr);
+                if value != mask {
+                    self.tape.set(ptr, value + 1);
+                } else {
+                    match self.opts.cell_wrap {
+                        CellWrap::Wrap => self.tape.set(ptr, 0),
+                        CellWrap::Saturate => {}
+                        CellWrap::Error => return Err(Error::CellOverflow),
+                    }
+                }
+                Effect::Cell(self.tape.get(ptr))
+            }
+            Code::MemDec => {
+                let value = self.tape.get(ptr);
+                if value != 0 {
+                    self.tape.set(ptr, value - 1);
+                } else {
+                    match self.opts.cell_wrap {
+                        CellWrap::Wrap => self.tape.set(ptr, mask),
+                        CellWrap::Saturate => {}
+                        CellWrap::Error => return Err(Error::CellUnderflow),
+                    }
+                }
+                Effect::Cell(self.tape.get(ptr))
+            }
+            Code::PtrInc => {
+                if ptr + 1 < self.tape.len() {
+                    self.ptr += 1;
+                } else if self.opts.tape_wrap {
+                    self.ptr = 0;
+                } else {
+                    return Err(Error::TapeOverflow);
+                }
+                Effect::Pointer(self.ptr)
+            }
+            Code::PtrDec => {
+                if ptr > 0 {
+                    self.ptr -= 1;
+                } else if self.opts.tape_wrap {
+                    self.ptr = self.tape.len() - 1;
+                } else {
+                    return Err(Error::TapeUnderflow);
+                }
+                Effect::Pointer(self.ptr)
+            }
+            Code::SysWrite => {
+                let byte = self.tape.get(ptr) as u8;
+                match output.write_all(&[byte]) {
+                    Ok(()) => Effect::Output(byte),
+                    // nothing reads the output anymore, like when piped into `head`
+                    Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {
+                        self.pc = self.codes.len();
+                        return Ok(None);
+                    }
+                    Err(err) => return Err(Error::Io(err)),
+                }
+            }
+            Code::SysRead => {
+                flush(output)?;
+                let mut byte = [0u8];
+                if input.read(&mut byte).map_err(Error::Io)? == 1 {
+                    self.tape.set(ptr, u64::from(byte[0]));
+                    Effect::Input(Some(byte[0]))
+                } else {
+                    if let Some(value) = self.opts.eof.value() {
+                        self.tape.set(ptr, value as u64);
+                    }
+                    Effect::Input(None)
+                }
+            }
+            Code::LoopStart => {
+                let taken = self.tape.get(ptr) == 0;
+                if taken {
+                    self.pc = self.jumps[self.pc];
+                }
+                Effect::Branch { taken }
+            }
+            Code::LoopEnd => {
+                let taken = self.tape.get(ptr) != 0;
+                if taken {
+                    self.pc = self.jumps[self.pc];
+                }
+                Effect::Branch { taken }
+            }
+            Code::Debug => Effect::Breakpoint,
+        };
+        self.pc += 1;
+        self.steps += 1;
+        Ok(Some(effect))
+    }
+}
+
+/// The cells of a tape, each stored in an integer of the cell width
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Tape {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+    U64(Vec<u64>),
+}
+
+impl Tape {
+    /// A tape of `len` cells of `cell_bits` bits, one of 8, 16, 32 and 64, set to the value
+    pub fn new(cell_bits: u8, len: usize, value: u64) -> Self {
+        match cell_bits {
+            8 => Self::U8(vec![value as u8; len]),
+            16 => Self::U16(vec![value as u16; len]),
+            32 => Self::U32(vec![value as u32; len]),
+            _ => Self::U64(vec![value; len]),
+        }
+    }
+
+    /// The number of cells
+    pub fn len(&self) -> usize {
+        match self {
+            Self::U8(cells) => cells.len(),
+            Self::U16(cells) => cells.len(),
+            Self::U32(cells) => cells.len(),
+            Self::U64(cells) => cells.len(),
+        }
+    }
+
+    /// Whether the tape has no cells
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The value of the cell
+    pub fn get(&self, index: usize) -> u64 {
+        match self {
+            Self::U8(cells) => u64::from(cells[index]),
+            Self::U16(cells) => u64::from(cells[index]),
+            Self::U32(cells) => u64::from(cells[index]),
+            Self::U64(cells) => cells[index],
+        }
+    }
+
+    /// Sets the cell to the value truncated to the cell width
+    pub fn set(&mut self, index: usize, value: u64) {
+        match self {
+            Self::U8(cells) => cells[index] = value as u8,
+            Self::U16(cells) => cells[index] = value as u16,
+            Self::U32(cells) => cells[index] = value as u32,
+            Self::U64(cells) => cells[index] = value,
+        }
+    }
+}
+
+/// Maps the index of each bracket to the index of its partner.
+///
+/// The entries of non-bracket codes are unspecified. Unmatched brackets are reported at
+/// `Span::of_index`.
+pub fn jump_table(codes: &[Code]) -> Result<Vec<usize>, Error> {
+    let mut jumps = vec![0; codes.len()];
+    let mut opens = vec![];
+    for (pc, code) in codes.iter().enumerate() {
+        match code {
+            Code::LoopStart => opens.push(pc),
+            Code::LoopEnd => {
+                let start = opens.pop().ok_or(Error::UnmatchedClose {
+                    span: Span::of_index(pc),
+                })?;
+                jumps[start] = pc;
+                jumps[pc] = start;
+            }
+            _ => {}
+        }
+    }
+    if let Some(&pc) = opens.first() {
+        return Err(Error::UnmatchedOpen {
+            span: Span::of_index(pc),
+        });
+    }
+    Ok(jumps)
+}
+
+/// Flushes the output, ignoring a closed pipe, which ends the program at its next `.`
+fn flush<W: Write>(output: &mut W) -> Result<(), Error> {
+    match output.flush() {
+        Err(err) if err.kind() != io::ErrorKind::BrokenPipe => Err(Error::Io(err)),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EofMode;
+
+    fn codes(src: &str) -> Vec<Code> {
+        src.bytes()
+            .filter_map(|byte| Code::try_from(byte).ok())
+            .collect()
+    }
+
+    fn small_tape(cell_bits: u8) -> CompileOptions {
+        CompileOptions {
+            tape_size: 16,
+            cell_bits,
+            ..CompileOptions::default()
+        }
+    }
+
+    #[test]
+    fn multiplication_prints_a() {
+        let mut output = vec![];
+        let program = codes("++++++++[>++++++++<-]>+.");
+        run(
+            &program,
+            &small_tape(8),
+            None,
+            &mut io::empty(),
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(output, b"A");
+    }
+
+    #[test]
+    fn input_is_echoed_and_eof_keeps_the_cell() {
+        let mut output = vec![];
+        run(
+            &codes("+,.,."),
+            &small_tape(8),
+            None,
+            &mut &b"x"[..],
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(output, b"xx");
+    }
+
+    #[test]
+    fn nested_brackets_jump_to_their_partners() {
+        let jumps = jump_table(&codes("[[+[]]-[]]")).unwrap();
+        for (open, close) in [(0, 9), (1, 5), (3, 4), (7, 8)] {
+            assert_eq!(jumps[open], close);
+            assert_eq!(jumps[close], open);
+        }
+        assert!(jump_table(&codes("[[]")).is_err());
+    }
+
+    #[test]
+    fn cells_wrap_at_their_width() {
+        let program = codes(&format!("{}[>+++<[-]]>.", "+".repeat(256)));
+        for (cell_bits, expected) in [(8, 0), (16, 3)] {
+            let mut output = vec![];
+            run(
+                &program,
+                &small_tape(cell_bits),
+                None,
+                &mut io::empty(),
+                &mut output,
+            )
+            .unwrap();
+            assert_eq!(output, [expected]);
+        }
+        let mut output = vec![];
+        assert!(run(
+            &program,
+            &small_tape(12),
+            None,
+            &mut io::empty(),
+            &mut output
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn eof_modes() {
+        let program = codes("+++,.");
+        for (eof, expected) in [
+            (EofMode::Unchanged, 3),
+            (EofMode::Zero, 0),
+            (EofMode::NegOne, 255),
+        ] {
+            let opts = CompileOptions {
+                eof,
+                ..small_tape(8)
+            };
+            let mut output = vec![];
+            run(&program, &opts, None, &mut io::empty(), &mut output).unwrap();
+            assert_eq!(output, [expected]);
+        }
+    }
+
+    #[test]
+    fn moving_below_the_start() {
+        let program = codes(&format!("{}+.", "<".repeat(9)));
+        let wrap = CompileOptions {
+            tape_wrap: true,
+            ..small_tape(8)
+        };
+        let mut output = vec![];
+        run(&program, &wrap, None, &mut io::empty(), &mut output).unwrap();
+        assert_eq!(output, [1]);
+
+        let mut output = vec![];
+        let err = run(
+            &program,
+            &small_tape(8),
+            None,
+            &mut io::empty(),
+            &mut output,
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::TapeUnderflow), "{}", err);
+    }
+
+    #[test]
+    fn infinite_loop_hits_the_step_limit() {
+        let mut output = vec![];
+        let program = codes("+[]");
+        let err = run(
+            &program,
+            &small_tape(8),
+            Some(1000),
+            &mut io::empty(),
+            &mut output,
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::StepLimit(1000)), "{}", err);
+    }
+
+    #[test]
+    fn stepper_reports_each_effect() {
+        let program = codes("+>+");
+        let opts = small_tape(8);
+        let mut machine = Machine::new(&program, &opts).unwrap();
+        let mut output = vec![];
+        let mut step = |machine: &mut Machine| {
+            let effect = machine.step(&mut io::empty(), &mut output).unwrap();
+            (
+                effect,
+                machine.ptr(),
+                (7..10)
+                    .map(|index| machine.tape().get(index))
+                    .collect::<Vec<_>>(),
+            )
+        };
+        assert_eq!(
+            step(&mut machine),
+            (Some(Effect::Cell(1)), 8, vec![0, 1, 0])
+        );
+        assert_eq!(
+            step(&mut machine),
+            (Some(Effect::Pointer(9)), 9, vec![0, 1, 0])
+        );
+        assert_eq!(
+            step(&mut machine),
+            (Some(Effect::Cell(1)), 9, vec![0, 1, 1])
+        );
+        assert!(machine.is_done());
+        assert_eq!(machine.steps(), 3);
+        assert_eq!(step(&mut machine).0, None);
+    }
+
+    #[test]
+    fn hash_is_a_breakpoint() {
+        let program = codes("+#");
+        let opts = small_tape(8);
+        let mut machine = Machine::new(&program, &opts).unwrap();
+        let mut output = vec![];
+        machine.step(&mut io::empty(), &mut output).unwrap();
+        let effect = machine.step(&mut io::empty(), &mut output).unwrap();
+        assert_eq!(effect, Some(Effect::Breakpoint));
+        assert_eq!(machine.tape().get(machine.ptr()), 1);
+    }
+
+    #[test]
+    fn profile_counts_loop_iterations() {
+        // the inner loop runs 3 times for each of the 2 iterations of the outer loop
+        let program = codes("++[>+++[-]<-]");
+        let mut output = vec![];
+        let profile = profile(
+            &program,
+            &small_tape(8),
+            None,
+            &mut io::empty(),
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(profile.hottest(), [(7, 6), (2, 2)]);
+        // `++[`, then `>+++[`, `-]` 3 times and `<-]` for each outer iteration
+        assert_eq!(profile.steps, 3 + 2 * (5 + 3 * 2 + 3));
+    }
+
+    #[test]
+    fn cells_start_with_init_cell() {
+        let opts = CompileOptions {
+            init_cell: 42,
+            ..small_tape(8)
+        };
+        let mut output = vec![];
+        run(&codes(".>."), &opts, None, &mut io::empty(), &mut output).unwrap();
+        assert_eq!(output, [42, 42]);
+    }
+
+    #[test]
+    fn cell_wrap_modes() {
+        let program = codes("-.");
+        let mut outputs = vec![];
+        for &cell_wrap in &[CellWrap::Wrap, CellWrap::Saturate] {
+            let opts = CompileOptions {
+                cell_wrap,
+                ..small_tape(8)
+            };
+            let mut output = vec![];
+            run(&program, &opts, None, &mut io::empty(), &mut output).unwrap();
+            outputs.push(output);
+        }
+        assert_eq!(outputs, [[255], [0]]);
+
+        let opts = CompileOptions {
+            cell_wrap: CellWrap::Error,
+            ..small_tape(8)
+        };
+        let err = run(&program, &opts, None, &mut io::empty(), &mut vec![]).unwrap_err();
+        assert!(matches!(err, Error::CellUnderflow), "{}", err);
+    }
+
+    #[test]
+    fn trace_has_a_line_per_step() {
+        let mut lines = vec![];
+        trace(
+            &codes("++>"),
+            &small_tape(8),
+            None,
+            &mut io::empty(),
+            &mut io::sink(),
+            &mut lines,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(lines).unwrap(),
+            "0\t+\t8\t1\n1\t+\t8\t2\n2\t>\t9\t0\n"
+        );
+    }
+
+    #[test]
+    fn decimal_output_writes_numbers() {
+        let program = codes("+++.++++++++++.");
+        let mut raw = vec![];
+        run(&program, &small_tape(8), None, &mut io::empty(), &mut raw).unwrap();
+        assert_eq!(raw, [3, 13]);
+
+        let mut decimal = DecimalWriter::new(vec![]);
+        run(
+            &program,
+            &small_tape(8),
+            None,
+            &mut io::empty(),
+            &mut decimal,
+        )
+        .unwrap();
+        assert_eq!(decimal.inner, b"3 13");
+        assert_eq!(
+            "decimal".parse::<OutputMode>().unwrap(),
+            OutputMode::Decimal
+        );
+    }
+
+    /// A pipe whose reader went away after the first byte
+    struct ClosedPipe {
+        written: Vec<u8>,
+    }
+
+    impl Write for ClosedPipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if !self.written.is_empty() {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            self.written.push(buf[0]);
+            Ok(1)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn closed_pipe_ends_the_program() {
+        let mut output = ClosedPipe { written: vec![] };
+        // the loop would never end without the pipe closing
+        run(
+            &codes("+[.]"),
+            &small_tape(8),
+            None,
+            &mut io::empty(),
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(output.written, [1]);
+    }
+
+    #[test]
+    fn cells_are_stored_at_their_width() {
+        let program = codes("-");
+        for (cell_bits, expected) in [(8, 0xff), (16, 0xffff), (64, u64::MAX)] {
+            let opts = small_tape(cell_bits);
+            let mut machine = Machine::new(&program, &opts).unwrap();
+            machine.step(&mut io::empty(), &mut io::sink()).unwrap();
+            assert_eq!(machine.tape().get(machine.ptr()), expected);
+        }
+        let opts = small_tape(8);
+        let machine = Machine::new(&program, &opts).unwrap();
+        assert!(matches!(machine.tape(), Tape::U8(cells) if cells.len() == 16));
+    }
+
+    #[test]
+    fn unmatched_brackets_are_reported_at_their_position() {
+        let err = run_program("+\n ]", b"").unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::UnmatchedClose {
+                    span: Span { line: 2, col: 2 }
+                }
+            ),
+            "{}",
+            err
+        );
+        let err = jump_table(&codes("+[")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Reached end of file with the `[` code at line 1, column 2 unclosed"
+        );
+    }
+}
@@ -0,0 +1,215 @@
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::{ptr, slice};
+
+use crate::backend::machine::{self, Runtime, EXIT_OK, EXIT_OVERFLOW, EXIT_UNDERFLOW};
+use crate::{ast, opt, CellWrap, Code, CompileOptions, Error};
+
+/// The signature of the generated function, which is called with the initial cell pointer,
+/// the start and the end of the tape and the I/O context, see `Runtime::Callbacks`
+type Entry = unsafe extern "C" fn(*mut u8, *mut u8, *mut u8, *mut Context) -> u32;
+
+/// Compiles the program to x86-64 machine code in memory and executes it,
+/// with the same semantics as `interp::run`.
+///
+/// The program is optimized at `opts.opt_level` first.
+pub fn run<R, W>(
+    codes: &[Code],
+    opts: &CompileOptions,
+    input: &mut R,
+    output: &mut W,
+) -> Result<(), Error>
+where
+    R: Read,
+    W: Write,
+{
+    opts.check()?;
+    if opts.cell_wrap != CellWrap::Wrap {
+        return Err(crate::unsupported("The JIT only supports wrapping cells"));
+    }
+    let nodes = opt::optimize_with(ast::build_ast(codes.iter().cloned())?, opts);
+    let runtime = Runtime::Callbacks {
+        put: put as *const () as u64,
+        get: get as *const () as u64,
+    };
+    let code = machine::assemble(&nodes, opts, runtime);
+    let tape_bytes = usize::try_from(opts.tape_bytes()).map_err(|_| opts.tape_too_large())?;
+    let mut tape = vec![0u8; tape_bytes];
+
+    let mut context = Context {
+        input,
+        output,
+        opts,
+        error: None,
+    };
+    let exit = {
+        let page = ExecutablePage::new(&code)?;
+        let tape = tape.as_mut_ptr();
+        let start = (opts.start_cell() * opts.cell_bytes()) as usize;
+        // SAFETY: the generated code stays within the tape, or returns before leaving it
+        unsafe { (page.entry())(tape.add(start), tape, tape.add(tape_bytes), &mut context) }
+    };
+    context.output.flush().map_err(Error::Io)?;
+    match exit {
+        EXIT_OK => Ok(()),
+        EXIT_UNDERFLOW => Err(Error::TapeUnderflow),
+        EXIT_OVERFLOW => Err(Error::TapeOverflow),
+        _ => Err(Error::Io(
+            context
+                .error
+                .unwrap_or_else(|| io::Error::other("Unknown I/O error")),
+        )),
+    }
+}
+
+/// The state shared with the I/O callbacks of the generated code
+struct Context<'a> {
+    input: &'a mut dyn Read,
+    output: &'a mut dyn Write,
+    opts: &'a CompileOptions,
+    /// The error that made a callback fail
+    error: Option<io::Error>,
+}
+
+impl Context<'_> {
+    /// Reads the cell at `cell` as a `u64`.
+    ///
+    /// # Safety
+    /// `cell` must point to a cell on the tape.
+    unsafe fn load(&self, cell: *const u8) -> u64 {
+        match self.opts.cell_bits {
+            8 => u64::from(*cell),
+            16 => u64::from(ptr::read_unaligned(cell as *const u16)),
+            32 => u64::from(ptr::read_unaligned(cell as *const u32)),
+            _ => ptr::read_unaligned(cell as *const u64),
+        }
+    }
+
+    /// Stores the value truncated to the cell width at `cell`.
+    ///
+    /// # Safety
+    /// `cell` must point to a cell on the tape.
+    unsafe fn store(&self, cell: *mut u8, value: u64) {
+        match self.opts.cell_bits {
+            8 => *cell = value as u8,
+            16 => ptr::write_unaligned(cell as *mut u16, value as u16),
+            32 => ptr::write_unaligned(cell as *mut u32, value as u32),
+            _ => ptr::write_unaligned(cell as *mut u64, value),
+        }
+    }
+}
+
+/// Writes the low byte of the cell, returning nonzero on failure
+unsafe extern "C" fn put(context: *mut Context, cell: *mut u8) -> u32 {
+    let context = &mut *context;
+    let byte = context.load(cell) as u8;
+    match context.output.write_all(&[byte]) {
+        Ok(()) => 0,
+        Err(err) => {
+            context.error = Some(err);
+            1
+        }
+    }
+}
+
+/// Reads a byte into the cell, returning nonzero on failure
+unsafe extern "C" fn get(context: *mut Context, cell: *mut u8) -> u32 {
+    let context = &mut *context;
+    let mut byte = [0u8];
+    let read = context
+        .output
+        .flush()
+        .and_then(|()| context.input.read(&mut byte));
+    match read {
+        Ok(1) => context.store(cell, u64::from(byte[0])),
+        Ok(_) => {
+            if let Some(value) = context.opts.eof.value() {
+                context.store(cell, value as u64);
+            }
+        }
+        Err(err) => {
+            context.error = Some(err);
+            return 1;
+        }
+    }
+    0
+}
+
+/// An anonymous mapping holding the generated code, unmapped on drop
+struct ExecutablePage {
+    addr: *mut libc::c_void,
+    len: usize,
+}
+
+impl ExecutablePage {
+    fn new(code: &[u8]) -> Result<Self, Error> {
+        let len = code.len();
+        // SAFETY: the mapping is fresh and exactly `len` bytes long
+        unsafe {
+            let addr = libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if addr == libc::MAP_FAILED {
+                return Err(Error::Io(io::Error::last_os_error()));
+            }
+            let page = Self { addr, len };
+            slice::from_raw_parts_mut(addr as *mut u8, len).copy_from_slice(code);
+            if libc::mprotect(addr, len, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                return Err(Error::Io(io::Error::last_os_error()));
+            }
+            Ok(page)
+        }
+    }
+
+    fn entry(&self) -> Entry {
+        // SAFETY: the page holds a function assembled for `Runtime::Callbacks`
+        unsafe { std::mem::transmute::<*mut libc::c_void, Entry>(self.addr) }
+    }
+}
+
+impl Drop for ExecutablePage {
+    fn drop(&mut self) {
+        // SAFETY: the mapping is owned by the page
+        unsafe {
+            libc::munmap(self.addr, self.len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jit(src: &str, opts: &CompileOptions, input: &[u8]) -> Result<Vec<u8>, Error> {
+        let codes = src
+            .bytes()
+            .filter_map(|byte| Code::try_from(byte).ok())
+            .collect::<Vec<_>>();
+        let mut output = vec![];
+        run(&codes, opts, &mut &input[..], &mut output)?;
+        Ok(output)
+    }
+
+    #[test]
+    fn output_is_written() {
+        let opts = CompileOptions::default();
+        assert_eq!(jit("+++.", &opts, b"").unwrap(), [3]);
+    }
+
+    #[test]
+    fn matches_the_interpreter() {
+        let src = "++++++++[>++++++++<-]>+.,+.,.";
+        for opt_level in 0..=2 {
+            let opts = CompileOptions {
+                opt_level,
+                ..CompileOptions::default()
+            };
+            assert_eq!(jit(src, &opts, b"a").unwrap(), b"Abb");
+        }
+    }
+}
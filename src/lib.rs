@@ -0,0 +1,814 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+pub mod ast;
+pub mod backend;
+pub mod code;
+pub mod dialect;
+pub mod error;
+pub mod interp;
+#[cfg(all(target_arch = "x86_64", unix))]
+pub mod jit;
+pub mod opt;
+pub mod parse;
+pub mod span;
+pub mod stats;
+
+pub use backend::Target;
+pub use code::{Code, Program};
+pub use error::CompileError;
+
+pub type Error = CompileError;
+
+/// The largest tape in bytes, the lower half of the 48-bit address space of x86-64
+pub const MAX_TAPE_BYTES: u64 = 1 << 47;
+
+#[derive(Clone, Debug)]
+pub struct CompileOptions {
+    /// The number of cells to allocate in the output program
+    pub tape_size: u64,
+    /// The width of each cell in bits, one of 8, 16, 32 and 64
+    pub cell_bits: u8,
+    /// The effect of `,` at the end of input
+    pub eof: EofMode,
+    /// The effect of `+` and `-` past the range of a cell
+    pub cell_wrap: CellWrap,
+    /// The cell the pointer starts at
+    pub tape_start: TapeStart,
+    /// The value every cell of the tape starts with
+    pub init_cell: u8,
+    /// The alignment of the tape in bytes, a power of two; the default of its section if `None`
+    pub tape_align: Option<u64>,
+    /// Whether pointer moves wrap around the tape, which must then have a power-of-two size;
+    /// otherwise moving off the tape is a runtime error
+    pub tape_wrap: bool,
+    /// The entry symbol of the assembly and WebAssembly targets;
+    /// `main` returns to the C runtime instead of exiting the process
+    pub entry: String,
+    /// The name of a function `void name(cell *tape)` to write for the C and LLVM targets
+    /// instead of a program; it runs on the tape of `tape_size` cells that the caller passes
+    pub function: Option<String>,
+    /// Whether the Linux assembly marks the stack as non-executable with a `.note.GNU-stack`
+    /// section, without which linkers warn and may make it executable
+    pub gnu_stack: bool,
+    /// Whether the x86-64 Linux assembly only uses RIP-relative addresses, so that it can be linked
+    /// as a position-independent executable
+    pub pie: bool,
+    /// The optimization level, see `opt::optimize`
+    pub opt_level: u8,
+    /// Loops with a constant trip count of at most this many iterations are unrolled, see
+    /// `opt::unroll_loops`; 0 unrolls none
+    pub unroll: u32,
+    /// The platform and output format
+    pub target: Target,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self {
+            tape_size: 1048576,
+            cell_bits: 8,
+            eof: EofMode::Unchanged,
+            cell_wrap: CellWrap::Wrap,
+            tape_start: TapeStart::Center,
+            init_cell: 0,
+            tape_align: None,
+            tape_wrap: false,
+            entry: "_start".to_string(),
+            function: None,
+            gnu_stack: true,
+            pie: false,
+            opt_level: 0,
+            unroll: 0,
+            target: Target::X86_64Linux,
+        }
+    }
+}
+
+impl CompileOptions {
+    /// Rejects unsupported option values.
+    pub fn check(&self) -> Result<(), Error> {
+        match self.cell_bits {
+            8 | 16 | 32 | 64 => {}
+            bits => return Err(Error::BadCellSize(bits)),
+        }
+        match self.tape_size.checked_mul(self.cell_bytes()) {
+            Some(bytes) if bytes <= MAX_TAPE_BYTES => {}
+            _ => return Err(self.tape_too_large()),
+        }
+        if self.target == Target::I386Linux {
+            if self.cell_bits > 32 {
+                return Err(unsupported(
+                    "The i386-linux target supports cells of up to 32 bits",
+                ));
+            }
+            if self.tape_bytes() > i32::MAX as u64 {
+                return Err(unsupported(
+                    "The tape is too large for the i386-linux target",
+                ));
+            }
+        }
+        if self.target == Target::Wasm32
+            && backend::wat::message_address(self, backend::TAPE_ERRORS.len()) > u64::from(u32::MAX)
+        {
+            // the tape and the messages after it are addressed with i32
+            return Err(unsupported("The tape is too large for the wasm32 target"));
+        }
+        if self.tape_size == 0 {
+            return Err(Error::EmptyTape);
+        }
+        if self.tape_wrap && !self.tape_size.is_power_of_two() {
+            return Err(Error::BadTapeSize {
+                size: self.tape_size,
+            });
+        }
+        if self.pie && self.target != Target::X86_64Linux {
+            return Err(unsupported(
+                "Position-independent output is only supported for x86_64-linux",
+            ));
+        }
+        if self.cell_wrap != CellWrap::Wrap {
+            if !matches!(self.target, Target::X86_64Linux | Target::X86_64Macos) {
+                return Err(unsupported(format!(
+                    "The {} target only supports wrapping cells",
+                    self.target
+                )));
+            }
+            if self.opt_level != 0 {
+                // the optimizer folds `+` and `-` assuming that they wrap
+                return Err(unsupported(format!(
+                    "--cell-wrap {} needs -O0",
+                    self.cell_wrap
+                )));
+            }
+        }
+        if self.function.is_some() {
+            if !matches!(self.target, Target::C | Target::Llvm) {
+                return Err(unsupported(format!(
+                    "The {} target cannot be written as a function",
+                    self.target
+                )));
+            }
+            if self.init_cell != 0 || self.tape_align.is_some() {
+                return Err(unsupported(
+                    "A function runs on the tape of its caller, which it cannot fill or align",
+                ));
+            }
+        }
+        if self.unroll != 0 && self.opt_level == 0 {
+            return Err(unsupported("Unrolling loops needs -O1 or higher"));
+        }
+        if let Some(align) = self.tape_align {
+            if !align.is_power_of_two() {
+                return Err(Error::BadTapeAlign(align));
+            }
+            match self.target {
+                Target::X86_64LinuxObj | Target::Wasm32 | Target::Rust | Target::Brainfuck => {
+                    return Err(unsupported(format!(
+                        "The {} target does not support aligning the tape",
+                        self.target
+                    )))
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// The error of a tape too large for the options or the address space
+    pub fn tape_too_large(&self) -> Error {
+        Error::TapeTooLarge {
+            size: self.tape_size,
+            cell_bits: self.cell_bits,
+        }
+    }
+
+    /// The width of each cell in bytes
+    pub fn cell_bytes(&self) -> u64 {
+        u64::from(self.cell_bits / 8)
+    }
+
+    /// The size of the tape in bytes, which `check` keeps from overflowing
+    pub fn tape_bytes(&self) -> u64 {
+        self.tape_size * self.cell_bytes()
+    }
+
+    /// Whether the entry is `main`, called by the C runtime, which the program returns from
+    pub fn returns_from_main(&self) -> bool {
+        self.entry == "main"
+    }
+
+    /// The index of the cell the pointer starts at
+    pub fn start_cell(&self) -> u64 {
+        match self.tape_start {
+            TapeStart::Zero => 0,
+            TapeStart::Center => self.tape_size / 2,
+        }
+    }
+}
+
+/// The cell the pointer starts at
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TapeStart {
+    /// The first cell of the tape
+    Zero,
+    /// The middle cell of the tape, rounded down
+    Center,
+}
+
+impl fmt::Display for TapeStart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Zero => "zero",
+                Self::Center => "center",
+            }
+        )
+    }
+}
+
+impl FromStr for TapeStart {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let start = match s {
+            "zero" | "0" => Self::Zero,
+            "center" => Self::Center,
+            _ => return Err(unknown("tape start", s, "zero or center")),
+        };
+        Ok(start)
+    }
+}
+
+/// The effect of `,` when the input has ended or cannot be read
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EofMode {
+    /// Leave the cell unchanged
+    Unchanged,
+    /// Set the cell to 0
+    Zero,
+    /// Set the cell to -1, i.e. all bits set
+    NegOne,
+}
+
+impl EofMode {
+    /// The value stored into the cell, if any
+    pub fn value(self) -> Option<i32> {
+        match self {
+            Self::Unchanged => None,
+            Self::Zero => Some(0),
+            Self::NegOne => Some(-1),
+        }
+    }
+}
+
+impl fmt::Display for EofMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Unchanged => "unchanged",
+                Self::Zero => "zero",
+                Self::NegOne => "neg-one",
+            }
+        )
+    }
+}
+
+impl FromStr for EofMode {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mode = match s {
+            "unchanged" => Self::Unchanged,
+            "zero" | "0" => Self::Zero,
+            "neg-one" | "-1" => Self::NegOne,
+            _ => return Err(unknown("EOF mode", s, "unchanged, zero or neg-one")),
+        };
+        Ok(mode)
+    }
+}
+
+/// The effect of `+` on the largest value of a cell and `-` on 0
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CellWrap {
+    /// Wrap around to 0 or the largest value
+    Wrap,
+    /// Leave the cell unchanged
+    Saturate,
+    /// Exit with a runtime error
+    Error,
+}
+
+impl fmt::Display for CellWrap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Wrap => "wrap",
+                Self::Saturate => "saturate",
+                Self::Error => "error",
+            }
+        )
+    }
+}
+
+impl FromStr for CellWrap {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mode = match s {
+            "wrap" => Self::Wrap,
+            "saturate" => Self::Saturate,
+            "error" => Self::Error,
+            _ => return Err(unknown("cell wrapping mode", s, "wrap, saturate or error")),
+        };
+        Ok(mode)
+    }
+}
+
+/// Compiles the codes for the target selected in `opts`.
+///
+/// The codes are consumed lazily in a single pass and are never collected,
+/// so they can be streamed straight from the source, e.g. with `parse::tokens`.
+/// Only the AST is held in memory, since the optimizer and the backends work on whole loops;
+/// at `-O0`, the x86-64 assembly targets skip it and write each code as it is read.
+pub fn compile_to_writer<I, W>(codes: I, out: &mut W, opts: &CompileOptions) -> io::Result<()>
+where
+    I: IntoIterator<Item = Code>,
+    W: Write,
+{
+    opts.check().map_err(io::Error::other)?;
+    // without optimization, the x86-64 assembly is streamed straight from the codes
+    if opts.opt_level == 0 && matches!(opts.target, Target::X86_64Linux | Target::X86_64Macos) {
+        return backend::x86_64::write_codes(codes, out, opts);
+    }
+    let nodes = ast::build_ast(codes).map_err(io::Error::other)?;
+    let nodes = opt::optimize_with(nodes, opts);
+    write_nodes(&nodes, out, opts)
+}
+
+/// Compiles the codes like `compile_to_writer`, but with the backend instead of the one of the
+/// target in `opts`.
+pub fn compile_with<I, B, W>(
+    codes: I,
+    backend: &mut B,
+    out: &mut W,
+    opts: &CompileOptions,
+) -> io::Result<()>
+where
+    I: IntoIterator<Item = Code>,
+    B: backend::Backend + ?Sized,
+    W: Write,
+{
+    opts.check().map_err(io::Error::other)?;
+    let nodes = ast::build_ast(codes).map_err(io::Error::other)?;
+    let nodes = opt::optimize_with(nodes, opts);
+    backend::write_with(backend, &nodes, out, opts)
+}
+
+/// Writes the optimized nodes for the target selected in `opts`.
+pub fn write_nodes<W: Write>(
+    nodes: &[ast::Node],
+    out: &mut W,
+    opts: &CompileOptions,
+) -> io::Result<()> {
+    match opts.target {
+        Target::X86_64Linux => backend::x86_64::write(nodes, out, opts),
+        Target::X86_64LinuxObj => backend::elf::write(nodes, out, opts),
+        Target::X86_64Macos => backend::x86_64::write_macos(nodes, out, opts),
+        Target::I386Linux => backend::i386::write(nodes, out, opts),
+        Target::Aarch64Linux => backend::aarch64::write(nodes, out, opts),
+        Target::Riscv64Linux => backend::riscv64::write(nodes, out, opts),
+        Target::Wasm32 => backend::wat::write(nodes, out, opts),
+        Target::C => backend::c::write(nodes, out, opts),
+        Target::Llvm => backend::llvm::write(nodes, out, opts),
+        Target::Rust => backend::rust::write(nodes, out, opts),
+        Target::Brainfuck => backend::bf::write(nodes, out, opts),
+    }
+}
+
+/// Rejects the options that `write_annotated` does not support.
+///
+/// Only the x86-64 assembly targets are annotated, and only without optimization, where each
+/// instruction comes from a single code.
+pub fn check_annotated(opts: &CompileOptions) -> Result<(), Error> {
+    if !matches!(opts.target, Target::X86_64Linux | Target::X86_64Macos) {
+        return Err(unsupported(format!(
+            "The {} target cannot be annotated",
+            opts.target
+        )));
+    }
+    if opts.opt_level != 0 {
+        return Err(unsupported("Annotated output needs -O0"));
+    }
+    Ok(())
+}
+
+/// Writes the nodes like `write_nodes`, preceding the instructions of each code with a comment
+/// showing it and its position.
+///
+/// The nodes must be built from the codes of `source` without optimization.
+pub fn write_annotated<W: Write>(
+    nodes: &[ast::Node],
+    source: &[span::Spanned<Code>],
+    out: &mut W,
+    opts: &CompileOptions,
+) -> io::Result<()> {
+    check_annotated(opts).map_err(io::Error::other)?;
+    backend::x86_64::write_annotated(nodes, source, out, opts)
+}
+
+/// Writes the nodes like `write_annotated`, preceding the instructions of the codes of each line
+/// of `text` with the line.
+///
+/// The nodes must be built from the codes of `source`, parsed from `text`, without optimization.
+pub fn write_listing<W: Write>(
+    nodes: &[ast::Node],
+    source: &[span::Spanned<Code>],
+    text: &[u8],
+    out: &mut W,
+    opts: &CompileOptions,
+) -> io::Result<()> {
+    check_annotated(opts).map_err(io::Error::other)?;
+    backend::x86_64::write_listing(nodes, source, text, out, opts)
+}
+
+/// Compiles the codes and returns the output as a string.
+pub fn compile_to_string<I>(codes: I, opts: &CompileOptions) -> Result<String, Error>
+where
+    I: IntoIterator<Item = Code>,
+{
+    let mut out = vec![];
+    compile_to_writer(codes, &mut out, opts)?;
+    let string =
+        String::from_utf8(out).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    Ok(string)
+}
+
+/// The error of an option value that is not one of the `expected` names
+pub(crate) fn unknown(
+    kind: &'static str,
+    value: &str,
+    expected: impl Into<Cow<'static, str>>,
+) -> Error {
+    Error::UnknownValue {
+        kind,
+        value: value.to_string(),
+        expected: expected.into(),
+    }
+}
+
+/// The error of options that are not supported together
+pub(crate) fn unsupported(message: impl Into<Cow<'static, str>>) -> Error {
+    Error::Unsupported(message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile_str(codes: &[Code]) -> String {
+        compile_to_string(
+            codes.iter().cloned(),
+            &CompileOptions {
+                tape_size: 16,
+                ..CompileOptions::default()
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn loop_end_jumps_to_innermost_start() {
+        use Code::*;
+        let asm = compile_str(&[LoopStart, LoopStart, LoopEnd, LoopEnd]);
+        let labels = asm
+            .lines()
+            .filter(|line| line.contains(".start_") || line.contains(".end_"))
+            .map(str::trim)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            labels,
+            [
+                "je .end_1",
+                ".start_1:",
+                "je .end_2",
+                ".start_2:",
+                "jne .start_2",
+                ".end_2:",
+                "jne .start_1",
+                ".end_1:",
+            ]
+        );
+    }
+
+    #[test]
+    fn loop_jumps_test_the_cell() {
+        use Code::*;
+        let asm = compile_str(&[LoopStart, MemDec, LoopStart, LoopEnd, LoopEnd]);
+        let lines = asm.lines().map(str::trim).collect::<Vec<_>>();
+        let mut jumps = 0;
+        for (i, line) in lines.iter().enumerate() {
+            if line.starts_with("je ") || line.starts_with("jne ") {
+                assert_eq!(lines[i - 1], "cmp byte [rax], 0");
+                jumps += 1;
+            }
+        }
+        assert_eq!(jumps, 4);
+    }
+
+    #[test]
+    fn output_writes_to_stdout() {
+        let asm = compile_str(&[Code::SysWrite]);
+        let lines = asm.lines().map(str::trim).collect::<Vec<_>>();
+        let syscall = lines.iter().position(|&line| line == "syscall").unwrap();
+        assert!(lines[..syscall].contains(&"mov rax, 1"));
+        assert!(lines[..syscall].contains(&"mov rdi, 1"));
+        // the pointer is restored after the syscall
+        assert_eq!(lines[syscall + 1], "mov rax, [tape_ptr]");
+    }
+
+    #[test]
+    fn input_reads_from_stdin() {
+        let asm = compile_str(&[Code::SysRead]);
+        let lines = asm.lines().map(str::trim).collect::<Vec<_>>();
+        let syscall = lines.iter().position(|&line| line == "syscall").unwrap();
+        assert!(lines[..syscall].contains(&"mov rax, 0"));
+        assert!(lines[..syscall].contains(&"mov rdi, 0"));
+        assert_eq!(lines[syscall + 1], "mov rax, [tape_ptr]");
+    }
+
+    #[test]
+    fn output_ends_with_exit() {
+        use Code::*;
+        for codes in [
+            &[][..],
+            &[SysRead, SysWrite][..],
+            &[LoopStart, MemDec, LoopEnd][..],
+        ] {
+            let asm = compile_str(codes);
+            let lines = asm.lines().map(str::trim).collect::<Vec<_>>();
+            assert!(lines
+                .windows(3)
+                .any(|window| window == ["mov rax, 60", "xor rdi, rdi", "syscall"]));
+        }
+    }
+
+    #[test]
+    fn folded_adds_are_one_instruction() {
+        let mut codes = vec![Code::MemInc; 10];
+        codes.extend(vec![Code::MemDec; 3]);
+        let opts = CompileOptions {
+            opt_level: 1,
+            ..CompileOptions::default()
+        };
+        let asm = compile_to_string(codes, &opts).unwrap();
+        assert!(asm.contains("add byte [rax], 7"));
+        assert!(!asm.contains("inc byte [rax]"));
+    }
+
+    #[test]
+    fn folded_moves_are_one_instruction() {
+        let codes = vec![Code::PtrInc, Code::PtrInc, Code::PtrInc, Code::PtrDec];
+        let opts = CompileOptions {
+            opt_level: 1,
+            ..CompileOptions::default()
+        };
+        let asm = compile_to_string(codes, &opts).unwrap();
+        assert!(asm.contains("add rax, 2"));
+        assert!(!asm.contains("inc rax"));
+        assert!(!asm.contains("dec rax"));
+    }
+
+    #[test]
+    fn clear_loop_is_a_store() {
+        let codes = vec![Code::SysRead, Code::LoopStart, Code::MemDec, Code::LoopEnd];
+        let opts = CompileOptions {
+            opt_level: 2,
+            ..CompileOptions::default()
+        };
+        let asm = compile_to_string(codes, &opts).unwrap();
+        assert!(asm.contains("mov byte [rax], 0"));
+        assert!(!asm.contains(".start_"));
+    }
+
+    #[test]
+    fn copy_loop_has_no_label() {
+        let codes = [
+            Code::SysRead,
+            Code::LoopStart,
+            Code::MemDec,
+            Code::PtrInc,
+            Code::MemInc,
+            Code::PtrDec,
+            Code::LoopEnd,
+        ];
+        let opts = CompileOptions {
+            opt_level: 2,
+            ..CompileOptions::default()
+        };
+        let asm = compile_to_string(codes.iter().cloned(), &opts).unwrap();
+        assert!(!asm.contains(".start_"));
+        assert!(asm.contains("add byte [rdx], cl"));
+    }
+
+    #[test]
+    fn comment_loop_is_removed() {
+        let codes = [Code::LoopStart, Code::SysWrite, Code::LoopEnd, Code::MemInc];
+        let opts = CompileOptions {
+            opt_level: 2,
+            ..CompileOptions::default()
+        };
+        let asm = compile_to_string(codes.iter().cloned(), &opts).unwrap();
+        let body = asm
+            .lines()
+            .skip_while(|line| *line != "_start:")
+            .skip(2)
+            .take(4)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            body,
+            [
+                "  mov byte [rax], 1",
+                "  mov rax, 60",
+                "  xor rdi, rdi",
+                "  syscall"
+            ]
+        );
+    }
+
+    #[test]
+    fn eof_modes_are_parsed() {
+        assert_eq!("unchanged".parse::<EofMode>().unwrap(), EofMode::Unchanged);
+        assert_eq!("zero".parse::<EofMode>().unwrap(), EofMode::Zero);
+        assert_eq!("neg-one".parse::<EofMode>().unwrap(), EofMode::NegOne);
+        assert!("eof".parse::<EofMode>().is_err());
+    }
+
+    #[test]
+    fn zero_tape_size_is_rejected() {
+        let opts = CompileOptions {
+            tape_size: 0,
+            ..CompileOptions::default()
+        };
+        assert!(opts.check().is_err());
+        assert!(compile_to_string(vec![Code::MemInc], &opts).is_err());
+    }
+
+    #[test]
+    fn clear_then_add_is_one_store() {
+        let codes = [
+            Code::SysRead,
+            Code::LoopStart,
+            Code::MemDec,
+            Code::LoopEnd,
+            Code::MemInc,
+            Code::MemInc,
+            Code::MemInc,
+        ];
+        let opts = CompileOptions {
+            opt_level: 2,
+            ..CompileOptions::default()
+        };
+        let asm = compile_to_string(codes.iter().cloned(), &opts).unwrap();
+        assert!(asm.contains("mov byte [rax], 3"));
+        assert!(!asm.contains("mov byte [rax], 0"));
+        assert!(!asm.contains("add byte [rax]"));
+    }
+
+    #[test]
+    fn tape_align_is_emitted_or_rejected() {
+        let opts = CompileOptions {
+            tape_align: Some(4096),
+            ..CompileOptions::default()
+        };
+        let asm = compile_to_string(vec![Code::MemInc], &opts).unwrap();
+        assert!(asm.contains("  alignb 4096\n  tape RESB"));
+        let filled = CompileOptions {
+            init_cell: 42,
+            ..opts.clone()
+        };
+        let asm = compile_to_string(vec![Code::MemInc], &filled).unwrap();
+        assert!(asm.contains("section .data\n  align 4096\n"));
+        let odd = CompileOptions {
+            tape_align: Some(3),
+            ..opts
+        };
+        assert!(odd.check().is_err());
+    }
+
+    #[test]
+    fn annotated_asm_shows_the_positions() {
+        let source = parse::parse_program("+\n >").unwrap();
+        let nodes = ast::build_ast(source.iter().map(|code| code.inner.clone())).unwrap();
+        let mut out = vec![];
+        write_annotated(&nodes, &source, &mut out, &CompileOptions::default()).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+        assert!(asm.contains("  ; + @ line 1, column 1\n  inc byte [rax]\n"));
+        assert!(asm.contains("  ; > @ line 2, column 2\n"));
+
+        let optimized = CompileOptions {
+            opt_level: 2,
+            ..CompileOptions::default()
+        };
+        assert!(check_annotated(&optimized).is_err());
+    }
+
+    #[test]
+    fn huge_tapes_are_rejected() {
+        let overflow = CompileOptions {
+            tape_size: u64::MAX - 1,
+            cell_bits: 64,
+            ..CompileOptions::default()
+        };
+        let err = overflow.check().unwrap_err();
+        assert!(
+            matches!(
+                err,
+                CompileError::TapeTooLarge {
+                    size,
+                    cell_bits: 64
+                } if size == u64::MAX - 1
+            ),
+            "{}",
+            err
+        );
+        let largest = CompileOptions {
+            tape_size: MAX_TAPE_BYTES / 8,
+            cell_bits: 64,
+            ..CompileOptions::default()
+        };
+        assert!(largest.check().is_ok());
+    }
+
+    #[test]
+    fn level_zero_streams_one_instruction_per_code() {
+        let codes = parse::CodeParser::new("+++[-]".bytes()).collect::<Vec<_>>();
+        let asm = compile_to_string(codes.iter().cloned(), &CompileOptions::default()).unwrap();
+        assert_eq!(asm.matches("  inc byte [rax]\n").count(), 3);
+        assert!(!asm.contains("add byte"));
+
+        // the same as writing the AST
+        let nodes = ast::build_ast(codes).unwrap();
+        let mut out = vec![];
+        backend::x86_64::write(&nodes, &mut out, &CompileOptions::default()).unwrap();
+        assert_eq!(asm, String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn adjacent_clears_are_one_block_store() {
+        let codes = parse::CodeParser::new(",[-]>[-]>[-].".bytes()).collect::<Vec<_>>();
+        let opts = CompileOptions {
+            opt_level: 2,
+            ..CompileOptions::default()
+        };
+        let asm = compile_to_string(codes, &opts).unwrap();
+        assert_eq!(asm.matches("rep stosb").count(), 1);
+        assert!(!asm.contains("], 0\n"));
+    }
+
+    #[test]
+    fn unrolled_loop_has_no_label() {
+        let codes = parse::CodeParser::new("+++[->+<]".bytes()).collect::<Vec<_>>();
+        let opts = CompileOptions {
+            opt_level: 1,
+            unroll: 4,
+            ..CompileOptions::default()
+        };
+        let asm = compile_to_string(codes, &opts).unwrap();
+        assert!(!asm.contains(".start_"));
+    }
+
+    #[test]
+    fn listing_groups_the_instructions_by_line() {
+        let text = b"+\n[-]\n";
+        let source = parse::parse_program(&text[..]).unwrap();
+        let nodes = ast::build_ast(source.iter().map(|code| code.inner.clone())).unwrap();
+        let mut out = vec![];
+        write_listing(&nodes, &source, text, &mut out, &CompileOptions::default()).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+        assert!(asm.contains(
+            ";    2 | [-]\n  cmp byte [rax], 0\n  je .end_1\n.start_1:\n  dec byte [rax]\n"
+        ));
+        assert_eq!(asm.matches("; ").count(), 2);
+    }
+
+    #[test]
+    fn wasm32_tapes_are_addressed_with_i32() {
+        let opts = CompileOptions {
+            target: Target::Wasm32,
+            tape_size: 1 << 32,
+            ..CompileOptions::default()
+        };
+        assert!(matches!(opts.check(), Err(CompileError::Unsupported(_))));
+        let fits = CompileOptions {
+            tape_size: 1 << 31,
+            ..opts
+        };
+        assert!(fits.check().is_ok());
+    }
+}
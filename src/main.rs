@@ -1,145 +1,1261 @@
 use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::env;
+use std::ffi::OsString;
 use std::fs;
-use std::io;
+use std::io::{self, BufRead, Read};
 use std::path::{Path, PathBuf};
+use std::process;
 use std::result::Result as ResultOf;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use structopt::clap::AppSettings;
 use structopt::StructOpt;
 
-type Error = Cow<'static, str>;
-type Result<T = (), E = Error> = ResultOf<T, E>;
+use bfc::ast::Node;
+use bfc::dialect::Dialect;
+use bfc::interp::{DecimalWriter, OutputMode};
+use bfc::span::Span;
+use bfc::{parse, CellWrap, Code, CompileOptions, EofMode, Program, TapeStart, Target};
+
+type Result<T = (), E = Cow<'static, str>> = ResultOf<T, E>;
+
+/// Tapes with fewer cells than this are too small for many programs
+const SMALL_TAPE_SIZE: u64 = 4096;
 
 #[derive(Debug, StructOpt)]
-#[structopt(name = "bfc")]
+#[structopt(name = "bfc", setting = AppSettings::SubcommandsNegateReqs)]
 struct Args {
-    /// Input .bf file
-    #[structopt(parse(from_os_str))]
-    file: PathBuf,
-    /// Output .asm file, default <file> with file extension changed
+    /// Input .bf files, concatenated in order, or `-` to read from standard input
+    #[structopt(parse(from_os_str), required_unless = "list-targets")]
+    files: Vec<PathBuf>,
+    /// The characters of the commands: brainfuck (default), alphuck, or eight characters to use
+    /// for `+-><.,[]` respectively
+    #[structopt(long, default_value = "brainfuck")]
+    dialect: Dialect,
+    /// Reject characters other than commands and whitespace, and programs without commands
+    #[structopt(long)]
+    strict: bool,
+    /// Output file, default <file> with file extension changed, or `-` to write to standard output
     #[structopt(short, long)]
     out: Option<PathBuf>,
-    /// The tape size to allocate in the output program
+    /// Directory to write the output to, created if needed, with the file name of the default
+    /// output
+    #[structopt(long, parse(from_os_str), conflicts_with = "out")]
+    out_dir: Option<PathBuf>,
+    /// The number of cells to allocate in the output program
     #[structopt(long, default_value = "1048576")]
     tape_size: u64,
+    /// The width of each cell in bits: 8, 16, 32 or 64
+    #[structopt(long = "cell-size", default_value = "8")]
+    cell_bits: u8,
+    /// The effect of `,` at the end of input: unchanged (default) keeps the cell, zero sets it to 0,
+    /// neg-one sets it to -1 (255 for 8-bit cells)
+    #[structopt(long, default_value = "unchanged")]
+    eof: EofMode,
+    /// The effect of `+` on the largest value of a cell and `-` on 0: wrap (default) around,
+    /// saturate at the limit or exit with a runtime error. The others need -O0 and an x86-64
+    /// assembly target
+    #[structopt(long, default_value = "wrap")]
+    cell_wrap: CellWrap,
+    /// The cell the pointer starts at: center (default) or zero
+    #[structopt(long, default_value = "center")]
+    tape_start: TapeStart,
+    /// The value every cell starts with instead of 0, for programs that expect a filled tape
+    #[structopt(long, default_value = "0")]
+    init_cell: u8,
+    /// Aligns the tape to a power-of-two number of bytes, e.g. 4096 for a page
+    #[structopt(long)]
+    tape_align: Option<u64>,
+    /// Wrap the pointer around when it moves off either end of the tape, which must then have a
+    /// power-of-two size, instead of exiting with a runtime error
+    #[structopt(long)]
+    tape_wrap: bool,
+    /// Check every pointer move against the ends of the tape and exit with a runtime error when it
+    /// moves off the tape. This is already the default, the flag only rules out --tape-wrap
+    #[structopt(long, conflicts_with = "tape-wrap")]
+    #[allow(dead_code)] // only checked by the argument parser
+    safe: bool,
+    /// The entry symbol of the program, `_start` by default or `start` on macOS; the program
+    /// returns from `main` so that it can be linked with a C compiler. Ignored for the c and
+    /// llvm targets
+    #[structopt(long)]
+    entry: Option<String>,
+    /// Write a function `void <name>(uint8_t *tape)`, with the cell type of --cell-size, that runs
+    /// the program on the tape of --tape-size cells passed by its caller instead of a program.
+    /// Only for the c and llvm targets
+    #[structopt(long, conflicts_with = "assemble")]
+    emit_function: Option<String>,
+    /// Leave out the `.note.GNU-stack` section that marks the stack of the Linux assembly targets
+    /// as non-executable
+    #[structopt(long)]
+    no_gnu_stack: bool,
+    /// Use RIP-relative addresses in the x86_64-linux output, so that it links as a
+    /// position-independent executable like the C compilers of most distributions build by default
+    #[structopt(long)]
+    pie: bool,
+    /// The optimization level: 0 compiles verbatim, 1 folds runs of `+-` and `><`, 2 also
+    /// replaces clear and copy loops with straight-line code and removes dead loops
+    #[structopt(short = "O", long, default_value = "0")]
+    opt_level: u8,
+    /// Unroll the loops that run a constant number of times, at most this many, into straight-line
+    /// code, which needs -O1 or higher
+    #[structopt(long, default_value = "0")]
+    unroll: u32,
+    /// The target: x86_64-linux (NASM), x86_64-linux-obj (an ELF object, built without NASM),
+    /// x86_64-macos (NASM for Mach-O), i386-linux (NASM for 32-bit x86),
+    /// aarch64-linux or riscv64-linux (GNU assembler), wasm32 (WebAssembly text for WASI),
+    /// c (portable C), llvm (LLVM IR), rust (a standalone Rust program) or bf (the optimized
+    /// program as Brainfuck without comments)
+    #[structopt(long, alias = "emit", default_value = "x86_64-linux")]
+    target: Target,
+    /// Precede the assembly of each instruction with a comment showing it and its position,
+    /// which needs -O0 and an x86-64 assembly target
+    #[structopt(long)]
+    comment_asm: bool,
+    /// Also write a listing of the assembly to this file, with each line of the source before the
+    /// instructions of its codes, which needs -O0 and an x86-64 assembly target
+    #[structopt(long, parse(from_os_str))]
+    listing: Option<PathBuf>,
+    /// Print the number of each instruction and the loop depth of the program before compiling it,
+    /// with the instructions and estimated cycles of a run on the --input
+    #[structopt(long)]
+    stats: bool,
+    /// Print the optimized intermediate representation to stderr instead of compiling the program
+    #[structopt(long)]
+    dump_ir: bool,
+    /// Print each instruction with its position to stderr instead of compiling the program
+    #[structopt(long)]
+    dump_tokens: bool,
+    /// Run the program with the built-in interpreter instead of compiling it
+    #[structopt(long)]
+    run: bool,
+    /// Read the input of the program run by --run, --jit, --profile or --debug from this file
+    /// instead of standard input
+    #[structopt(long, parse(from_os_str))]
+    input: Option<PathBuf>,
+    /// Treat everything after the first `!` of the files as the input of the program run by
+    /// --run, --jit, --profile, --debug or --stats when --input is not given, instead of code
+    #[structopt(long)]
+    bang_input: bool,
+    /// With --run, write each executed instruction to this file with its index, the pointer and
+    /// the value of the current cell after it, one per line
+    #[structopt(long, parse(from_os_str), requires = "run")]
+    trace: Option<PathBuf>,
+    /// With --run, write the output of `.` as raw (default) bytes or as decimal numbers separated
+    /// by spaces
+    #[structopt(long, default_value = "raw")]
+    ascii_out: OutputMode,
+    /// Abort the built-in interpreter after executing this many instructions
+    #[structopt(long)]
+    max_steps: Option<u64>,
+    /// Compile the program to machine code in memory and run it, which is only supported on
+    /// x86-64 Unix. The machine code does not count its steps, so --max-steps cannot be used
+    #[structopt(long, conflicts_with = "max-steps")]
+    jit: bool,
+    /// Run the program with the built-in interpreter and print the number of instructions executed
+    /// and the most iterated loops to stderr
+    #[structopt(long)]
+    profile: bool,
+    /// Step through the program with the built-in interpreter, one instruction per Enter;
+    /// type `c` to continue to the end or the next `#` or `q` to quit. Commands are read from the
+    /// terminal
+    #[structopt(long)]
+    debug: bool,
+    /// Also run the assembler and linker (or C compiler, or wat2wasm) to build an executable
+    #[structopt(long)]
+    assemble: bool,
+    /// Print the targets supported by this build and whether it has the JIT, then exit
+    #[structopt(long)]
+    list_targets: bool,
+    /// Keep the compiled source and object file next to the executable with `--assemble`,
+    /// instead of building from a temporary directory
+    #[structopt(long)]
+    keep_asm: bool,
+    /// Log each phase of the compilation and how long it took to stderr
+    #[structopt(short, long)]
+    verbose: bool,
+    /// Print only warnings and errors, without the messages about the output and how to build it
+    #[structopt(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+    /// After building, build again whenever one of the files changes, until stopped with Ctrl-C
+    #[structopt(long)]
+    watch: bool,
+    #[structopt(subcommand)]
+    command: Option<Command>,
 }
 
-mod code;
-use code::Code;
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Rewrite a file in place as standard Brainfuck without comments, read in --dialect
+    Fmt {
+        /// The file to rewrite, or `-` to write standard input to standard output
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+        /// Also run the optimizer at this level, which keeps the output of the program on a
+        /// zeroed tape but not its `#` breakpoints
+        #[structopt(short = "O", long, default_value = "0")]
+        opt_level: u8,
+    },
+}
 
 fn main() -> Result {
     let args = Args::from_args();
-    let code = read_code(&args.file)?;
-    let out_file = args
-        .out
-        .as_ref()
-        .map_or_else(|| Cow::Owned(change_ext(&args.file, "asm")), Cow::Borrowed);
-    compile(code.iter().cloned(), out_file.as_ref(), args.tape_size)
-        .map_err(|err| format!("Error compiling to {}: {}", out_file.display(), err))?;
-
-    println!("Done! Output has been written to {}.", out_file.display());
-    println!("You can compile it by running the following commands:");
-    let obj_file = change_ext(&out_file, "o");
-    println!(
-        "  nasm -f elf64 -o {} {}",
-        change_ext(&out_file, "o").display(),
-        out_file.display()
-    );
-    println!(
-        "  ld -o {} {}",
-        change_ext(&out_file, "exe").display(),
-        obj_file.display()
+    if args.list_targets {
+        for target in &Target::ALL {
+            println!("{} (.{})", target, target.extension());
+        }
+        if cfg!(all(target_arch = "x86_64", unix)) {
+            println!("The JIT is supported");
+        } else {
+            println!("The JIT is not supported");
+        }
+        return Ok(());
+    }
+    if let Some(Command::Fmt { file, opt_level }) = &args.command {
+        return fmt(file, &args.dialect, *opt_level);
+    }
+    if args.watch {
+        return watch(&args);
+    }
+    build(&args)
+}
+
+/// Compiles, runs or analyzes the program as the arguments say
+fn build(args: &Args) -> Result {
+    let start = Instant::now();
+    let (code, embedded) = read_code(&args.files, &args.dialect, args.strict, args.bang_input)?;
+    if args.verbose {
+        eprintln!(
+            "Parsed {} instructions in {:?}",
+            codes(&code, &args.dialect).count(),
+            start.elapsed()
+        );
+    }
+    let opts = CompileOptions {
+        tape_size: args.tape_size,
+        cell_bits: args.cell_bits,
+        eof: args.eof,
+        cell_wrap: args.cell_wrap,
+        tape_start: args.tape_start,
+        init_cell: args.init_cell,
+        tape_align: args.tape_align,
+        tape_wrap: args.tape_wrap,
+        entry: args
+            .entry
+            .clone()
+            .unwrap_or_else(|| args.target.info().entry.to_string()),
+        function: args.emit_function.clone(),
+        gnu_stack: !args.no_gnu_stack,
+        pie: args.pie,
+        opt_level: args.opt_level,
+        unroll: args.unroll,
+        target: args.target,
+    };
+
+    opts.check()?;
+    if args.comment_asm || args.listing.is_some() {
+        bfc::check_annotated(&opts)?;
+    }
+    if opts.tape_size < SMALL_TAPE_SIZE {
+        eprintln!(
+            "Warning: the tape has only {} cells, which many programs exceed",
+            opts.tape_size
+        );
+    }
+    // `#` only prints the tape for debugging, so it does not count as a command
+    if codes(&code, &args.dialect).all(|code| code == Code::Debug) {
+        let message = format!(
+            "{} {} no commands, so the program does nothing",
+            args.files
+                .iter()
+                .map(|file| display_file(file))
+                .collect::<Vec<_>>()
+                .join(", "),
+            if args.files.len() > 1 {
+                "contain"
+            } else {
+                "contains"
+            }
+        );
+        if args.strict {
+            return Err(message.into());
+        }
+        eprintln!("Warning: {}", message);
+    }
+
+    let loops = bfc::stats::infinite_loops(parse::tokens_with(code.iter().cloned(), &args.dialect));
+    for start in loops {
+        eprintln!(
+            "Warning: the loop at {} never changes its cell, so it loops forever once entered",
+            start
+        );
+    }
+
+    if args.stats {
+        let codes = codes(&code, &args.dialect).collect::<Vec<_>>();
+        eprintln!("{}", bfc::stats::program_stats(&codes));
+        print_cost(&codes, args, embedded.as_deref(), &opts)?;
+    }
+
+    if args.dump_tokens {
+        for token in parse::tokens_with(code.iter().cloned(), &args.dialect) {
+            eprintln!("{:?} at {}", token.inner, token.span);
+        }
+        return Ok(());
+    }
+
+    if args.dump_ir {
+        let nodes =
+            bfc::opt::optimize_with(bfc::ast::build_ast(codes(&code, &args.dialect))?, &opts);
+        for node in &nodes {
+            eprintln!("{}", node);
+        }
+        return Ok(());
+    }
+
+    if args.debug {
+        let codes = codes(&code, &args.dialect).collect::<Vec<_>>();
+        return debug(&codes, &opts, &mut input(args, embedded.as_deref())?);
+    }
+
+    if args.jit {
+        let codes = codes(&code, &args.dialect).collect::<Vec<_>>();
+        return jit(&codes, &opts, &mut input(args, embedded.as_deref())?);
+    }
+
+    if args.profile {
+        let codes = parse::tokens_with(code.iter().cloned(), &args.dialect).collect::<Vec<_>>();
+        let profile = bfc::interp::profile(
+            &codes
+                .iter()
+                .map(|code| code.inner.clone())
+                .collect::<Vec<_>>(),
+            &opts,
+            args.max_steps,
+            &mut input(args, embedded.as_deref())?,
+            &mut io::stdout().lock(),
+        )?;
+        eprintln!("Instructions executed: {}", profile.steps);
+        eprintln!("Hottest loops:");
+        for (start, count) in profile.hottest().into_iter().take(PROFILE_LOOPS) {
+            eprintln!(
+                "  {} iterations of the loop at {}",
+                count, codes[start].span
+            );
+        }
+        return Ok(());
+    }
+
+    if args.run {
+        let codes = codes(&code, &args.dialect).collect::<Vec<_>>();
+        let mut stdout = io::stdout().lock();
+        let mut decimal;
+        let mut output: &mut dyn io::Write = match args.ascii_out {
+            OutputMode::Raw => &mut stdout,
+            OutputMode::Decimal => {
+                decimal = DecimalWriter::new(stdout);
+                &mut decimal
+            }
+        };
+        if let Some(file) = &args.trace {
+            let trace = fs::File::create(file)
+                .map_err(|err| format!("Cannot create {}: {}", file.display(), err))?;
+            return Ok(bfc::interp::trace(
+                &codes,
+                &opts,
+                args.max_steps,
+                &mut input(args, embedded.as_deref())?,
+                &mut output,
+                &mut io::BufWriter::new(trace),
+            )?);
+        }
+        return Ok(bfc::interp::run(
+            &codes,
+            &opts,
+            args.max_steps,
+            &mut input(args, embedded.as_deref())?,
+            &mut output,
+        )?);
+    }
+
+    if let Some(file) = &args.listing {
+        use std::io::Write;
+
+        let tokens = parse::tokens_with(code.iter().cloned(), &args.dialect).collect::<Vec<_>>();
+        let nodes = bfc::ast::build_ast(tokens.iter().map(|token| token.inner.clone()))?;
+        let mut out = io::BufWriter::new(
+            fs::File::create(file)
+                .map_err(|err| format!("Cannot create {}: {}", file.display(), err))?,
+        );
+        bfc::write_listing(&nodes, &tokens, &code, &mut out, &opts)
+            .and_then(|()| out.flush())
+            .map_err(|err| format!("Error writing {}: {}", file.display(), err))?;
+    }
+
+    if args.out.as_deref().is_some_and(is_std_stream) {
+        if args.assemble {
+            return Err("Cannot build the output written to standard output".into());
+        }
+        compile(&code, args, &mut io::stdout().lock(), &opts)
+            .map_err(|err| format!("Error compiling to standard output: {}", err))?;
+        if !args.quiet {
+            eprintln!("Done! Output has been written to standard output.");
+        }
+        return Ok(());
+    }
+
+    let out_file = args.out.as_ref().map_or_else(
+        || {
+            // named after the first file
+            let file = if is_std_stream(&args.files[0]) {
+                Path::new("out")
+            } else {
+                &args.files[0]
+            };
+            Cow::Owned(out_path(
+                file,
+                args.out_dir.as_deref(),
+                args.target.extension(),
+            ))
+        },
+        Cow::Borrowed,
     );
+    if let Some(dir) = &args.out_dir {
+        fs::create_dir_all(dir)
+            .map_err(|err| format!("Cannot create the directory {}: {}", dir.display(), err))?;
+    }
+    if let Some(file) = args
+        .files
+        .iter()
+        .find(|file| !is_std_stream(file) && *out_file == **file)
+    {
+        return Err(format!(
+            "Refusing to overwrite the input file {}, choose another with --out",
+            file.display()
+        )
+        .into());
+    }
+    // some targets need no build
+    let assemble = args.assemble
+        && !BuildPaths::new(args.target, &out_file, true)
+            .commands(&opts)
+            .is_empty();
+    // the intermediate files are only removed if the build succeeds or fails, not if it is skipped
+    let keep = !assemble || args.keep_asm;
+    let paths = BuildPaths::new(args.target, &out_file, keep);
+    fs::File::create(&paths.source)
+        .map_err(|err| err.to_string())
+        .and_then(|mut file| compile(&code, args, &mut file, &opts).map_err(|err| err.to_string()))
+        .map_err(|err| format!("Error compiling to {}: {}", paths.source.display(), err))?;
+
+    let built = if assemble {
+        let result = run_commands(&paths.commands(&opts));
+        if !keep {
+            // the source is still wanted if the build was skipped
+            let copied = match result {
+                Ok(false) => fs::copy(&paths.source, out_file.as_ref()).map(drop),
+                _ => Ok(()),
+            };
+            let _ = fs::remove_file(&paths.source);
+            let _ = fs::remove_file(&paths.obj);
+            copied.map_err(|err| format!("Error writing to {}: {}", out_file.display(), err))?;
+        }
+        result?
+    } else {
+        false
+    };
+
+    // the messages go to stderr, so that scripts can read the output from stdout
+    if args.quiet {
+        return Ok(());
+    }
+    if built {
+        if keep {
+            eprintln!("Output has been written to {}.", paths.source.display());
+        }
+        eprintln!("Built {}.", paths.exe.display());
+    } else {
+        let paths = BuildPaths::new(args.target, &out_file, true);
+        eprintln!(
+            "Done! Output has been written to {}.",
+            paths.source.display()
+        );
+        let commands = paths.commands(&opts);
+        if !commands.is_empty() {
+            eprintln!("You can compile it by running the following commands:");
+            for command in commands {
+                eprintln!("  {}", display_command(&command));
+            }
+        }
+    }
+    if args.target == Target::Wasm32 {
+        eprintln!("You can run it with:");
+        eprintln!("  wasmtime {}", paths.exe.display());
+    }
 
     Ok(())
 }
 
-fn change_ext(path: &PathBuf, ext: &str) -> PathBuf {
-    let mut clone = path.clone();
-    clone.set_extension(ext);
-    clone
+/// Builds the program, then builds it again each time one of its files is modified
+///
+/// The files are polled every `WATCH_INTERVAL`, and errors are printed without stopping.
+fn watch(args: &Args) -> Result {
+    if args.files.iter().any(|file| is_std_stream(file)) {
+        return Err("--watch needs the program in files, not standard input".into());
+    }
+    loop {
+        let modified = modified_times(&args.files);
+        match build(args) {
+            Ok(()) if args.quiet => {}
+            Ok(()) => eprintln!("[{}] Built, watching for changes", timestamp()),
+            Err(err) => eprintln!("[{}] Error: {}", timestamp(), err),
+        }
+        while modified_times(&args.files) == modified {
+            thread::sleep(WATCH_INTERVAL);
+        }
+    }
 }
 
-fn read_code(file: &PathBuf) -> Result<Vec<Code>> {
-    use std::convert::TryFrom;
-    use std::io::Read;
+/// The modification time of each file, or `None` if it cannot be read, like while an editor
+/// replaces it
+fn modified_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|file| fs::metadata(file).and_then(|meta| meta.modified()).ok())
+        .collect()
+}
 
-    let mut vec = vec![];
-    for byte in fs::File::open(file)
-        .map_err(|err| format!("Cannot open {}: {}", file.display(), err))?
-        .bytes()
-    {
-        let byte = byte.map_err(|err| format!("Cannot read from {}: {}", file.display(), err))?;
-        if let Ok(code) = Code::try_from(char::from(byte)) {
-            vec.push(code);
+/// The current time of day in UTC, as `hh:mm:ss`
+fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Rewrites the file with only its codes, or the optimized program above -O0
+fn fmt(file: &Path, dialect: &Dialect, opt_level: u8) -> Result {
+    let (code, _) = read_code(&[file.to_path_buf()], dialect, false, false)?;
+    let out = if opt_level == 0 {
+        format!("{}\n", Program(codes(&code, dialect).collect()))
+    } else {
+        let opts = CompileOptions {
+            opt_level,
+            target: Target::Brainfuck,
+            ..CompileOptions::default()
+        };
+        bfc::compile_to_string(codes(&code, dialect), &opts)?
+    };
+    if is_std_stream(file) {
+        print!("{}", out);
+    } else {
+        fs::write(file, out).map_err(|err| format!("Error writing {}: {}", file.display(), err))?;
+    }
+    Ok(())
+}
+
+/// Prints the dynamic cost of the program for `--stats`, unless it reads input and there is no
+/// `--input` or input after a `!` to run it on
+fn print_cost(
+    codes: &[Code],
+    args: &Args,
+    embedded: Option<&[u8]>,
+    opts: &CompileOptions,
+) -> Result {
+    let has_input = args.input.is_some() || embedded.is_some();
+    if !has_input && codes.contains(&Code::SysRead) {
+        eprintln!(
+            "Dynamic cost: not estimated, since the program reads input and --input is not given"
+        );
+        return Ok(());
+    }
+    let max_steps = args.max_steps.or(Some(COST_STEPS));
+    let nodes = bfc::opt::optimize_with(bfc::ast::build_ast(codes.iter().cloned())?, opts);
+    let open_input = || -> Result<Box<dyn Read>> {
+        if has_input {
+            input(args, embedded)
+        } else {
+            Ok(Box::new(io::empty()))
+        }
+    };
+    let cost = || -> Result<_> {
+        let profile =
+            bfc::interp::profile(codes, opts, max_steps, &mut open_input()?, &mut io::sink())?;
+        let cost = bfc::stats::dynamic_cost(&nodes, opts, max_steps, &mut open_input()?)?;
+        Ok((profile.steps, cost))
+    };
+    match cost() {
+        Ok((steps, cost)) => {
+            eprintln!("Instructions executed: {}", steps);
+            eprintln!("{}", cost);
         }
+        Err(err) => eprintln!("Dynamic cost: not estimated, {}", err),
     }
-    Ok(vec)
+    Ok(())
 }
 
-fn compile<I, P>(codes: I, out_file: &P, tape_size: u64) -> io::Result<()>
-where
-    I: IntoIterator<Item = Code>,
-    P: AsRef<Path>,
-{
-    use std::io::Write;
+/// The input of the program run by the driver, from `--input`, the input embedded after a `!`
+/// or standard input
+fn input<'a>(args: &Args, embedded: Option<&'a [u8]>) -> Result<Box<dyn Read + 'a>> {
+    Ok(match (&args.input, embedded) {
+        (Some(file), _) => {
+            Box::new(io::BufReader::new(fs::File::open(file).map_err(|err| {
+                format!("Cannot read from {}: {}", file.display(), err)
+            })?))
+        }
+        (None, Some(embedded)) => Box::new(embedded),
+        (None, None) => Box::new(io::stdin().lock()),
+    })
+}
+
+#[cfg(all(target_arch = "x86_64", unix))]
+fn jit<R: Read>(codes: &[Code], opts: &CompileOptions, input: &mut R) -> Result {
+    Ok(bfc::jit::run(codes, opts, input, &mut io::stdout().lock())?)
+}
+
+#[cfg(not(all(target_arch = "x86_64", unix)))]
+fn jit<R: Read>(_: &[Code], _: &CompileOptions, _: &mut R) -> Result {
+    Err("The JIT is only supported on x86-64 Unix".into())
+}
+
+/// The number of steps after which `--stats` stops estimating the dynamic cost, unless
+/// `--max-steps` is given
+const COST_STEPS: u64 = 100_000_000;
+
+/// How often `--watch` checks the files for changes
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
 
-    let mut out = fs::File::create(out_file)?;
-
-    writeln!(out, "section .bss")?;
-    writeln!(out, "  tape_ptr RESQ 1")?;
-    writeln!(out, "  tape RESB {}", tape_size)?;
-
-    writeln!(out, "section .text")?;
-    writeln!(out, "  global _start")?;
-    writeln!(out, "_start:")?;
-    writeln!(out, "  mov EAX, tape+{}", tape_size / 2)?;
-
-    let mut loop_open = 0usize;
-    let mut loop_close = 0usize;
-    for code in codes {
-        match code {
-            Code::MemInc => writeln!(out, "  inc BYTE [EAX]")?,
-            Code::MemDec => writeln!(out, "  dec BYTE [EAX]")?,
-            Code::PtrInc => writeln!(out, "  inc EAX")?,
-            Code::PtrDec => writeln!(out, "  dec EAX")?,
-            Code::SysWrite => {
-                writeln!(out, "  mov tape_ptr, eax")?;
-                writeln!(out, "  mov eax, [tape_ptr]")?;
-                writeln!(out, "  mov ebx [tape_ptr+4]")?;
-                writeln!(out, "  mov ecx, [tape_ptr+8]")?;
-                writeln!(out, "  mov edx, [tape_ptr+12]")?;
-                writeln!(out, "  mov esi, [tape_ptr+16]")?;
-                writeln!(out, "  mov edi, [tape_ptr+20]")?;
-                writeln!(out, "  int 0x80")?;
-                writeln!(out, "  mov [tape_ptr], eax")?;
-                writeln!(out, "  mov eax, tape_ptr")?;
+/// The number of loops printed by `--profile`
+const PROFILE_LOOPS: usize = 10;
+
+/// The number of cells printed on each side of the current cell by `--debug`
+const DEBUG_WINDOW: usize = 4;
+
+/// Runs the program one code at a time, printing the state to stderr before each code.
+///
+/// The program still reads standard input, so the commands are read from the terminal.
+fn debug<R: Read>(codes: &[Code], opts: &CompileOptions, input: &mut R) -> Result {
+    let mut commands = io::BufReader::new(
+        fs::File::open("/dev/tty").map_err(|err| format!("Cannot open the terminal: {}", err))?,
+    );
+    let mut machine = bfc::interp::Machine::new(codes, opts)?;
+    let mut output = io::stdout().lock();
+    let mut stepping = true;
+    while let Some(code) = machine.next_code() {
+        if stepping {
+            io::Write::flush(&mut output).map_err(|err| err.to_string())?;
+            let ptr = machine.ptr();
+            let tape = machine.tape();
+            let cells = (ptr.saturating_sub(DEBUG_WINDOW)..tape.len().min(ptr + DEBUG_WINDOW + 1))
+                .map(|index| {
+                    if index == ptr {
+                        format!("[{}]", tape.get(index))
+                    } else {
+                        tape.get(index).to_string()
+                    }
+                })
+                .collect::<Vec<_>>();
+            eprint!(
+                "#{} `{}` at {}: {} > ",
+                machine.pc(),
+                code,
+                ptr,
+                cells.join(" ")
+            );
+            let mut line = String::new();
+            commands
+                .read_line(&mut line)
+                .map_err(|err| format!("Cannot read from the terminal: {}", err))?;
+            match line.trim() {
+                "" => {}
+                "c" => stepping = false,
+                "q" => return Ok(()),
+                command => {
+                    eprintln!("Unknown command {:?}, expected Enter, c or q", command);
+                    continue;
+                }
+            }
+        }
+        match machine.step(input, &mut output)? {
+            // `#` stops continuing, so the state is printed before the next code
+            Some(bfc::interp::Effect::Breakpoint) => stepping = true,
+            Some(effect) if stepping => eprintln!("{:?}", effect),
+            _ => {}
+        }
+    }
+    eprintln!("Finished after {} steps", machine.steps());
+    Ok(())
+}
+
+/// The files involved in building an executable from the output
+#[derive(Debug, PartialEq)]
+struct BuildPaths {
+    /// The compiled source
+    source: PathBuf,
+    /// The intermediate object file, if the build has one
+    obj: PathBuf,
+    /// The executable, or the binary module for WebAssembly
+    exe: PathBuf,
+}
+
+impl BuildPaths {
+    /// Places the files next to `out_file`, or the intermediate files in a temporary directory
+    /// if they are not kept.
+    fn new(target: Target, out_file: &Path, keep: bool) -> Self {
+        let exe = change_ext(
+            out_file,
+            if target == Target::Wasm32 {
+                "wasm"
+            } else {
+                "exe"
+            },
+        );
+        let source = if keep {
+            out_file.to_path_buf()
+        } else {
+            let name = out_file.file_name().unwrap_or_else(|| "out".as_ref());
+            let mut temp_name = OsString::from(format!("bfc-{}-", process::id()));
+            temp_name.push(name);
+            env::temp_dir().join(temp_name)
+        };
+        let obj = change_ext(&source, "o");
+        Self { source, obj, exe }
+    }
+
+    /// The commands that build the source into the executable, or into an object to link with the
+    /// caller of the --emit-function
+    fn commands(&self, opts: &CompileOptions) -> Vec<Vec<OsString>> {
+        // the flags followed by the files
+        let argv = |flags: &[&str], files: &[&Path]| {
+            flags
+                .iter()
+                .map(OsString::from)
+                .chain(files.iter().map(|file| file.as_os_str().to_owned()))
+                .collect::<Vec<_>>()
+        };
+        let (source, obj, exe) = (&*self.source, &*self.obj, &*self.exe);
+        // the C runtime calls `main`, but the output is not position-independent without --pie
+        let link = match (opts.returns_from_main(), opts.pie) {
+            (true, true) => argv(&["cc", "-o"], &[exe, obj]),
+            (true, false) => argv(&["cc", "-no-pie", "-o"], &[exe, obj]),
+            (false, true) => argv(&["ld", "-pie", "--no-dynamic-linker", "-o"], &[exe, obj]),
+            (false, false) => argv(&["ld", "-o"], &[exe, obj]),
+        };
+        match opts.target {
+            Target::X86_64Linux => vec![argv(&["nasm", "-f", "elf64", "-o"], &[obj, source]), link],
+            Target::X86_64Macos => {
+                // the output is position-independent and the syscalls need libSystem
+                let link = if opts.returns_from_main() {
+                    argv(&["cc", "-o"], &[exe, obj])
+                } else {
+                    argv(&["ld", "-lSystem", "-e", &opts.entry, "-o"], &[exe, obj])
+                };
+                vec![argv(&["nasm", "-f", "macho64", "-o"], &[obj, source]), link]
             }
-            Code::SysRead => {
-                writeln!(out, "  mov [eax], [[eax]]")?;
+            // the source is the object
+            Target::I386Linux => {
+                let link = if opts.returns_from_main() {
+                    argv(&["cc", "-m32", "-no-pie", "-o"], &[exe, obj])
+                } else {
+                    argv(&["ld", "-m", "elf_i386", "-o"], &[exe, obj])
+                };
+                vec![argv(&["nasm", "-f", "elf32", "-o"], &[obj, source]), link]
             }
-            Code::LoopStart => {
-                loop_open += 1;
-                writeln!(out, "label_{}:", loop_open)?;
+            Target::X86_64LinuxObj => vec![link],
+            Target::C if opts.function.is_some() => vec![argv(&["cc", "-c", "-o"], &[obj, source])],
+            Target::Llvm if opts.function.is_some() => {
+                vec![argv(&["clang", "-O2", "-c", "-o"], &[obj, source])]
             }
-            Code::LoopEnd => {
-                loop_close += 1;
-                if loop_close > loop_open {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        "Compile error: Found a `]` code without a matching `[`",
-                    ))?;
-                }
-                writeln!(out, "  jne label_{}", loop_close)?;
+            Target::C => vec![argv(&["cc", "-o"], &[exe, source])],
+            Target::Llvm => vec![argv(&["clang", "-O2", "-o"], &[exe, source])],
+            Target::Rust => vec![argv(&["rustc", "-O", "-o"], &[exe, source])],
+            Target::Wasm32 => vec![argv(&["wat2wasm", "-o"], &[exe, source])],
+            Target::Aarch64Linux | Target::Riscv64Linux => {
+                vec![argv(&["as", "-o"], &[obj, source]), link]
+            }
+            Target::Brainfuck => vec![],
+        }
+    }
+}
+
+/// Runs the commands in order, returning `false` if a tool is not installed
+fn run_commands(commands: &[Vec<OsString>]) -> Result<bool> {
+    for command in commands {
+        let output = match process::Command::new(&command[0])
+            .args(&command[1..])
+            .output()
+        {
+            Ok(output) => output,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                eprintln!(
+                    "Cannot find {} on PATH, skipping the build",
+                    command[0].to_string_lossy()
+                );
+                return Ok(false);
+            }
+            Err(err) => {
+                return Err(format!("Cannot run {}: {}", display_command(command), err).into())
             }
+        };
+        if !output.status.success() {
+            return Err(format!(
+                "{} failed with {}:\n{}",
+                display_command(command),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            )
+            .into());
         }
     }
+    Ok(true)
+}
+
+fn display_command(command: &[OsString]) -> String {
+    command
+        .iter()
+        .map(|arg| arg.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The default output file of the input file, in the directory if given or next to the input
+fn out_path(file: &Path, dir: Option<&Path>, ext: &str) -> PathBuf {
+    let file = match (dir, file.file_name()) {
+        (Some(dir), Some(name)) => dir.join(name),
+        (Some(dir), None) => dir.join("out"),
+        (None, _) => file.to_path_buf(),
+    };
+    change_ext(&file, ext)
+}
+
+fn change_ext(path: &Path, ext: &str) -> PathBuf {
+    let mut clone = path.to_path_buf();
+    clone.set_extension(ext);
+    clone
+}
+
+/// Whether the path is `-`, which refers to standard input or output
+fn is_std_stream(file: &Path) -> bool {
+    file == Path::new("-")
+}
 
-    if loop_open > loop_close {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Compile error: Reached end of file with {} `[` code(s) unclosed",
-        ))?;
+/// The name of the input file in messages
+fn display_file(file: &Path) -> Cow<'_, str> {
+    if is_std_stream(file) {
+        "standard input".into()
+    } else {
+        file.to_string_lossy()
     }
+}
+
+/// Reads the sources, concatenated in order, and checks that they form a valid program,
+/// and that they only contain commands and whitespace if `strict`.
+///
+/// The sources are separated by a newline, so that the positions of errors can be mapped back
+/// to the file they are in. Brackets may be matched across files. With `bang_input`, everything
+/// after the first `!` is returned separately as the input of the program.
+fn read_code(
+    files: &[PathBuf],
+    dialect: &Dialect,
+    strict: bool,
+    bang_input: bool,
+) -> Result<(Vec<u8>, Option<Vec<u8>>)> {
+    if bang_input && dialect.code(b'!').is_some() {
+        return Err("--bang-input cannot be used with a dialect that has a `!` command".into());
+    }
+    // the name of the file in errors, which is only needed to tell several files apart
+    let name = |file: &Path| {
+        if files.len() > 1 {
+            format!(" in {}", display_file(file))
+        } else {
+            String::new()
+        }
+    };
+    let mut bytes = vec![];
+    // the first line of each file in the concatenation
+    let mut starts = vec![];
+    let mut line = 1u32;
+    let mut embedded: Option<Vec<u8>> = None;
+    for (index, file) in files.iter().enumerate() {
+        let mut src = read_file(file)?;
+        // the files after the one with the `!` are all input
+        if let Some(embedded) = &mut embedded {
+            embedded.extend_from_slice(&src);
+            continue;
+        }
+        if bang_input {
+            if let Some(bang) = src.iter().position(|&byte| byte == b'!') {
+                embedded = Some(src.split_off(bang + 1));
+                src.truncate(bang);
+            }
+        }
+        if strict {
+            parse::check_strict(&src, dialect)
+                .map_err(|err| format!("Compile error: {}{}", err, name(file)))?;
+        }
+        if index > 0 {
+            bytes.push(b'\n');
+            line = line.saturating_add(1);
+        }
+        starts.push(line);
+        let lines = src.iter().filter(|&&byte| byte == b'\n').count();
+        line = line.saturating_add(u32::try_from(lines).unwrap_or(u32::MAX));
+        bytes.extend_from_slice(&src);
+    }
+    parse::check_program_with(&bytes, dialect).map_err(|errors| {
+        errors
+            .iter()
+            .map(|err| {
+                let index = starts.iter().rposition(|&start| start <= err.span.line);
+                let index = index.unwrap_or(0);
+                let err = parse::ParseError {
+                    kind: err.kind,
+                    span: Span {
+                        line: err.span.line - starts[index] + 1,
+                        col: err.span.col,
+                    },
+                };
+                format!("Compile error: {}{}", err, name(&files[index]))
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    })?;
+    Ok((bytes, embedded))
+}
 
+/// Whether the source is gzip data, from its magic number or its `.gz` extension
+#[cfg(feature = "flate2")]
+fn is_gzip(file: &Path, bytes: &[u8]) -> bool {
+    bytes.starts_with(&[0x1f, 0x8b]) || file.extension().is_some_and(|ext| ext == "gz")
+}
+
+/// Reads a source, decompressing it if it is gzip data with the `flate2` feature, and blanking
+/// its `#!` line
+fn read_file(file: &Path) -> Result<Vec<u8>> {
+    let mut bytes = if is_std_stream(file) {
+        let mut bytes = vec![];
+        io::stdin()
+            .lock()
+            .read_to_end(&mut bytes)
+            .map_err(|err| format!("Cannot read from standard input: {}", err))?;
+        bytes
+    } else {
+        fs::read(file).map_err(|err| format!("Cannot read from {}: {}", file.display(), err))?
+    };
+    #[cfg(feature = "flate2")]
+    if is_gzip(file, &bytes) {
+        let mut decompressed = vec![];
+        flate2::read::MultiGzDecoder::new(&bytes[..])
+            .read_to_end(&mut decompressed)
+            .map_err(|err| {
+                format!(
+                    "Cannot read from {}: Invalid gzip data: {}",
+                    display_file(file),
+                    err
+                )
+            })?;
+        bytes = decompressed;
+    }
+    let bom = bytes.len() - parse::strip_bom(&bytes).len();
+    bytes.drain(..bom);
+    // the `#!` line of a script may contain commands in the path of the interpreter;
+    // it is blanked instead of removed so that the positions of errors stay the same
+    if bytes.starts_with(b"#!") {
+        let end = bytes
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .unwrap_or(bytes.len());
+        for byte in &mut bytes[..end] {
+            *byte = b' ';
+        }
+    }
+    Ok(bytes)
+}
+
+/// The codes of a source checked by `read_code`
+fn codes<'a>(src: &'a [u8], dialect: &Dialect) -> impl Iterator<Item = Code> + 'a {
+    parse::CodeParser::with_dialect(src.iter().cloned(), dialect)
+}
+
+fn compile<W: io::Write>(src: &[u8], args: &Args, out: &mut W, opts: &CompileOptions) -> Result {
+    use std::io::Write;
+
+    let mut out = io::BufWriter::new(out);
+    if !args.verbose && !args.comment_asm {
+        bfc::compile_to_writer(codes(src, &args.dialect), &mut out, opts)
+            .map_err(|err| err.to_string())?;
+    } else {
+        // the phases of `compile_to_writer`, timed separately
+        opts.check()?;
+        let start = Instant::now();
+        let nodes = bfc::opt::optimize_with(bfc::ast::build_ast(codes(src, &args.dialect))?, opts);
+        if args.verbose {
+            eprintln!(
+                "Ran the optimizer at -O{} ({} instructions after) in {:?}",
+                opts.opt_level,
+                node_count(&nodes),
+                start.elapsed()
+            );
+        }
+        let start = Instant::now();
+        if args.comment_asm {
+            let source = parse::tokens_with(src.iter().cloned(), &args.dialect).collect::<Vec<_>>();
+            bfc::write_annotated(&nodes, &source, &mut out, opts)
+        } else {
+            bfc::write_nodes(&nodes, &mut out, opts)
+        }
+        .map_err(|err| err.to_string())?;
+        if args.verbose {
+            eprintln!("Emitted {} in {:?}", opts.target, start.elapsed());
+        }
+    }
+    out.flush().map_err(|err| err.to_string())?;
     Ok(())
 }
+
+/// The number of nodes in the program, counting those in loops
+fn node_count(nodes: &[Node]) -> usize {
+    nodes
+        .iter()
+        .map(|node| match node {
+            Node::Loop(body) => 1 + node_count(body),
+            _ => 1,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stray_close_is_reported_at_its_line_and_column() {
+        let file = std::env::temp_dir().join(format!("bfc-test-{}-lines.bf", std::process::id()));
+        fs::write(&file, "+[-]\n ok ]\n").unwrap();
+        let code = read_code(
+            std::slice::from_ref(&file),
+            &Dialect::default(),
+            false,
+            false,
+        );
+        let _ = fs::remove_file(&file);
+        assert_eq!(
+            code.unwrap_err(),
+            "Compile error: Found a `]` code without a matching `[` at line 2, column 5"
+        );
+    }
+
+    #[test]
+    fn asm_is_built_with_nasm_and_ld() {
+        let paths = BuildPaths::new(Target::X86_64Linux, Path::new("dir/prog.asm"), true);
+        let commands = paths
+            .commands(&CompileOptions::default())
+            .iter()
+            .map(|command| display_command(command))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            commands,
+            [
+                "nasm -f elf64 -o dir/prog.o dir/prog.asm",
+                "ld -o dir/prog.exe dir/prog.o"
+            ]
+        );
+    }
+
+    #[test]
+    fn intermediates_are_kept_or_temporary() {
+        let kept = BuildPaths::new(Target::X86_64Linux, Path::new("dir/prog.asm"), true);
+        assert_eq!(
+            kept,
+            BuildPaths {
+                source: PathBuf::from("dir/prog.asm"),
+                obj: PathBuf::from("dir/prog.o"),
+                exe: PathBuf::from("dir/prog.exe"),
+            }
+        );
+
+        let temp = BuildPaths::new(Target::X86_64Linux, Path::new("dir/prog.asm"), false);
+        assert!(temp.source.starts_with(env::temp_dir()));
+        assert!(temp.obj.starts_with(env::temp_dir()));
+        assert_eq!(temp.obj, change_ext(&temp.source, "o"));
+        assert_eq!(temp.exe, Path::new("dir/prog.exe"));
+    }
+
+    #[test]
+    fn main_entry_is_linked_with_cc() {
+        let paths = BuildPaths::new(Target::X86_64Linux, Path::new("prog.asm"), true);
+        let opts = CompileOptions {
+            entry: "main".to_string(),
+            ..CompileOptions::default()
+        };
+        let commands = paths.commands(&opts);
+        assert_eq!(
+            display_command(&commands[1]),
+            "cc -no-pie -o prog.exe prog.o"
+        );
+    }
+
+    #[test]
+    fn shebang_line_is_ignored() {
+        let file = std::env::temp_dir().join(format!("bfc-test-{}-shebang.bf", std::process::id()));
+        fs::write(&file, "#!/bin/bfc[<\n+.").unwrap();
+        let code = read_code(
+            std::slice::from_ref(&file),
+            &Dialect::default(),
+            false,
+            false,
+        )
+        .unwrap()
+        .0;
+        let _ = fs::remove_file(&file);
+        assert_eq!(
+            codes(&code, &Dialect::default()).collect::<Vec<_>>(),
+            [Code::MemInc, Code::SysWrite]
+        );
+    }
+
+    #[test]
+    fn brackets_are_matched_across_files() {
+        let dir = std::env::temp_dir();
+        let open = dir.join(format!("bfc-test-{}-open.bf", std::process::id()));
+        let close = dir.join(format!("bfc-test-{}-close.bf", std::process::id()));
+        fs::write(&open, "+[").unwrap();
+        fs::write(&close, "-]").unwrap();
+        let code = read_code(
+            &[open.clone(), close.clone()],
+            &Dialect::default(),
+            false,
+            false,
+        );
+        let stray = read_code(
+            &[close.clone(), open.clone()],
+            &Dialect::default(),
+            false,
+            false,
+        );
+        let _ = fs::remove_file(&open);
+        let _ = fs::remove_file(&close);
+        assert_eq!(
+            codes(&code.unwrap().0, &Dialect::default()).collect::<Vec<_>>(),
+            [Code::MemInc, Code::LoopStart, Code::MemDec, Code::LoopEnd]
+        );
+        let stray = stray.unwrap_err();
+        assert!(
+            stray.starts_with(&format!(
+                "Compile error: Found a `]` code without a matching `[` at line 1, column 2 in {}",
+                close.display()
+            )),
+            "{}",
+            stray
+        );
+    }
+
+    #[test]
+    fn pie_is_linked_as_pie() {
+        let paths = BuildPaths::new(Target::X86_64Linux, Path::new("prog.asm"), true);
+        let opts = CompileOptions {
+            pie: true,
+            ..CompileOptions::default()
+        };
+        let commands = paths.commands(&opts);
+        assert_eq!(
+            display_command(&commands[1]),
+            "ld -pie --no-dynamic-linker -o prog.exe prog.o"
+        );
+    }
+
+    #[test]
+    fn byte_order_mark_is_removed() {
+        let file = std::env::temp_dir().join(format!("bfc-test-{}-bom.bf", std::process::id()));
+        fs::write(&file, b"\xef\xbb\xbf+.").unwrap();
+        let code = read_code(
+            std::slice::from_ref(&file),
+            &Dialect::default(),
+            true,
+            false,
+        )
+        .unwrap()
+        .0;
+        let _ = fs::remove_file(&file);
+        let spans = parse::tokens(code.iter().cloned())
+            .map(|code| (code.inner, code.span.col))
+            .collect::<Vec<_>>();
+        assert_eq!(spans, [(Code::MemInc, 1), (Code::SysWrite, 2)]);
+    }
+
+    #[test]
+    fn out_dir_keeps_the_file_name() {
+        assert_eq!(
+            out_path(Path::new("foo/bar.bf"), Some(Path::new("build")), "asm"),
+            Path::new("build/bar.asm")
+        );
+        assert_eq!(
+            out_path(Path::new("foo/bar.bf"), None, "asm"),
+            Path::new("foo/bar.asm")
+        );
+    }
+
+    #[test]
+    fn modified_times_change_with_the_file() {
+        let file = std::env::temp_dir().join(format!("bfc-test-{}-watch.bf", std::process::id()));
+        fs::write(&file, "+").unwrap();
+        let files = [file.clone()];
+        let before = modified_times(&files);
+        assert_eq!(before, modified_times(&files));
+        let later = before[0].unwrap() + std::time::Duration::from_secs(10);
+        fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        let after = modified_times(&files);
+        let _ = fs::remove_file(&file);
+        assert_eq!(after, [Some(later)]);
+        assert_eq!(modified_times(&files), [None]);
+    }
+
+    #[cfg(feature = "flate2")]
+    #[test]
+    fn gzip_sources_are_decompressed() {
+        // `+.\n` compressed by gzip
+        let compressed = [
+            31, 139, 8, 0, 0, 0, 0, 0, 2, 3, 211, 214, 227, 2, 0, 33, 144, 139, 32, 3, 0, 0, 0,
+        ];
+        let dir = std::env::temp_dir();
+        let file = dir.join(format!("bfc-test-{}-gzip.bf.gz", std::process::id()));
+        let plain = dir.join(format!("bfc-test-{}-gzip.bf", std::process::id()));
+        fs::write(&file, compressed).unwrap();
+        fs::write(&plain, "+.\n").unwrap();
+        let code = read_code(
+            std::slice::from_ref(&file),
+            &Dialect::default(),
+            false,
+            false,
+        )
+        .unwrap()
+        .0;
+        let expected = read_code(
+            std::slice::from_ref(&plain),
+            &Dialect::default(),
+            false,
+            false,
+        )
+        .unwrap()
+        .0;
+        let _ = fs::remove_file(&file);
+        let _ = fs::remove_file(&plain);
+        assert_eq!(
+            codes(&code, &Dialect::default()).collect::<Vec<_>>(),
+            codes(&expected, &Dialect::default()).collect::<Vec<_>>()
+        );
+        assert_eq!(code, b"+.\n");
+    }
+}
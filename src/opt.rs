@@ -0,0 +1,849 @@
+use std::collections::BTreeMap;
+
+use crate::ast::Node;
+use crate::CompileOptions;
+
+/// Runs the optimization passes enabled at the level, for a tape that starts zeroed.
+///
+/// - 0 leaves the program verbatim
+/// - 1 removes cancelling pairs like `+-` and `<>`,
+///   and folds runs of `+`/`-` and `>`/`<` into single instructions
+/// - 2 also replaces clear loops like `[-]` and copy loops like `[->+<]` with straight-line code,
+///   recognizes scan loops like `[>]`,
+///   folds additions to a cell that was just set like `[-]+++`,
+///   merges clears of adjacent cells like `[-]>[-]>[-]` into one block clear,
+///   evaluates the start of the program up to the first input or loop,
+///   and removes loops that can never be entered
+pub fn optimize(nodes: Vec<Node>, level: u8) -> Vec<Node> {
+    run_passes(nodes, level, true)
+}
+
+/// Runs the optimization passes enabled at `opts.opt_level`, leaving out the evaluation of the
+/// start of the program unless the tape starts zeroed.
+///
+/// Loops are also unrolled up to `opts.unroll` iterations.
+///
+/// On a wrapping tape, nodes whose offsets span the whole tape are left alone by the passes that
+/// tell cells apart by their offsets, since different offsets can then be the same cell.
+pub fn optimize_with(nodes: Vec<Node>, opts: &CompileOptions) -> Vec<Node> {
+    let zeroed = opts.init_cell == 0;
+    let wrap = if opts.tape_wrap {
+        Some(opts.tape_size)
+    } else {
+        None
+    };
+    let mut optimizer = Optimizer::for_tape(opts.opt_level, zeroed, wrap);
+    if opts.unroll != 0 {
+        let max = opts.unroll;
+        optimizer.push(move |nodes| unroll_loops_in(nodes, max, zeroed, wrap));
+    }
+    optimizer.run(nodes)
+}
+
+fn run_passes(nodes: Vec<Node>, level: u8, zeroed: bool) -> Vec<Node> {
+    Optimizer::for_level(level, zeroed).run(nodes)
+}
+
+/// A transformation of the program that keeps its behavior, like the functions of this module
+pub trait Pass {
+    fn run(&self, nodes: Vec<Node>) -> Vec<Node>;
+}
+
+impl<F: Fn(Vec<Node>) -> Vec<Node>> Pass for F {
+    fn run(&self, nodes: Vec<Node>) -> Vec<Node> {
+        self(nodes)
+    }
+}
+
+/// A sequence of passes, run over and over until the program stops changing
+///
+/// Passes can enable each other, e.g. removing a dead loop can leave two `Add` nodes next to each
+/// other. The sequence is run at most `MAX_ROUNDS` times in case passes undo each other.
+#[derive(Default)]
+pub struct Optimizer {
+    passes: Vec<Box<dyn Pass>>,
+}
+
+impl Optimizer {
+    /// The number of times the passes are run at most
+    pub const MAX_ROUNDS: usize = 16;
+
+    /// An optimizer without passes, which leaves the program verbatim
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The passes of the optimization level, see `optimize`.
+    ///
+    /// `fold_constants` is left out unless the tape starts zeroed.
+    pub fn for_level(level: u8, zeroed: bool) -> Self {
+        Self::for_tape(level, zeroed, None)
+    }
+
+    /// The passes of the optimization level, for a tape of `wrap` cells if it wraps
+    fn for_tape(level: u8, zeroed: bool, wrap: Option<u64>) -> Self {
+        let mut optimizer = Self::new();
+        if level >= 1 {
+            optimizer.push(peephole);
+            optimizer.push(fold_adds);
+            optimizer.push(fold_moves);
+        }
+        if level >= 2 {
+            optimizer.push(clear_loops);
+            optimizer.push(scan_loops);
+            optimizer.push(move |nodes| mul_loops_in(nodes, wrap));
+            optimizer.push(fold_sets);
+            optimizer.push(zero_runs);
+            if zeroed {
+                optimizer.push(move |nodes| fold_constants_in(nodes, wrap));
+            }
+            optimizer.push(move |nodes| remove_dead_loops(nodes, zeroed));
+        }
+        optimizer
+    }
+
+    /// Adds the pass after the others.
+    pub fn push<P: Pass + 'static>(&mut self, pass: P) {
+        self.passes.push(Box::new(pass));
+    }
+
+    /// Runs the passes in order until the program stops changing.
+    pub fn run(&self, mut nodes: Vec<Node>) -> Vec<Node> {
+        if self.passes.is_empty() {
+            return nodes;
+        }
+        for _ in 0..Self::MAX_ROUNDS {
+            let previous = nodes.clone();
+            for pass in &self.passes {
+                nodes = pass.run(nodes);
+            }
+            if nodes == previous {
+                break;
+            }
+        }
+        nodes
+    }
+}
+
+/// Removes no-op nodes and adjacent nodes that undo each other, such as `+-` or `><`,
+/// until none are left, so that `>><<` is removed entirely.
+///
+/// Loops are kept even if their body becomes empty, since `[]` loops forever on a nonzero cell.
+pub fn peephole(nodes: Vec<Node>) -> Vec<Node> {
+    let mut output: Vec<Node> = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        let node = match node {
+            Node::Add(0) | Node::Move(0) => continue,
+            Node::Loop(body) => Node::Loop(peephole(body)),
+            node => node,
+        };
+        let cancels = match (output.last(), &node) {
+            (Some(&Node::Add(last)), &Node::Add(delta)) => last.wrapping_add(delta) == 0,
+            // moves do not wrap, so a pair whose sum overflows moves the pointer
+            (Some(&Node::Move(last)), &Node::Move(delta)) => last.checked_add(delta) == Some(0),
+            _ => false,
+        };
+        if cancels {
+            output.pop();
+        } else {
+            output.push(node);
+        }
+    }
+    output
+}
+
+/// Folds runs of `Add` nodes into a single `Add` of their sum.
+///
+/// Runs that cancel out are removed entirely. The sum is not reduced to the cell width here;
+/// the backends do that when lowering.
+pub fn fold_adds(nodes: Vec<Node>) -> Vec<Node> {
+    let mut output = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        match node {
+            Node::Add(delta) => {
+                let sum = match output.last() {
+                    Some(&Node::Add(last)) => {
+                        output.pop();
+                        last.wrapping_add(delta)
+                    }
+                    _ => delta,
+                };
+                if sum != 0 {
+                    output.push(Node::Add(sum));
+                }
+            }
+            Node::Loop(body) => output.push(Node::Loop(fold_adds(body))),
+            node => output.push(node),
+        }
+    }
+    output
+}
+
+/// Folds runs of `Move` nodes into a single `Move` of their sum.
+///
+/// Runs that cancel out are removed entirely, and moves whose sum would overflow are kept apart.
+pub fn fold_moves(nodes: Vec<Node>) -> Vec<Node> {
+    let mut output = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        match node {
+            Node::Move(delta) => {
+                let sum = match output.last() {
+                    Some(&Node::Move(last)) => match last.checked_add(delta) {
+                        Some(sum) => {
+                            output.pop();
+                            sum
+                        }
+                        None => delta,
+                    },
+                    _ => delta,
+                };
+                if sum != 0 {
+                    output.push(Node::Move(sum));
+                }
+            }
+            Node::Loop(body) => output.push(Node::Loop(fold_moves(body))),
+            node => output.push(node),
+        }
+    }
+    output
+}
+
+/// Replaces the clear idioms `[-]` and `[+]` with `Set(0)`.
+///
+/// This should run after `fold_adds`, so that loops like `[--]` are not mistaken for clears.
+pub fn clear_loops(nodes: Vec<Node>) -> Vec<Node> {
+    nodes
+        .into_iter()
+        .map(|node| match node {
+            Node::Loop(body) => match body[..] {
+                [Node::Add(1)] | [Node::Add(-1)] => Node::Set(0),
+                _ => Node::Loop(clear_loops(body)),
+            },
+            node => node,
+        })
+        .collect()
+}
+
+/// Folds an `Add` right after a `Set` into the value of the `Set`, so that `[-]+++` is `Set(3)`.
+///
+/// Like in `fold_adds`, the value is not reduced to the cell width here.
+/// This should run after `clear_loops` and `mul_loops`, which produce the `Set` nodes.
+pub fn fold_sets(nodes: Vec<Node>) -> Vec<Node> {
+    let mut output = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        match (output.last_mut(), node) {
+            (Some(&mut Node::Set(ref mut value)), Node::Add(delta)) => {
+                *value = value.wrapping_add(delta)
+            }
+            (_, Node::Loop(body)) => output.push(Node::Loop(fold_sets(body))),
+            (_, node) => output.push(node),
+        }
+    }
+    output
+}
+
+/// Replaces runs of at least `ZERO_RUN` clears of adjacent cells, like `[-]>[-]>[-]`, with a
+/// `Zero` node followed by the move to the last cell.
+///
+/// This should run after `fold_sets`, so that clears followed by an `Add` are not merged.
+pub fn zero_runs(nodes: Vec<Node>) -> Vec<Node> {
+    let mut output = Vec::with_capacity(nodes.len());
+    let mut clears = 0;
+    for node in nodes {
+        // a run is a `Set(0)` followed by pairs of `Move(1)` and `Set(0)`
+        let extends = match (&node, output.last()) {
+            (Node::Move(1), Some(Node::Set(0))) => clears > 0,
+            (Node::Set(0), Some(Node::Move(1))) => clears > 0,
+            _ => false,
+        };
+        if !extends {
+            end_zero_run(&mut output, &mut clears);
+        }
+        match node {
+            Node::Set(0) => {
+                clears += 1;
+                output.push(node);
+            }
+            Node::Loop(body) => output.push(Node::Loop(zero_runs(body))),
+            node => output.push(node),
+        }
+    }
+    end_zero_run(&mut output, &mut clears);
+    output
+}
+
+/// Replaces the run of clears at the end of the output with a `Zero` if it is long enough
+fn end_zero_run(output: &mut Vec<Node>, clears: &mut i32) {
+    // a trailing `Move(1)` is not part of the run
+    let trailing = matches!(output.last(), Some(Node::Move(1))) as usize;
+    if *clears >= ZERO_RUN {
+        let end = output.len() - trailing;
+        output.truncate(end - (2 * *clears as usize - 1));
+        output.extend([Node::Zero(*clears), Node::Move(*clears - 1)]);
+        if trailing == 1 {
+            output.push(Node::Move(1));
+        }
+    }
+    *clears = 0;
+}
+
+/// The number of clears `zero_runs` merges at least
+pub const ZERO_RUN: i32 = 3;
+
+/// Replaces loops that run a constant number of times, at most `max`, with copies of their body.
+///
+/// The current cell must be known before the loop, from a `Set` or, with `zeroed`, the start of
+/// the tape, and the body must return to it and subtract the same amount from it in each
+/// iteration without any other change to it. The known value must be below 256, so that it does
+/// not depend on the cell width, and divisible by the amount, so that the loop does not wrap.
+pub fn unroll_loops(nodes: Vec<Node>, max: u32, zeroed: bool) -> Vec<Node> {
+    unroll_loops_in(nodes, max, zeroed, None)
+}
+
+fn unroll_loops_in(nodes: Vec<Node>, max: u32, zeroed: bool, wrap: Option<u64>) -> Vec<Node> {
+    let mut output = Vec::with_capacity(nodes.len());
+    let mut value = if zeroed { Some(0) } else { None };
+    for node in nodes {
+        value = match node {
+            Node::Add(delta) => value.and_then(|value: i32| value.checked_add(delta)),
+            Node::Set(value) => Some(value),
+            Node::MulAdd { offset: 0, .. } | Node::Move(_) | Node::Input => None,
+            Node::MulAdd { .. } | Node::Output => value,
+            Node::Zero(_) | Node::Scan(_) => Some(0),
+            Node::Loop(body) => {
+                let trips = value
+                    .zip(loop_decrement(&body, wrap))
+                    .and_then(|(value, step)| {
+                        let trips = value / step;
+                        (0 < value && value < 256 && value % step == 0 && trips as u32 <= max)
+                            .then_some(trips)
+                    });
+                match trips {
+                    Some(trips) => {
+                        let body = unroll_loops_in(body, max, false, wrap);
+                        for _ in 0..trips {
+                            output.extend(body.iter().cloned());
+                        }
+                    }
+                    None => output.push(Node::Loop(unroll_loops_in(body, max, false, wrap))),
+                }
+                // a loop ends on a zero cell
+                value = Some(0);
+                continue;
+            }
+        };
+        output.push(node);
+    }
+    output
+}
+
+/// The amount the body of a loop subtracts from the current cell, if it returns to the cell and
+/// only changes it by a positive total of `Add` nodes
+fn loop_decrement(body: &[Node], wrap: Option<u64>) -> Option<i32> {
+    let (mut offset, mut delta) = (0i32, 0i32);
+    let mut offsets = Offsets::default();
+    for node in body {
+        match *node {
+            Node::Add(add) if offset == 0 => delta = delta.checked_add(add)?,
+            Node::Move(step) => {
+                offset = offset.checked_add(step)?;
+                offsets.reach(offset, wrap)?;
+            }
+            Node::MulAdd { offset: target, .. } if offset.checked_add(target)? == 0 => return None,
+            Node::Set(_) | Node::Input if offset == 0 => return None,
+            Node::Zero(len) if offset <= 0 && 0 < offset.checked_add(len)? => return None,
+            Node::Zero(len) => offsets.reach(offset.checked_add(len - 1)?, wrap)?,
+            Node::MulAdd { offset: target, .. } => {
+                offsets.reach(offset.checked_add(target)?, wrap)?
+            }
+            Node::Scan(_) | Node::Loop(_) => return None,
+            _ => {}
+        }
+    }
+    (offset == 0 && delta < 0).then(|| -delta)
+}
+
+/// Replaces loops that only move the pointer, such as `[>]` or `[<<]`, with `Scan` nodes.
+///
+/// This should run after `fold_moves`.
+pub fn scan_loops(nodes: Vec<Node>) -> Vec<Node> {
+    nodes
+        .into_iter()
+        .map(|node| match node {
+            Node::Loop(body) => match body[..] {
+                [Node::Move(step)] => Node::Scan(step),
+                _ => Node::Loop(scan_loops(body)),
+            },
+            node => node,
+        })
+        .collect()
+}
+
+/// Replaces balanced loops that decrement the current cell by one per iteration, such as
+/// `[->+<]` or `[->++>+<<]`, with `MulAdd` nodes followed by `Set(0)`.
+///
+/// This should run after `fold_adds` and `fold_moves`.
+pub fn mul_loops(nodes: Vec<Node>) -> Vec<Node> {
+    mul_loops_in(nodes, None)
+}
+
+fn mul_loops_in(nodes: Vec<Node>, wrap: Option<u64>) -> Vec<Node> {
+    let mut output = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        match node {
+            Node::Loop(body) => match mul_loop_targets(&body, wrap) {
+                Some(targets) => {
+                    output.extend(
+                        targets
+                            .into_iter()
+                            .map(|(offset, factor)| Node::MulAdd { offset, factor }),
+                    );
+                    output.push(Node::Set(0));
+                }
+                None => output.push(Node::Loop(mul_loops_in(body, wrap))),
+            },
+            node => output.push(node),
+        }
+    }
+    output
+}
+
+fn mul_loop_targets(body: &[Node], wrap: Option<u64>) -> Option<Vec<(i32, i32)>> {
+    let mut offset: i32 = 0;
+    let mut offsets = Offsets::default();
+    let mut targets: Vec<(i32, i32)> = vec![];
+    for node in body {
+        match *node {
+            // loops that overflow the offsets or factors are left alone
+            Node::Move(delta) => {
+                offset = offset.checked_add(delta)?;
+                offsets.reach(offset, wrap)?;
+            }
+            Node::Add(delta) => match targets.iter_mut().find(|(target, _)| *target == offset) {
+                Some((_, factor)) => *factor = factor.checked_add(delta)?,
+                None => targets.push((offset, delta)),
+            },
+            _ => return None,
+        }
+    }
+    if offset != 0 {
+        return None;
+    }
+
+    // the adds to each offset are merged, so the source has at most one
+    let source = targets
+        .iter()
+        .find(|&&(target, _)| target == 0)
+        .map_or(0, |&(_, factor)| factor);
+    if source != -1 {
+        return None;
+    }
+    targets.retain(|&(target, factor)| target != 0 && factor != 0);
+    Some(targets)
+}
+
+/// Removes loops that can never be entered because the current cell is known to be zero,
+/// such as comment loops at the start of the program or loops right after another loop.
+pub fn dead_loops(nodes: Vec<Node>) -> Vec<Node> {
+    remove_dead_loops(nodes, true)
+}
+
+fn remove_dead_loops(nodes: Vec<Node>, mut zero: bool) -> Vec<Node> {
+    let mut output = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        let node = match node {
+            Node::Loop(_) | Node::Scan(_) if zero => continue,
+            // the loop is only entered if the current cell is nonzero
+            Node::Loop(body) => Node::Loop(remove_dead_loops(body, false)),
+            node => node,
+        };
+        zero = matches!(
+            node,
+            Node::Loop(_) | Node::Scan(_) | Node::Set(0) | Node::Zero(_)
+        );
+        output.push(node);
+    }
+    output
+}
+
+/// Evaluates the start of the program, where the whole tape is known to be zero,
+/// and replaces it with `Set` nodes for the final values and the output.
+///
+/// Evaluation stops at the first input, loop over a possibly nonzero cell or arithmetic overflow,
+/// and the rest of the program is kept as it is.
+/// Loops over a zero cell are skipped. Values are only known to be zero if they are exactly zero,
+/// since the cell width is not known here.
+///
+/// This should run after `clear_loops` and `mul_loops`, so that their results can be evaluated.
+pub fn fold_constants(nodes: Vec<Node>) -> Vec<Node> {
+    fold_constants_in(nodes, None)
+}
+
+fn fold_constants_in(nodes: Vec<Node>, wrap: Option<u64>) -> Vec<Node> {
+    // the values of the cells by their offset from the start, and those written to the tape so far
+    let mut cells = BTreeMap::<i32, i32>::new();
+    let mut written = BTreeMap::<i32, i32>::new();
+    // the offsets of the pointer and of the cell the output code points to
+    let (mut ptr, mut written_ptr) = (0i32, 0i32);
+    let mut offsets = Offsets::default();
+    let mut output = vec![];
+
+    let mut nodes = nodes.into_iter().peekable();
+    while let Some(node) = nodes.peek() {
+        let value = cells.get(&ptr).cloned().unwrap_or(0);
+        let known = match *node {
+            Node::Add(delta) => value.checked_add(delta).map(|sum| {
+                cells.insert(ptr, sum);
+            }),
+            Node::Move(delta) => ptr
+                .checked_add(delta)
+                .and_then(|moved| offsets.reach(moved, wrap).map(|()| ptr = moved)),
+            Node::Set(value) => {
+                cells.insert(ptr, value);
+                Some(())
+            }
+            Node::Zero(len) => ptr
+                .checked_add(len - 1)
+                .filter(|&last| offsets.reach(last, wrap).is_some())
+                .map(|last| {
+                    for offset in ptr..=last {
+                        cells.insert(offset, 0);
+                    }
+                }),
+            Node::MulAdd { offset, factor } => ptr
+                .checked_add(offset)
+                .filter(|&target| offsets.reach(target, wrap).is_some())
+                .and_then(|target| {
+                    let old = cells.get(&target).cloned().unwrap_or(0);
+                    Some((target, old.checked_add(value.checked_mul(factor)?)?))
+                })
+                .map(|(target, sum)| {
+                    cells.insert(target, sum);
+                }),
+            Node::Output => {
+                write_cell(&mut output, &mut written, &mut written_ptr, ptr, value);
+                // the cell may already have the value without the pointer being there
+                move_to(&mut output, &mut written_ptr, ptr);
+                output.push(Node::Output);
+                Some(())
+            }
+            Node::Loop(_) | Node::Scan(_) if value == 0 => Some(()),
+            Node::Loop(_) | Node::Scan(_) | Node::Input => None,
+        };
+        if known.is_none() {
+            break;
+        }
+        nodes.next();
+    }
+
+    for (&offset, &value) in &cells {
+        write_cell(&mut output, &mut written, &mut written_ptr, offset, value);
+    }
+    move_to(&mut output, &mut written_ptr, ptr);
+    output.extend(nodes);
+    output
+}
+
+/// The lowest and highest offsets reached so far, which start at the current cell
+#[derive(Default)]
+struct Offsets {
+    min: i32,
+    max: i32,
+}
+
+impl Offsets {
+    /// Extends the span to the offset, unless it would then cover all `wrap` cells of a wrapping
+    /// tape, where the offsets no longer tell the cells apart.
+    fn reach(&mut self, offset: i32, wrap: Option<u64>) -> Option<()> {
+        let (min, max) = (self.min.min(offset), self.max.max(offset));
+        if let Some(size) = wrap {
+            if (i64::from(max) - i64::from(min)) as u64 >= size {
+                return None;
+            }
+        }
+        self.min = min;
+        self.max = max;
+        Some(())
+    }
+}
+
+/// Moves to the cell and sets it to the value, unless it already has the value on the tape
+fn write_cell(
+    output: &mut Vec<Node>,
+    written: &mut BTreeMap<i32, i32>,
+    written_ptr: &mut i32,
+    offset: i32,
+    value: i32,
+) {
+    if written.get(&offset).cloned().unwrap_or(0) == value {
+        return;
+    }
+    move_to(output, written_ptr, offset);
+    output.push(Node::Set(value));
+    written.insert(offset, value);
+}
+
+/// Moves the pointer of the output from `written_ptr` to the cell
+fn move_to(output: &mut Vec<Node>, written_ptr: &mut i32, offset: i32) {
+    if offset != *written_ptr {
+        output.push(Node::Move(offset - *written_ptr));
+        *written_ptr = offset;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_of_adds_are_folded() {
+        let mut nodes = vec![Node::Add(1); 10];
+        nodes.extend(vec![Node::Add(-1); 3]);
+        assert_eq!(fold_adds(nodes), vec![Node::Add(7)]);
+        assert_eq!(fold_adds(vec![Node::Add(1), Node::Add(-1)]), vec![]);
+    }
+
+    #[test]
+    fn runs_of_moves_are_folded() {
+        let nodes = vec![Node::Move(1), Node::Move(1), Node::Move(1), Node::Move(-1)];
+        assert_eq!(fold_moves(nodes), vec![Node::Move(2)]);
+        assert_eq!(fold_moves(vec![Node::Move(1), Node::Move(-1)]), vec![]);
+    }
+
+    #[test]
+    fn single_step_loops_are_clears() {
+        assert_eq!(
+            clear_loops(vec![Node::Loop(vec![Node::Add(-1)])]),
+            vec![Node::Set(0)]
+        );
+        assert_eq!(
+            clear_loops(vec![Node::Loop(vec![Node::Add(1)])]),
+            vec![Node::Set(0)]
+        );
+        let double = vec![Node::Loop(vec![Node::Add(-2)])];
+        assert_eq!(clear_loops(double.clone()), double);
+        let transfer = vec![Node::Loop(vec![
+            Node::Add(-1),
+            Node::Move(1),
+            Node::Add(1),
+            Node::Move(-1),
+        ])];
+        assert_eq!(clear_loops(transfer.clone()), transfer);
+    }
+
+    #[test]
+    fn copy_loops_become_mul_adds() {
+        let body = |factor| {
+            vec![
+                Node::Add(-1),
+                Node::Move(1),
+                Node::Add(factor),
+                Node::Move(-1),
+            ]
+        };
+        assert_eq!(
+            mul_loops(vec![Node::Loop(body(1))]),
+            vec![
+                Node::MulAdd {
+                    offset: 1,
+                    factor: 1
+                },
+                Node::Set(0)
+            ]
+        );
+        assert_eq!(
+            mul_loops(vec![Node::Loop(body(2))]),
+            vec![
+                Node::MulAdd {
+                    offset: 1,
+                    factor: 2
+                },
+                Node::Set(0)
+            ]
+        );
+        let unbalanced = vec![Node::Loop(vec![Node::Add(-1), Node::Move(1)])];
+        assert_eq!(mul_loops(unbalanced.clone()), unbalanced);
+    }
+
+    #[test]
+    fn loops_on_a_zero_cell_are_removed() {
+        let nodes = vec![
+            Node::Loop(vec![Node::Output]),
+            Node::Add(1),
+            Node::Loop(vec![Node::Input]),
+            Node::Loop(vec![Node::Output]),
+        ];
+        assert_eq!(
+            dead_loops(nodes),
+            vec![Node::Add(1), Node::Loop(vec![Node::Input])]
+        );
+    }
+
+    #[test]
+    fn level_zero_is_verbatim() {
+        let nodes = vec![
+            Node::Input,
+            Node::Add(1),
+            Node::Add(1),
+            Node::Loop(vec![Node::Add(-1)]),
+        ];
+        assert_eq!(optimize(nodes.clone(), 0), nodes);
+        assert_eq!(
+            optimize(nodes, 2),
+            vec![Node::Input, Node::Add(2), Node::Set(0)]
+        );
+    }
+
+    #[test]
+    fn inverse_pairs_are_removed() {
+        assert_eq!(peephole(vec![Node::Add(1), Node::Add(-1)]), vec![]);
+        let moves = vec![Node::Move(1), Node::Move(1), Node::Move(-1), Node::Move(-1)];
+        assert_eq!(peephole(moves), vec![]);
+        assert_eq!(
+            peephole(vec![Node::Add(0), Node::Output, Node::Move(0)]),
+            vec![Node::Output]
+        );
+    }
+
+    #[test]
+    fn overflowing_move_pairs_are_kept() {
+        let moves = vec![Node::Move(i32::MIN), Node::Move(i32::MIN)];
+        assert_eq!(peephole(moves.clone()), moves);
+    }
+
+    #[test]
+    fn reachable_empty_loop_is_kept() {
+        let nodes = vec![
+            Node::Add(1),
+            Node::Loop(vec![Node::Move(1), Node::Move(-1)]),
+        ];
+        assert_eq!(peephole(nodes), vec![Node::Add(1), Node::Loop(vec![])]);
+    }
+
+    #[test]
+    fn constant_output_is_folded() {
+        let nodes = vec![Node::Add(1), Node::Add(1), Node::Add(1), Node::Output];
+        assert_eq!(optimize(nodes, 2), vec![Node::Set(3), Node::Output]);
+        let input = vec![Node::Add(3), Node::Input, Node::Add(1)];
+        assert_eq!(
+            fold_constants(input),
+            vec![Node::Set(3), Node::Input, Node::Add(1)]
+        );
+    }
+
+    #[test]
+    fn adds_after_a_set_are_folded() {
+        assert_eq!(
+            fold_sets(vec![Node::Set(0), Node::Add(3), Node::Add(-1)]),
+            vec![Node::Set(2)]
+        );
+        let blocked = vec![Node::Set(0), Node::Output, Node::Add(3)];
+        assert_eq!(fold_sets(blocked.clone()), blocked);
+    }
+
+    #[test]
+    fn move_loops_are_scans() {
+        assert_eq!(
+            scan_loops(vec![Node::Loop(vec![Node::Move(1)])]),
+            vec![Node::Scan(1)]
+        );
+        assert_eq!(
+            scan_loops(vec![Node::Loop(vec![Node::Loop(vec![Node::Move(-2)])])]),
+            vec![Node::Loop(vec![Node::Scan(-2)])]
+        );
+    }
+
+    #[test]
+    fn filled_tape_keeps_the_start() {
+        let nodes = vec![Node::Loop(vec![Node::Output]), Node::Output];
+        let opts = CompileOptions {
+            opt_level: 2,
+            init_cell: 42,
+            ..CompileOptions::default()
+        };
+        assert_eq!(optimize_with(nodes.clone(), &opts), nodes);
+        assert_eq!(optimize(nodes, 2), vec![Node::Output]);
+    }
+
+    #[test]
+    fn optimizer_runs_only_its_passes() {
+        let mut optimizer = Optimizer::new();
+        optimizer.push(fold_adds);
+        let nodes = vec![
+            Node::Add(1),
+            Node::Add(1),
+            Node::Add(1),
+            Node::Loop(vec![Node::Add(-1)]),
+        ];
+        assert_eq!(
+            optimizer.run(nodes),
+            vec![Node::Add(3), Node::Loop(vec![Node::Add(-1)])]
+        );
+    }
+
+    #[test]
+    fn passes_run_to_a_fixed_point() {
+        // removing the dead loop leaves adds to fold
+        let nodes = vec![
+            Node::Input,
+            Node::Set(0),
+            Node::Loop(vec![Node::Output]),
+            Node::Add(1),
+        ];
+        assert_eq!(optimize(nodes, 2), vec![Node::Input, Node::Set(1)]);
+    }
+
+    #[test]
+    fn adjacent_clears_are_merged() {
+        let clears = vec![
+            Node::Set(0),
+            Node::Move(1),
+            Node::Set(0),
+            Node::Move(1),
+            Node::Set(0),
+            Node::Output,
+        ];
+        assert_eq!(
+            zero_runs(clears),
+            vec![Node::Zero(3), Node::Move(2), Node::Output]
+        );
+        let short = vec![Node::Set(0), Node::Move(1), Node::Set(0)];
+        assert_eq!(zero_runs(short.clone()), short);
+    }
+
+    #[test]
+    fn constant_loops_are_unrolled() {
+        let body = vec![Node::Add(-1), Node::Move(1), Node::Add(1), Node::Move(-1)];
+        let nodes = vec![Node::Add(3), Node::Loop(body.clone())];
+        let mut unrolled = vec![Node::Add(3)];
+        for _ in 0..3 {
+            unrolled.extend(body.iter().cloned());
+        }
+        assert_eq!(unroll_loops(nodes.clone(), 4, true), unrolled);
+        assert_eq!(unroll_loops(nodes.clone(), 2, true), nodes);
+        // the first cell is not known
+        assert_eq!(unroll_loops(nodes.clone(), 4, false), nodes);
+    }
+
+    #[test]
+    fn overflowing_moves_and_factors_are_not_folded() {
+        let moves = vec![Node::Move(i32::MAX), Node::Move(1)];
+        assert_eq!(fold_moves(moves.clone()), moves);
+        let far = vec![Node::Loop(vec![
+            Node::Add(-1),
+            Node::Move(i32::MAX),
+            Node::Move(i32::MAX),
+            Node::Add(1),
+            Node::Move(2),
+        ])];
+        assert_eq!(mul_loops(far.clone()), far);
+        let large = vec![Node::Loop(vec![
+            Node::Add(-1),
+            Node::Move(1),
+            Node::Add(i32::MAX),
+            Node::Add(1),
+            Node::Move(-1),
+        ])];
+        assert_eq!(mul_loops(large.clone()), large);
+    }
+}
@@ -0,0 +1,362 @@
+use std::fmt;
+
+use crate::code::Code;
+use crate::dialect::Dialect;
+use crate::span::{Span, Spanned};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ParseErrorKind {
+    /// A `]` code without a matching `[`
+    UnexpectedClose,
+    /// A `[` code that is still open at the end of the program
+    UnclosedOpen,
+    /// A byte that is neither a command nor whitespace, rejected by `check_strict`
+    UnknownByte(u8),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Span,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ParseErrorKind::UnexpectedClose => write!(
+                f,
+                "Found a `]` code without a matching `[` at {}",
+                self.span
+            ),
+            ParseErrorKind::UnclosedOpen => write!(
+                f,
+                "Reached end of file with the `[` code at {} unclosed",
+                self.span
+            ),
+            ParseErrorKind::UnknownByte(byte) if byte.is_ascii() => write!(
+                f,
+                "Found the non-command character {:?} at {}",
+                char::from(byte),
+                self.span
+            ),
+            ParseErrorKind::UnknownByte(byte) => write!(
+                f,
+                "Found the non-ASCII byte 0x{:02x} at {}",
+                byte, self.span
+            ),
+        }
+    }
+}
+
+/// Parses a whole program, reporting every unmatched bracket instead of stopping at the first.
+pub fn parse_program<S>(src: &S) -> Result<Vec<Spanned<Code>>, Vec<ParseError>>
+where
+    S: AsRef<[u8]> + ?Sized,
+{
+    parse_program_with(src, &Dialect::brainfuck())
+}
+
+/// Parses a whole program written in the dialect like `parse_program`.
+pub fn parse_program_with<S>(
+    src: &S,
+    dialect: &Dialect,
+) -> Result<Vec<Spanned<Code>>, Vec<ParseError>>
+where
+    S: AsRef<[u8]> + ?Sized,
+{
+    let codes = tokens_with(src.as_ref().iter().cloned(), dialect).collect::<Vec<_>>();
+    validate(codes.iter().cloned())?;
+    Ok(codes)
+}
+
+/// Parses the program and returns its codes, or the first error that `parse_program` would report.
+///
+/// This never panics on any input, so that it can be used as a fuzzing target.
+pub fn parse_and_validate(bytes: &[u8]) -> Result<Vec<Code>, ParseError> {
+    let mut codes = vec![];
+    let mut opens = vec![];
+    for code in tokens(bytes.iter().cloned()) {
+        match code.inner {
+            Code::LoopStart => opens.push(code.span),
+            Code::LoopEnd if opens.pop().is_none() => {
+                return Err(ParseError {
+                    kind: ParseErrorKind::UnexpectedClose,
+                    span: code.span,
+                })
+            }
+            _ => {}
+        }
+        codes.push(code.inner);
+    }
+    match opens.first() {
+        Some(&span) => Err(ParseError {
+            kind: ParseErrorKind::UnclosedOpen,
+            span,
+        }),
+        None => Ok(codes),
+    }
+}
+
+/// Checks that all brackets in the program are matched without collecting its codes,
+/// so that they can be streamed from the source with `tokens` afterwards.
+pub fn check_program<S>(src: &S) -> Result<(), Vec<ParseError>>
+where
+    S: AsRef<[u8]> + ?Sized,
+{
+    check_program_with(src, &Dialect::brainfuck())
+}
+
+/// Checks a program written in the dialect like `check_program`.
+pub fn check_program_with<S>(src: &S, dialect: &Dialect) -> Result<(), Vec<ParseError>>
+where
+    S: AsRef<[u8]> + ?Sized,
+{
+    validate(tokens_with(src.as_ref().iter().cloned(), dialect))
+}
+
+/// Rejects the first byte that is neither a command of the dialect nor ASCII whitespace,
+/// since it may be a mistyped command.
+pub fn check_strict<S>(src: &S, dialect: &Dialect) -> Result<(), ParseError>
+where
+    S: AsRef<[u8]> + ?Sized,
+{
+    let mut span = Span::START;
+    for &byte in src.as_ref() {
+        if dialect.code(byte).is_none() && !byte.is_ascii_whitespace() {
+            return Err(ParseError {
+                kind: ParseErrorKind::UnknownByte(byte),
+                span,
+            });
+        }
+        span.advance(byte);
+    }
+    Ok(())
+}
+
+/// Removes the UTF-8 byte order mark that some editors start files with, so that it is neither
+/// counted in the columns of the first line nor rejected by `check_strict`.
+///
+/// Other bytes are left to the parser: whitespace, including the `\r` of `\r\n`, is skipped like
+/// any other non-command byte.
+pub fn strip_bom(src: &[u8]) -> &[u8] {
+    src.strip_prefix(b"\xef\xbb\xbf").unwrap_or(src)
+}
+
+/// Lazily extracts the commands from a byte stream, skipping all other bytes.
+pub fn tokens<I: IntoIterator<Item = u8>>(bytes: I) -> Tokens<I::IntoIter> {
+    tokens_with(bytes, &Dialect::brainfuck())
+}
+
+/// Lazily extracts the commands of the dialect from a byte stream like `tokens`.
+pub fn tokens_with<I: IntoIterator<Item = u8>>(bytes: I, dialect: &Dialect) -> Tokens<I::IntoIter> {
+    Tokens {
+        bytes: bytes.into_iter(),
+        dialect: dialect.clone(),
+        span: Span::START,
+    }
+}
+
+/// The iterator returned by `tokens`
+#[derive(Clone, Debug)]
+pub struct Tokens<I> {
+    bytes: I,
+    dialect: Dialect,
+    /// The position of the next byte
+    span: Span,
+}
+
+impl<I: Iterator<Item = u8>> Iterator for Tokens<I> {
+    type Item = Spanned<Code>;
+
+    fn next(&mut self) -> Option<Spanned<Code>> {
+        for byte in &mut self.bytes {
+            let span = self.span;
+            self.span.advance(byte);
+            if let Some(code) = self.dialect.code(byte) {
+                return Some(Spanned { inner: code, span });
+            }
+        }
+        None
+    }
+}
+
+/// Extracts the commands from any byte iterator, skipping all other bytes, like `tokens` without
+/// the positions.
+#[derive(Clone, Debug)]
+pub struct CodeParser<I> {
+    tokens: Tokens<I>,
+}
+
+impl<I: Iterator<Item = u8>> CodeParser<I> {
+    /// Parses the commands of standard brainfuck.
+    pub fn new<B: IntoIterator<Item = u8, IntoIter = I>>(bytes: B) -> Self {
+        Self::with_dialect(bytes, &Dialect::brainfuck())
+    }
+
+    /// Parses the commands of the dialect.
+    pub fn with_dialect<B: IntoIterator<Item = u8, IntoIter = I>>(
+        bytes: B,
+        dialect: &Dialect,
+    ) -> Self {
+        Self {
+            tokens: tokens_with(bytes, dialect),
+        }
+    }
+
+    /// Yields the rest of the commands with their positions instead.
+    pub fn spanned(self) -> Tokens<I> {
+        self.tokens
+    }
+}
+
+impl<I: Iterator<Item = u8>> Iterator for CodeParser<I> {
+    type Item = Code;
+
+    fn next(&mut self) -> Option<Code> {
+        self.tokens.next().map(|code| code.inner)
+    }
+}
+
+/// Checks that all brackets in the program are matched.
+///
+/// Only the positions of the currently open brackets are kept.
+fn validate<I>(codes: I) -> Result<(), Vec<ParseError>>
+where
+    I: IntoIterator<Item = Spanned<Code>>,
+{
+    let mut errors = vec![];
+    let mut opens = vec![];
+    for code in codes {
+        match code.inner {
+            Code::LoopStart => opens.push(code.span),
+            Code::LoopEnd if opens.pop().is_none() => errors.push(ParseError {
+                kind: ParseErrorKind::UnexpectedClose,
+                span: code.span,
+            }),
+            _ => {}
+        }
+    }
+    errors.extend(opens.into_iter().map(|span| ParseError {
+        kind: ParseErrorKind::UnclosedOpen,
+        span,
+    }));
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(src: &str) -> Vec<(ParseErrorKind, u32)> {
+        parse_program(src)
+            .unwrap_err()
+            .into_iter()
+            .map(|err| (err.kind, err.span.col))
+            .collect()
+    }
+
+    #[test]
+    fn every_unmatched_bracket_is_reported() {
+        use ParseErrorKind::*;
+        assert_eq!(kinds("]["), [(UnexpectedClose, 1), (UnclosedOpen, 2)]);
+        assert_eq!(kinds("[[]"), [(UnclosedOpen, 1)]);
+        assert_eq!(kinds("]"), [(UnexpectedClose, 1)]);
+        assert_eq!(kinds("[["), [(UnclosedOpen, 1), (UnclosedOpen, 2)]);
+        assert_eq!(kinds("][]]"), [(UnexpectedClose, 1), (UnexpectedClose, 4)]);
+        assert_eq!(kinds("][]["), [(UnexpectedClose, 1), (UnclosedOpen, 4)]);
+    }
+
+    #[test]
+    fn valid_program_is_parsed() {
+        let codes = parse_program("a[-]").unwrap();
+        assert_eq!(
+            codes
+                .iter()
+                .map(|code| code.inner.clone())
+                .collect::<Vec<_>>(),
+            [Code::LoopStart, Code::MemDec, Code::LoopEnd]
+        );
+        assert_eq!(codes[0].span, Span { line: 1, col: 2 });
+    }
+
+    #[test]
+    fn single_command_from_str() {
+        assert_eq!("+".parse(), Ok(Code::MemInc));
+        assert_eq!("".parse::<Code>(), Err(()));
+        assert_eq!("++".parse::<Code>(), Err(()));
+    }
+
+    #[test]
+    fn tokens_are_lazy() {
+        let mut tokens = tokens(b"+x\n-".iter().cloned());
+        assert_eq!(
+            tokens.next(),
+            Some(Spanned {
+                inner: Code::MemInc,
+                span: Span { line: 1, col: 1 }
+            })
+        );
+        assert_eq!(
+            tokens.next(),
+            Some(Spanned {
+                inner: Code::MemDec,
+                span: Span { line: 2, col: 1 }
+            })
+        );
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn strict_check_rejects_other_characters() {
+        let dialect = Dialect::brainfuck();
+        assert!(check_program_with("+(.", &dialect).is_ok());
+        assert!(check_strict("+ .\n", &dialect).is_ok());
+        let err = check_strict("+(.", &dialect).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnknownByte(b'('));
+        assert_eq!(err.span, Span { line: 1, col: 2 });
+    }
+
+    #[test]
+    fn random_bytes_do_not_panic() {
+        // xorshift, so that the test is reproducible without a dependency
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        assert_eq!(parse_and_validate(b""), Ok(vec![]));
+        for _ in 0..1000 {
+            let len = (next() % 64) as usize;
+            let bytes = (0..len)
+                .map(|_| b"[]+.\n\xff"[(next() % 6) as usize])
+                .collect::<Vec<_>>();
+            match (parse_and_validate(&bytes), parse_program(&bytes)) {
+                (Ok(codes), Ok(expected)) => assert_eq!(
+                    codes,
+                    expected
+                        .into_iter()
+                        .map(|code| code.inner)
+                        .collect::<Vec<_>>()
+                ),
+                (Err(err), Err(errors)) => assert_eq!(err, errors[0]),
+                (result, expected) => panic!("{:?} != {:?}", result, expected),
+            }
+        }
+    }
+
+    #[test]
+    fn errors_are_reported_at_their_line_and_column() {
+        for src in ["+\n[-]\n.]\n", "+\r\n[-]\r\n.]\r\n"] {
+            let errors = parse_program(src).unwrap_err();
+            assert_eq!(errors[0].span, Span { line: 3, col: 2 });
+            assert_eq!(errors[0].span.to_string(), "line 3, column 2");
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Span {
+    /// 1-based line number
+    pub line: u32,
+    /// 1-based column number, counted in bytes
+    pub col: u32,
+}
+
+impl Span {
+    /// The position of the first byte
+    pub const START: Span = Span { line: 1, col: 1 };
+
+    /// The position of the code at the index of a program without a source, as if it was written
+    /// on a single line like `Program` displays it
+    pub fn of_index(index: usize) -> Span {
+        let col = u32::try_from(index).map_or(u32::MAX, |index| index.saturating_add(1));
+        Span { line: 1, col }
+    }
+
+    /// Moves past the byte, saturating on inputs with more than `u32::MAX` lines or columns.
+    ///
+    /// Only `\n` ends a line, so `\r\n` counts as a single line ending: the `\r` is the last
+    /// column of its line.
+    pub fn advance(&mut self, byte: u8) {
+        if byte == b'\n' {
+            self.line = self.line.saturating_add(1);
+            self.col = 1;
+        } else {
+            self.col = self.col.saturating_add(1);
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.col)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Spanned<T> {
+    pub inner: T,
+    pub span: Span,
+}
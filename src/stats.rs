@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::Read;
+
+use crate::ast::Node;
+use crate::backend::Target;
+use crate::interp::Tape;
+use crate::span::{Span, Spanned};
+use crate::{Code, CompileOptions, Error};
+
+/// Static statistics of a program
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The total number of codes
+    pub total: usize,
+    /// The number of occurrences of each code
+    pub counts: HashMap<Code, usize>,
+    /// The number of loops
+    pub loops: usize,
+    /// The maximum loop nesting depth, 0 if there are no loops
+    pub max_depth: usize,
+}
+
+/// Counts the codes and loops of the program.
+///
+/// Unmatched brackets are counted as they are, without affecting the depth below 0.
+pub fn program_stats(codes: &[Code]) -> Stats {
+    let mut stats = Stats {
+        total: codes.len(),
+        ..Stats::default()
+    };
+    let mut depth = 0usize;
+    for code in codes {
+        *stats.counts.entry(code.clone()).or_insert(0) += 1;
+        match code {
+            Code::LoopStart => {
+                stats.loops += 1;
+                depth += 1;
+                stats.max_depth = stats.max_depth.max(depth);
+            }
+            Code::LoopEnd => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    stats
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Instructions: {}", self.total)?;
+        for code in &Code::ALL {
+            writeln!(
+                f,
+                "  {}  {}",
+                code,
+                self.counts.get(code).cloned().unwrap_or(0)
+            )?;
+        }
+        writeln!(f, "Loops: {}", self.loops)?;
+        write!(f, "Maximum loop depth: {}", self.max_depth)
+    }
+}
+
+/// Finds the loops that never change their own cell, which loop forever once entered.
+///
+/// This is conservative: only loops whose body returns to the same cell and contains no input,
+/// no nested loop and no change of that cell are reported, by the position of their `[`.
+/// The tokens are read once, so the program need not be collected.
+pub fn infinite_loops<I: IntoIterator<Item = Spanned<Code>>>(tokens: I) -> Vec<Span> {
+    let mut loops = Vec::new();
+    // the innermost open loop, while it can still loop forever: its `[`, the offset from its
+    // cell and whether that cell was changed
+    let mut open: Option<(Span, i64, bool)> = None;
+    for token in tokens {
+        match (token.inner, &mut open) {
+            (Code::LoopStart, _) => open = Some((token.span, 0, false)),
+            (_, None) => {}
+            (Code::MemInc | Code::MemDec, Some((_, offset, changed))) => *changed |= *offset == 0,
+            (Code::PtrInc, Some((_, offset, _))) => *offset += 1,
+            (Code::PtrDec, Some((_, offset, _))) => *offset -= 1,
+            (Code::SysWrite | Code::Debug, _) => {}
+            (Code::SysRead, _) => open = None,
+            (Code::LoopEnd, &mut Some((start, offset, changed))) => {
+                if offset == 0 && !changed {
+                    loops.push(start);
+                }
+                // the loops around it contain a nested loop, so they are not reported
+                open = None;
+            }
+        }
+    }
+    loops
+}
+
+/// The dynamic cost of running a program, estimated by `dynamic_cost`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Cost {
+    /// The number of nodes executed, counting each iteration of a loop and each move of a scan
+    pub nodes: u64,
+    /// The approximate number of cycles the compiled program takes
+    pub cycles: u64,
+}
+
+impl fmt::Display for Cost {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Operations executed: {}", self.nodes)?;
+        write!(f, "Estimated cycles: {}", self.cycles)
+    }
+}
+
+/// The cycles of a byte transferred by a syscall
+const SYSCALL_CYCLES: u64 = 500;
+/// The cycles of a byte transferred through the buffered stdio of the C and Rust runtimes
+const BUFFERED_IO_CYCLES: u64 = 20;
+
+/// Runs the optimized nodes on the input, discarding the output, and estimates the cost of
+/// running the program compiled for `opts.target`.
+///
+/// The model charges a cycle for each simple instruction of the generated code and a fixed
+/// cost for each byte transferred, which depends on whether the target makes a syscall for it.
+/// Cells always wrap around, whatever `opts.cell_wrap` says. Executing more than `max_steps`
+/// nodes, if given, is an error.
+pub fn dynamic_cost<R: Read>(
+    nodes: &[Node],
+    opts: &CompileOptions,
+    max_steps: Option<u64>,
+    input: &mut R,
+) -> Result<Cost, Error> {
+    opts.check()?;
+    let tape_size = usize::try_from(opts.tape_size).map_err(|_| opts.tape_too_large())?;
+    let io_cycles = match opts.target {
+        Target::C | Target::Rust => BUFFERED_IO_CYCLES,
+        _ => SYSCALL_CYCLES,
+    };
+    let mut runner = CostRunner {
+        opts,
+        max_steps,
+        io_cycles,
+        // a move is checked against the end of the tape or wrapped around it
+        move_cycles: if opts.tape_wrap { 4 } else { 3 },
+        tape: Tape::new(opts.cell_bits, tape_size, u64::from(opts.init_cell)),
+        ptr: opts.start_cell() as usize,
+        cost: Cost::default(),
+    };
+    runner.run(nodes, input)?;
+    Ok(runner.cost)
+}
+
+struct CostRunner<'a> {
+    opts: &'a CompileOptions,
+    max_steps: Option<u64>,
+    io_cycles: u64,
+    move_cycles: u64,
+    tape: Tape,
+    ptr: usize,
+    cost: Cost,
+}
+
+impl CostRunner<'_> {
+    fn run<R: Read>(&mut self, nodes: &[Node], input: &mut R) -> Result<(), Error> {
+        for node in nodes {
+            match *node {
+                Node::Add(delta) => {
+                    self.count(1)?;
+                    let value = self.tape.get(self.ptr).wrapping_add(delta as u64);
+                    self.tape.set(self.ptr, value);
+                }
+                Node::Move(delta) => {
+                    self.count(self.move_cycles)?;
+                    self.ptr = self.offset(delta)?;
+                }
+                Node::Set(value) => {
+                    self.count(1)?;
+                    self.tape.set(self.ptr, value as u64);
+                }
+                Node::Zero(len) => {
+                    // the bound of the last cell and the block store
+                    self.count(self.move_cycles + 4 + len as u64 / 8)?;
+                    self.offset(len - 1)?;
+                    for offset in 0..len {
+                        let cell = self.offset(offset)?;
+                        self.tape.set(cell, 0);
+                    }
+                }
+                Node::MulAdd { offset, factor } => {
+                    self.count(3 + self.move_cycles)?;
+                    let target = self.offset(offset)?;
+                    let product = self.tape.get(self.ptr).wrapping_mul(factor as u64);
+                    let value = self.tape.get(target).wrapping_add(product);
+                    self.tape.set(target, value);
+                }
+                Node::Output => self.count(self.io_cycles)?,
+                Node::Input => {
+                    self.count(self.io_cycles)?;
+                    let mut byte = [0u8];
+                    if input.read(&mut byte).map_err(Error::Io)? == 1 {
+                        self.tape.set(self.ptr, u64::from(byte[0]));
+                    } else if let Some(value) = self.opts.eof.value() {
+                        self.tape.set(self.ptr, value as u64);
+                    }
+                }
+                Node::Scan(step) => {
+                    self.count(2)?;
+                    while self.tape.get(self.ptr) != 0 {
+                        self.count(self.move_cycles + 2)?;
+                        self.ptr = self.offset(step)?;
+                    }
+                }
+                Node::Loop(ref body) => {
+                    // the test before the loop and the one at the end of each iteration
+                    self.count(2)?;
+                    while self.tape.get(self.ptr) != 0 {
+                        self.run(body, input)?;
+                        self.count(2)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts a node that takes the cycles
+    fn count(&mut self, cycles: u64) -> Result<(), Error> {
+        if self.max_steps.is_some_and(|max| self.cost.nodes >= max) {
+            return Err(Error::StepLimit(self.cost.nodes));
+        }
+        self.cost.nodes += 1;
+        self.cost.cycles += cycles;
+        Ok(())
+    }
+
+    /// The index of the cell at `delta` from the current cell
+    fn offset(&self, delta: i32) -> Result<usize, Error> {
+        let index = self.ptr as i64 + i64::from(delta);
+        let size = self.tape.len() as i64;
+        if self.opts.tape_wrap {
+            Ok(index.rem_euclid(size) as usize)
+        } else if index < 0 {
+            Err(Error::TapeUnderflow)
+        } else if index >= size {
+            Err(Error::TapeOverflow)
+        } else {
+            Ok(index as usize)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_codes_and_loops() {
+        let codes = [
+            Code::MemInc,
+            Code::MemInc,
+            Code::LoopStart,
+            Code::PtrInc,
+            Code::LoopEnd,
+        ];
+        let stats = program_stats(&codes);
+        assert_eq!(stats.total, 5);
+        assert_eq!(stats.counts[&Code::MemInc], 2);
+        assert_eq!(stats.counts.get(&Code::MemDec), None);
+        assert_eq!(stats.loops, 1);
+        assert_eq!(stats.max_depth, 1);
+    }
+
+    #[test]
+    fn loops_that_never_change_their_cell_are_found() {
+        let found = |src: &str| infinite_loops(crate::parse::tokens(src.bytes()));
+        assert_eq!(found("+[]"), [Span::of_index(1)]);
+        assert_eq!(found("[>.<]"), [Span::of_index(0)]);
+        assert!(found("[-]").is_empty());
+        assert!(found("[>]").is_empty());
+        assert!(found("[,]").is_empty());
+        assert!(found("[[-]]").is_empty());
+    }
+
+    #[test]
+    fn dynamic_cost_counts_each_iteration() {
+        let nodes = [Node::Add(2), Node::Loop(vec![Node::Add(-1), Node::Output])];
+        let opts = CompileOptions {
+            tape_size: 16,
+            ..CompileOptions::default()
+        };
+        let cost = dynamic_cost(&nodes, &opts, None, &mut std::io::empty()).unwrap();
+        // the add, the test before the loop, and an add, an output and a test per iteration
+        assert_eq!(cost.nodes, 2 + 2 * 3);
+        assert_eq!(cost.cycles, 1 + 2 + 2 * (1 + SYSCALL_CYCLES + 2));
+        assert!(dynamic_cost(&nodes, &opts, Some(4), &mut std::io::empty()).is_err());
+    }
+}
@@ -0,0 +1,40 @@
+use std::io::{self, Write};
+
+use bfc::ast::Node;
+use bfc::backend::Backend;
+use bfc::{compile_with, Code, CompileOptions};
+
+/// Records the calls it gets instead of writing anything
+#[derive(Default)]
+struct Recorder {
+    calls: Vec<String>,
+}
+
+impl Backend for Recorder {
+    fn prologue(&mut self, _: &mut dyn Write, _: &CompileOptions) -> io::Result<()> {
+        self.calls.push("prologue".to_string());
+        Ok(())
+    }
+
+    fn emit(&mut self, node: &Node, _: &mut dyn Write, _: &CompileOptions) -> io::Result<()> {
+        self.calls.push(format!("{:?}", node));
+        Ok(())
+    }
+
+    fn epilogue(&mut self, _: &mut dyn Write, _: &CompileOptions) -> io::Result<()> {
+        self.calls.push("epilogue".to_string());
+        Ok(())
+    }
+}
+
+#[test]
+fn custom_backend_gets_each_node() {
+    let mut recorder = Recorder::default();
+    let codes = [Code::MemInc, Code::PtrInc, Code::SysWrite];
+    let opts = CompileOptions::default();
+    compile_with(codes.iter().cloned(), &mut recorder, &mut io::sink(), &opts).unwrap();
+    assert_eq!(
+        recorder.calls,
+        ["prologue", "Add(1)", "Move(1)", "Output", "epilogue"]
+    );
+}
@@ -0,0 +1,334 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Compiles the source with the bfc binary and returns the output file
+fn compile(name: &str, src: &str, args: &[&str]) -> String {
+    let dir = env::temp_dir();
+    let input = dir.join(format!("bfc-cli-{}-{}.bf", std::process::id(), name));
+    let output = PathBuf::from(format!("{}.out", input.display()));
+    fs::write(&input, src).unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_bfc"))
+        .arg(&input)
+        .arg("-o")
+        .arg(&output)
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(status.status.success(), "{:?}", status);
+    let out = fs::read_to_string(&output).unwrap();
+    let _ = fs::remove_file(&input);
+    let _ = fs::remove_file(&output);
+    out
+}
+
+#[test]
+fn opt_level_changes_the_output() {
+    let verbatim = compile("o0", ",++[-]", &["-O0"]);
+    let optimized = compile("o2", ",++[-]", &["-O2"]);
+    assert_ne!(verbatim, optimized);
+    assert!(optimized.contains("mov byte [rax], 0"));
+}
+
+#[test]
+fn target_c_writes_c() {
+    let c = compile("target-c", "+.", &["--target", "c"]);
+    assert!(c.contains("putchar"));
+}
+
+#[test]
+fn dash_reads_the_program_from_stdin() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bfc"))
+        .args(["-", "--run"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"++++++++[>++++++++<-]>+.")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(output.stdout, b"A");
+}
+
+#[test]
+fn dump_ir_prints_the_optimized_nodes() {
+    let input = env::temp_dir().join(format!("bfc-cli-{}-dump-ir.bf", std::process::id()));
+    fs::write(&input, "+++[->+<]").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_bfc"))
+        .arg(&input)
+        .args(["--dump-ir", "-O1"])
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(&input);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Add(3)\nLoop\n  Add(-1)\n  Move(1)\n  Add(1)\n  Move(-1)\n"
+    );
+}
+
+#[test]
+fn target_bf_minifies() {
+    let bf = compile("target-bf", "++  + comment\n.", &["--target", "bf", "-O1"]);
+    assert_eq!(bf, "+++.\n");
+}
+
+#[test]
+fn dash_out_writes_to_stdout() {
+    let input = env::temp_dir().join(format!("bfc-cli-{}-stdout.bf", std::process::id()));
+    fs::write(&input, "+.").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_bfc"))
+        .arg(&input)
+        .args(["-o", "-"])
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(&input);
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("section .bss"));
+    assert!(stdout.contains("inc byte [rax]"));
+    assert!(!stdout.contains("Done!"));
+}
+
+#[test]
+fn comment_only_program_warns() {
+    let input = env::temp_dir().join(format!("bfc-cli-{}-comment.bf", std::process::id()));
+    fs::write(&input, "only a comment\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_bfc"))
+        .arg(&input)
+        .args(["-o", "-"])
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(&input);
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("contains no commands"), "{}", stderr);
+}
+
+#[test]
+fn debug_only_program_warns_and_fails_with_strict() {
+    let input = env::temp_dir().join(format!("bfc-cli-{}-debug.bf", std::process::id()));
+    fs::write(&input, "#\n").unwrap();
+    let lenient = Command::new(env!("CARGO_BIN_EXE_bfc"))
+        .arg(&input)
+        .args(["-o", "-"])
+        .output()
+        .unwrap();
+    let strict = Command::new(env!("CARGO_BIN_EXE_bfc"))
+        .arg(&input)
+        .args(["-o", "-", "--strict"])
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(&input);
+    assert!(lenient.status.success(), "{:?}", lenient);
+    let stderr = String::from_utf8(lenient.stderr).unwrap();
+    assert!(stderr.contains("contains no commands"), "{}", stderr);
+    assert!(!strict.status.success(), "{:?}", strict);
+}
+
+#[test]
+fn safe_reports_moving_off_the_tape() {
+    let input = env::temp_dir().join(format!("bfc-cli-{}-safe.bf", std::process::id()));
+    fs::write(&input, "<+.").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_bfc"))
+        .arg(&input)
+        .args(["--safe", "--run", "--tape-start", "zero"])
+        .output()
+        .unwrap();
+    let conflict = Command::new(env!("CARGO_BIN_EXE_bfc"))
+        .arg(&input)
+        .args(["--safe", "--tape-wrap", "--run"])
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(&input);
+    assert!(!output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("past the start of the tape"), "{}", stderr);
+    assert!(!conflict.status.success(), "{:?}", conflict);
+}
+
+#[test]
+fn run_reads_the_input_file() {
+    let dir = env::temp_dir();
+    let input = dir.join(format!("bfc-cli-{}-echo.bf", std::process::id()));
+    let data = dir.join(format!("bfc-cli-{}-echo.txt", std::process::id()));
+    fs::write(&input, ",.").unwrap();
+    fs::write(&data, "Z").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_bfc"))
+        .arg(&input)
+        .arg("--run")
+        .arg("--input")
+        .arg(&data)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(&input);
+    let _ = fs::remove_file(&data);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(output.stdout, b"Z");
+}
+
+#[test]
+fn list_targets_prints_the_targets() {
+    let output = Command::new(env!("CARGO_BIN_EXE_bfc"))
+        .arg("--list-targets")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert!(lines.contains(&"x86_64-linux (.asm)"), "{}", stdout);
+    assert!(lines.contains(&"c (.c)"), "{}", stdout);
+}
+
+#[test]
+fn verbose_logs_the_phases() {
+    let input = env::temp_dir().join(format!("bfc-cli-{}-verbose.bf", std::process::id()));
+    fs::write(&input, "+++ comment [-]").unwrap();
+    let quiet = Command::new(env!("CARGO_BIN_EXE_bfc"))
+        .arg(&input)
+        .args(["-o", "-"])
+        .output()
+        .unwrap();
+    let verbose = Command::new(env!("CARGO_BIN_EXE_bfc"))
+        .arg(&input)
+        .args(["-o", "-", "-v"])
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(&input);
+    assert!(verbose.status.success(), "{:?}", verbose);
+    assert!(!String::from_utf8(quiet.stderr).unwrap().contains("Parsed"));
+    let stderr = String::from_utf8(verbose.stderr).unwrap();
+    assert!(
+        stderr.starts_with("Parsed 6 instructions in "),
+        "{}",
+        stderr
+    );
+}
+
+/// Runs the bfc binary with the arguments on the input and returns its standard output
+fn run_with_stdin(args: &[&str], input: &[u8]) -> Vec<u8> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bfc"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    output.stdout
+}
+
+#[test]
+fn fmt_strips_comments() {
+    let hello = b"print A: ++++++++ [ > ++++++++ < - ] > + .\n";
+    let formatted = run_with_stdin(&["fmt", "-"], hello);
+    assert_eq!(formatted, b"++++++++[>++++++++<-]>+.\n");
+    assert_eq!(run_with_stdin(&["-", "--run"], &formatted), b"A");
+    assert_eq!(run_with_stdin(&["-", "--run"], hello), b"A");
+}
+
+#[test]
+fn dump_tokens_prints_the_positions() {
+    let input = env::temp_dir().join(format!("bfc-cli-{}-tokens.bf", std::process::id()));
+    fs::write(&input, "+ >").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_bfc"))
+        .arg(&input)
+        .arg("--dump-tokens")
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(&input);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "MemInc at line 1, column 1\nPtrInc at line 1, column 3\n"
+    );
+}
+
+#[test]
+fn quiet_prints_nothing() {
+    let dir = env::temp_dir();
+    let input = dir.join(format!("bfc-cli-{}-quiet.bf", std::process::id()));
+    let out = dir.join(format!("bfc-cli-{}-quiet.asm", std::process::id()));
+    fs::write(&input, "+.").unwrap();
+    let quiet = Command::new(env!("CARGO_BIN_EXE_bfc"))
+        .arg(&input)
+        .arg("-o")
+        .arg(&out)
+        .arg("-q")
+        .output()
+        .unwrap();
+    let loud = Command::new(env!("CARGO_BIN_EXE_bfc"))
+        .arg(&input)
+        .arg("-o")
+        .arg(&out)
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(&input);
+    let _ = fs::remove_file(&out);
+    assert!(quiet.status.success(), "{:?}", quiet);
+    assert!(
+        quiet.stdout.is_empty() && quiet.stderr.is_empty(),
+        "{:?}",
+        quiet
+    );
+    assert!(loud.stdout.is_empty(), "{:?}", loud);
+    assert!(String::from_utf8(loud.stderr).unwrap().contains("Done!"));
+}
+
+#[test]
+fn bang_input_is_read_by_the_program() {
+    assert_eq!(
+        run_with_stdin(&["-", "--run", "--bang-input"], b",.!Z"),
+        b"Z"
+    );
+}
+
+#[test]
+fn jit_rejects_max_steps() {
+    let output = Command::new(env!("CARGO_BIN_EXE_bfc"))
+        .args(["-", "--jit", "--max-steps", "10"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(!output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("cannot be used with"), "{}", stderr);
+}
+
+#[cfg(unix)]
+#[test]
+fn c_target_multiplies_by_zero_at_the_start_of_the_tape() {
+    let c = compile(
+        "mul-c",
+        ",[-<+>]+.",
+        &["--target", "c", "-O2", "--tape-start", "zero"],
+    );
+    let source = env::temp_dir().join(format!("bfc-cli-{}-mul.c", std::process::id()));
+    let binary = PathBuf::from(format!("{}.out", source.display()));
+    fs::write(&source, c).unwrap();
+    // the test needs a C compiler to run the program
+    let cc = match Command::new("cc")
+        .arg(&source)
+        .arg("-o")
+        .arg(&binary)
+        .output()
+    {
+        Ok(cc) => cc,
+        Err(_) => return,
+    };
+    assert!(cc.status.success(), "{:?}", cc);
+    let output = Command::new(&binary).stdin(Stdio::null()).output().unwrap();
+    let _ = fs::remove_file(&source);
+    let _ = fs::remove_file(&binary);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(output.stdout, [1]);
+}
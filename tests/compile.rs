@@ -0,0 +1,65 @@
+use bfc::{compile_to_writer, Code, CompileOptions, Target};
+
+#[test]
+fn compiles_through_the_public_api() {
+    let mut out = vec![];
+    compile_to_writer(
+        vec![Code::MemInc, Code::SysWrite],
+        &mut out,
+        &CompileOptions::default(),
+    )
+    .unwrap();
+    let asm = String::from_utf8(out).unwrap();
+    assert!(asm.contains("tape RESB 1048576"));
+    assert!(asm.contains("inc byte [rax]"));
+    assert!(asm.contains("syscall"));
+}
+
+#[test]
+fn compiles_to_a_string() {
+    let asm = bfc::compile_to_string(vec![Code::MemInc], &CompileOptions::default()).unwrap();
+    assert!(asm.contains("inc byte [rax]"));
+}
+
+#[test]
+fn compiles_a_streamed_program() {
+    let codes = std::iter::repeat_n(Code::MemInc, 1_000_000);
+    let opts = CompileOptions {
+        opt_level: 1,
+        ..CompileOptions::default()
+    };
+    let asm = bfc::compile_to_string(codes, &opts).unwrap();
+    // 1000000 is 64 modulo 256
+    assert!(asm.contains("add byte [rax], 64"));
+}
+
+#[test]
+fn target_selects_the_backend() {
+    let opts = CompileOptions {
+        target: "aarch64-linux".parse().unwrap(),
+        ..CompileOptions::default()
+    };
+    assert_eq!(opts.target, Target::Aarch64Linux);
+    let asm = bfc::compile_to_string(vec![Code::SysWrite], &opts).unwrap();
+    assert!(asm.contains("svc #0"));
+}
+
+#[test]
+fn unmatched_close_is_a_variant() {
+    let codes = vec![Code::MemInc, Code::LoopEnd];
+    let err = bfc::compile_to_string(codes, &CompileOptions::default()).unwrap_err();
+    assert!(
+        matches!(
+            err,
+            bfc::CompileError::UnmatchedClose {
+                span: bfc::span::Span { line: 1, col: 2 }
+            }
+        ),
+        "{}",
+        err
+    );
+    assert_eq!(
+        err.to_string(),
+        "Found a `]` code without a matching `[` at line 1, column 2"
+    );
+}
@@ -0,0 +1,106 @@
+use bfc::interp::run_program;
+
+const HELLO: &str = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.\
+                     >>.<-.<.+++.------.--------.>>+.>++.";
+
+/// The programs with their input and expected output
+const CORPUS: [(&str, &[u8], &[u8]); 6] = [
+    (HELLO, b"", b"Hello World!\n"),
+    ("++++++++[>++++++++<-]>+.+.", b"", b"AB"),
+    (",.,.,.,.", b"echo", b"echo"),
+    ("+++[>+++[>++++++<-]<-]>>+++++++.", b"", b"="),
+    (",[->+<]+++.", b"\x05", b"\x03"),
+    (",[->+<]>[-]>[-]>[-].", b"\x05", b"\x00"),
+];
+
+#[test]
+fn interpreter_runs_the_corpus() {
+    for &(src, input, expected) in &CORPUS {
+        assert_eq!(run_program(src, input).unwrap(), expected, "{}", src);
+    }
+}
+
+#[test]
+fn bf_target_keeps_the_behavior() {
+    use bfc::backend::Target;
+    use bfc::parse::CodeParser;
+    use bfc::CompileOptions;
+
+    for &(src, input, expected) in &CORPUS {
+        let opts = CompileOptions {
+            opt_level: 2,
+            target: Target::Brainfuck,
+            ..CompileOptions::default()
+        };
+        let bf = bfc::compile_to_string(CodeParser::new(src.bytes()), &opts).unwrap();
+        assert_eq!(
+            run_program(&bf, input).unwrap(),
+            expected,
+            "{} -> {}",
+            src,
+            bf
+        );
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", unix))]
+#[test]
+fn jit_matches_the_interpreter() {
+    use bfc::parse::CodeParser;
+    use bfc::CompileOptions;
+
+    for &(src, input, _) in &CORPUS {
+        let codes = CodeParser::new(src.bytes()).collect::<Vec<_>>();
+        for opt_level in 0..=2 {
+            let opts = CompileOptions {
+                opt_level,
+                ..CompileOptions::default()
+            };
+            let mut output = vec![];
+            bfc::jit::run(&codes, &opts, &mut &input[..], &mut output).unwrap();
+            assert_eq!(output, run_program(src, input).unwrap(), "{}", src);
+        }
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", unix))]
+#[test]
+fn jit_multiplies_by_zero_at_the_start_of_the_tape() {
+    use bfc::parse::CodeParser;
+    use bfc::{CompileOptions, TapeStart};
+
+    let codes = CodeParser::new(",[-<+>]+.".bytes()).collect::<Vec<_>>();
+    let opts = CompileOptions {
+        opt_level: 2,
+        tape_start: TapeStart::Zero,
+        ..CompileOptions::default()
+    };
+    let mut output = vec![];
+    bfc::jit::run(&codes, &opts, &mut &b""[..], &mut output).unwrap();
+    assert_eq!(output, [1]);
+}
+
+#[cfg(all(target_arch = "x86_64", unix))]
+#[test]
+fn jit_optimizes_the_same_behavior_on_a_wrapping_tape() {
+    use bfc::parse::CodeParser;
+    use bfc::{CompileOptions, TapeStart};
+
+    // the offsets 0 and 2 are the same cell of the tape
+    for &(src, expected) in &[("+>>.", 1), ("+[->>++<<]>>.", 0), ("++++[->>-<<>+<]>.", 2)] {
+        let codes = CodeParser::new(src.bytes()).collect::<Vec<_>>();
+        for &(opt_level, unroll) in &[(0, 0), (2, 0), (2, 4)] {
+            let opts = CompileOptions {
+                opt_level,
+                unroll,
+                tape_size: 2,
+                tape_wrap: true,
+                tape_start: TapeStart::Zero,
+                ..CompileOptions::default()
+            };
+            let mut output = vec![];
+            bfc::jit::run(&codes, &opts, &mut &b""[..], &mut output).unwrap();
+            assert_eq!(output, [expected], "{} at -O{}", src, opt_level);
+        }
+    }
+}
@@ -0,0 +1,18 @@
+use bfc::parse::CodeParser;
+use bfc::span::Span;
+use bfc::Code;
+
+#[test]
+fn code_parser_skips_other_bytes() {
+    let codes = CodeParser::new("a+b>".bytes()).collect::<Vec<_>>();
+    assert_eq!(codes, [Code::MemInc, Code::PtrInc]);
+}
+
+#[test]
+fn code_parser_can_yield_positions() {
+    let spans = CodeParser::new(b"+\n >".iter().cloned())
+        .spanned()
+        .map(|code| code.span)
+        .collect::<Vec<_>>();
+    assert_eq!(spans, [Span { line: 1, col: 1 }, Span { line: 2, col: 2 }]);
+}
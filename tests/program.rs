@@ -0,0 +1,12 @@
+use bfc::parse::parse_program;
+use bfc::Program;
+
+#[test]
+fn program_displays_its_canonical_source() {
+    let codes = parse_program("+ + [ - ] comment")
+        .unwrap()
+        .into_iter()
+        .map(|code| code.inner)
+        .collect::<Vec<_>>();
+    assert_eq!(Program::from(codes).to_string(), "++[-]");
+}
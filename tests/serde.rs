@@ -0,0 +1,37 @@
+#![cfg(feature = "serde")]
+
+use bfc::ast::{build_ast, Node};
+use bfc::Code;
+
+#[test]
+fn codes_round_trip_through_json() {
+    let codes = "+-><.,[]#"
+        .chars()
+        .map(|c| c.to_string().parse().unwrap())
+        .collect::<Vec<Code>>();
+    let json = serde_json::to_string(&codes).unwrap();
+    assert_eq!(json, r##"["+","-",">","<",".",",","[","]","#"]"##);
+    assert_eq!(serde_json::from_str::<Vec<Code>>(&json).unwrap(), codes);
+    assert!(serde_json::from_str::<Code>(r#""x""#).is_err());
+}
+
+#[test]
+fn nodes_round_trip_through_json() {
+    let nodes = build_ast(vec![
+        Code::MemInc,
+        Code::LoopStart,
+        Code::MemDec,
+        Code::LoopEnd,
+    ])
+    .unwrap();
+    let nodes = [
+        nodes,
+        vec![Node::MulAdd {
+            offset: -1,
+            factor: 2,
+        }],
+    ]
+    .concat();
+    let json = serde_json::to_string(&nodes).unwrap();
+    assert_eq!(serde_json::from_str::<Vec<Node>>(&json).unwrap(), nodes);
+}